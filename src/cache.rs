@@ -0,0 +1,58 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Summaries from earlier runs, stored as one file per request under the cache directory.
+/// Entries are keyed by everything that goes into a request, so a changed file, prompt or
+/// model simply misses the cache instead of returning a stale summary.
+#[derive(Debug, Clone)]
+pub struct SummaryCache {
+    dir: PathBuf,
+}
+
+/// `DIRSCRIBE_CACHE_DIR`, else `$XDG_CACHE_HOME/dirscribe`, else `~/.cache/dirscribe`
+pub fn default_cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("DIRSCRIBE_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    cache_home.join("dirscribe")
+}
+
+impl SummaryCache {
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        Ok(SummaryCache { dir })
+    }
+
+    /// Hashes the parts of a request; they are separated so that moving text between parts changes the key
+    pub fn key(parts: &[&str]) -> String {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        fs::read_to_string(self.dir.join(key)).ok()
+    }
+
+    pub fn put(&self, key: &str, summary: &str) -> Result<()> {
+        // Written under a temporary name first, so an interrupted write never leaves a partial entry
+        let path = self.dir.join(key);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, summary)
+            .with_context(|| format!("Failed to write cache entry {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to write cache entry {}", path.display()))?;
+        Ok(())
+    }
+}
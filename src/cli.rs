@@ -1,23 +1,111 @@
 use clap::Parser;
+use crate::color::ColorChoice;
+use crate::output::OutputFormat;
+use crate::file_processing::{CommentStyle, SortOrder, SummaryPosition};
+use crate::summary::Provider;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
     /// Comma-separated list of file extensions to process (e.g., "txt,md,rs")
-    pub suffixes: String,
+    pub suffixes: Option<String>,
+
+    /// Path to a TOML config file with default options (defaults to .dirscribe.toml if it exists)
+    #[arg(long)]
+    pub config: Option<String>,
+
+    /// Match file extensions regardless of case (e.g. "py" also matches ".PY")
+    #[arg(long, default_value_t = false)]
+    pub case_insensitive_suffixes: bool,
 
     /// Path to prompt template file
     #[arg(long)]
     pub prompt_template_path: Option<String>,
 
+    /// Shell command each file's content is piped through before it is used
+    #[arg(long)]
+    pub preprocess_cmd: Option<String>,
+
+    /// Strip trailing spaces and tabs from every line of the file contents
+    #[arg(long, default_value_t = false)]
+    pub trim_trailing_whitespace: bool,
+
+    /// Minify JSON and YAML files in the output
+    #[arg(long, default_value_t = false)]
+    pub minify_data: bool,
+
+    /// Remove lines matching this regex from the file contents; can be given multiple times
+    #[arg(long)]
+    pub strip_lines: Vec<String>,
+
     /// Path to output path
     #[arg(long)]
     pub output_path: Option<String>,
 
+    /// Write each file's content or summary to its own file below this directory, mirroring the source tree
+    #[arg(long)]
+    pub output_dir: Option<String>,
+
+    /// List the files that would be processed on stdout, without processing or summarizing them
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    /// Write the output to stdout instead of the clipboard
+    #[arg(long, default_value_t = false)]
+    pub stdout: bool,
+
+    /// Maximum size of the output in bytes; files that don't fit are left out
+    #[arg(long)]
+    pub max_output_bytes: Option<usize>,
+
+    /// Approximate maximum number of tokens in the output (4 bytes per token); the largest files are left out first
+    #[arg(long)]
+    pub max_output_tokens: Option<usize>,
+
+    /// Split the output into numbered files of at most this many bytes, or tokens with a 't' suffix (e.g. "25000t")
+    #[arg(long)]
+    pub split_size: Option<String>,
+
+    /// Output format; markdown wraps file contents in code fences tagged with their language, json emits one object per file
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Order of the files in the output
+    #[arg(long, value_enum, default_value_t = SortOrder::Path)]
+    pub sort: SortOrder,
+
+    /// List the file paths as a directory tree
+    #[arg(long, default_value_t = false)]
+    pub tree: bool,
+
+    /// Output only the file contents separated by blank lines, without any headers
+    #[arg(long, default_value_t = false)]
+    pub content_only: bool,
+
+    /// Prefix each line of the file contents with its line number
+    #[arg(long, default_value_t = false)]
+    pub line_numbers: bool,
+
+    /// Mark executable files with a trailing `*` in the file paths section
+    #[arg(long, default_value_t = false)]
+    pub classify: bool,
+
+    /// Annotate each entry in the file paths section with its size and modification time
+    #[arg(long, default_value_t = false)]
+    pub show_metadata: bool,
+
+    /// Comma-separated order of the output sections (available: paths, tree, contents)
+    #[arg(long, default_value = "paths,contents")]
+    pub section_order: String,
+
     /// Include files that are ignored by default based on .gitignore rules
     #[arg(long, default_value_t = false)]
     pub dont_use_gitignore: bool,
 
+    /// Follow symlinks to directories instead of skipping them
+    #[arg(long, default_value_t = false)]
+    pub follow_symlinks: bool,
+
     /// Summarize file contents with keywords
     #[arg(long, default_value_t = false)]
     pub summarize: bool,
@@ -28,14 +116,130 @@ pub struct Cli {
 
 
 
+    /// Name of the built-in prompt template to summarize with (e.g. "summary-0.2"), instead of the default for the mode
+    #[arg(long)]
+    pub prompt_version: Option<String>,
+
+    /// Check that the model is offered by the provider before summarizing (Ollama and OpenAI-compatible providers)
+    #[arg(long, default_value_t = false)]
+    pub check_model: bool,
+
+    /// Describe image files (png, jpg, webp) with providers that support images
+    #[arg(long, default_value_t = false)]
+    pub summarize_binary: bool,
+
+    /// Show the estimated cost of the summaries next to the tokens used
+    #[arg(long, default_value_t = false)]
+    pub show_cost: bool,
+
+    /// Don't read or write the summary cache
+    #[arg(long, default_value_t = false)]
+    pub no_cache: bool,
+
+    /// Summarize every file again and overwrite its cached summary
+    #[arg(long, default_value_t = false)]
+    pub refresh_cache: bool,
+
+    /// Path to a journal of completed summaries, used to resume an interrupted run
+    #[arg(long)]
+    pub resume_file: Option<String>,
+
     /// Apply summaries to code files
     #[arg(long, default_value_t = false)]
     pub apply: bool,
 
+    /// Where --apply writes the summary into each file
+    #[arg(long, value_enum, default_value_t = SummaryPosition::Top)]
+    pub summary_position: SummaryPosition,
+
+    /// Comment style to write summaries in, for languages dirscribe doesn't know the comment syntax of
+    #[arg(long, value_enum)]
+    pub comment_style: Option<CommentStyle>,
+
+    /// Prefix of every comment line with --comment-style line (e.g. --comment-prefix=--)
+    #[arg(long)]
+    pub comment_prefix: Option<String>,
+
+    /// Opening delimiter of the comment with --comment-style block (e.g. "{-")
+    #[arg(long)]
+    pub comment_open: Option<String>,
+
+    /// Closing delimiter of the comment with --comment-style block (e.g. "-}")
+    #[arg(long)]
+    pub comment_close: Option<String>,
+
+    /// Write each summary to a <file>.dirscribe.md file next to it instead of into the file
+    #[arg(long, default_value_t = false)]
+    pub sidecar: bool,
+
     /// Retrieve summaries from code files
     #[arg(long, default_value_t = false)]
     pub retrieve: bool,
 
+    /// Summarize content piped through stdin instead of walking a directory
+    #[arg(long, default_value_t = false)]
+    pub summarize_stdin: bool,
+
+    /// File extension used to format the summary of stdin content (e.g., "rs")
+    #[arg(long)]
+    pub stdin_ext: Option<String>,
+
+    /// Timeout in seconds for summarizing a single file; files that time out are skipped
+    #[arg(long)]
+    pub per_file_timeout: Option<u64>,
+
+    /// Also summarize every directory from the summaries of its files and subdirectories, up to the root
+    #[arg(long, default_value_t = false)]
+    pub hierarchical_summary: bool,
+
+    /// Prepend an architectural overview of the whole directory, written by the provider from the file summaries or contents
+    #[arg(long, default_value_t = false)]
+    pub overview: bool,
+
+    /// Model to write the overview with, instead of DIRSCRIBE_MODEL (e.g. a stronger model than for the per-file summaries)
+    #[arg(long)]
+    pub overview_model: Option<String>,
+
+    /// Write a markdown index linking each file to the first line of its summary to this path
+    #[arg(long)]
+    pub index: Option<String>,
+
+    /// Maximum number of summarization requests in flight at once (overrides DIRSCRIBE_CONCURRENT_REQUESTS)
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+
+    /// Sampling temperature for summarization requests (defaults to the provider's setting)
+    #[arg(long)]
+    pub temperature: Option<f32>,
+
+    /// Maximum number of tokens in each summary (defaults to the provider's setting)
+    #[arg(long)]
+    pub max_tokens: Option<i32>,
+
+    /// Treat summaries that look like a refusal from the provider as failures
+    #[arg(long, default_value_t = false)]
+    pub strict: bool,
+
+    /// Comma-separated list of phrases that mark a response as a refusal (replaces the defaults)
+    #[arg(long)]
+    pub refusal_patterns: Option<String>,
+
+    /// Comma-separated list of providers (optionally "provider:model") to summarize with side by side
+    #[arg(long)]
+    pub compare_providers: Option<String>,
+
+    /// Provider to switch to if the primary provider is unavailable (overrides DIRSCRIBE_PROVIDER_FALLBACK)
+    #[arg(long)]
+    pub fallback_provider: Option<String>,
+
+    /// Provider from the config file, used when DIRSCRIBE_PROVIDER is not set
+    #[arg(skip)]
+    pub config_provider: Option<Provider>,
+
+    /// Skip minified files, recognized by a line longer than 5000 bytes
+    #[arg(long, default_value_t = false)]
+    pub skip_minified: bool,
+
     /// Comma-separated list of paths to exclude
     #[arg(long)]
     pub exclude_paths: Option<String>,
@@ -44,22 +248,86 @@ pub struct Cli {
     #[arg(long)]
     pub include_paths: Option<String>,
 
-    /// Comma-separated list of keywords - only include files containing at least one keyword
+    /// Comma-separated list of file extensions to leave out when processing all files with "*"
+    #[arg(long)]
+    pub exclude_extensions: Option<String>,
+
+    /// Comma-separated list of directory names to exclude at any depth
+    #[arg(long)]
+    pub exclude_dirs: Option<String>,
+
+    /// Process the newline-separated paths in this file ("-" for stdin) instead of walking the directory
+    #[arg(long)]
+    pub files_from: Option<String>,
+
+    /// Path to a file with newline-separated paths or globs to exclude
+    #[arg(long)]
+    pub exclude_paths_file: Option<String>,
+
+    /// Path to a file with newline-separated paths or globs to include
+    #[arg(long)]
+    pub include_paths_file: Option<String>,
+
+    /// Path to a file with gitignore-style patterns - only include files matching them
+    #[arg(long)]
+    pub only_matching_file: Option<String>,
+
+    /// Comma-separated list of keywords - only include files containing at least one keyword; can be given multiple times
     #[arg(long)]
-    pub or_keywords: Option<String>,
+    pub or_keywords: Vec<String>,
 
-    /// Comma-separated list of keywords - only include files containing all keywords
+    /// Comma-separated list of keywords - only include files containing all keywords; can be given multiple times
     #[arg(long)]
-    pub and_keywords: Option<String>,
+    pub and_keywords: Vec<String>,
 
-    /// Comma-separated list of keywords - exclude files containing any of these keywords
+    /// Comma-separated list of keywords - exclude files containing any of these keywords; can be given multiple times
     #[arg(long)]
-    pub exclude_keywords: Option<String>,
+    pub exclude_keywords: Vec<String>,
+
+    /// Interpret the or, and and exclude keywords as regular expressions, one per flag instead of comma-separated
+    #[arg(long, default_value_t = false)]
+    pub regex_keywords: bool,
+
+    /// Match the or, and and exclude keywords regardless of case
+    #[arg(long, default_value_t = false)]
+    pub ignore_case: bool,
+
+    /// Print which keywords matched each file and whether it was included to stderr
+    #[arg(long, default_value_t = false)]
+    pub keyword_report: bool,
+
+    /// Print how many of the selected files contain each of the or keywords to stderr
+    #[arg(long, default_value_t = false)]
+    pub keyword_stats: bool,
+
+    /// Report clusters of files with highly similar contents to stderr
+    #[arg(long, default_value_t = false)]
+    pub detect_duplicates: bool,
 
     /// Only show files that have differences
     #[arg(long, default_value_t = false)]
     pub diff_only: bool,
 
+    /// Annotate each diff with the line ranges changed in the file
+    #[arg(long, default_value_t = false)]
+    pub diff_line_ranges: bool,
+
+    /// List the inserted and deleted lines of every changed file before the diffs
+    #[arg(long, default_value_t = false)]
+    pub diffstat: bool,
+
+    /// Show the current content of each changed file below its diff
+    #[arg(long, default_value_t = false)]
+    pub diff_with_content: bool,
+
+    /// Include the messages of the commits that touched each file in its diff section
+    #[arg(long, default_value_t = false)]
+    pub include_commit_messages: bool,
+
+    /// List the abbreviated id and subject of every commit in the diffed range before the diffs
+    #[arg(long, default_value_t = false)]
+    pub list_commits: bool,
+
     /// Starting commit hash for diff comparison
     #[arg(long)]
     pub start_commit_id: Option<String>,
@@ -67,4 +335,52 @@ pub struct Cli {
     /// Ending commit hash for diff comparison
     #[arg(long)]
     pub end_commit_id: Option<String>,
+
+    /// Only show changes staged for the next commit, comparing HEAD with the index instead of the working directory
+    #[arg(long, default_value_t = false)]
+    pub staged: bool,
+
+    /// Include untracked files in diffs against the working directory, shown as entirely added
+    #[arg(long, default_value_t = false)]
+    pub include_untracked: bool,
+
+    /// Ignore changes that only touch whitespace, such as reindented lines, in the diffs
+    #[arg(long, default_value_t = false)]
+    pub ignore_whitespace: bool,
+
+    /// Only process files changed since HEAD branched off this branch; implies --diff-only
+    #[arg(long, num_args = 0..=1, default_missing_value = "origin/main")]
+    pub pr_base: Option<String>,
+
+    /// Only process files changed since the most recent tag reachable from HEAD; implies --diff-only
+    #[arg(long, default_value_t = false)]
+    pub since_tag: bool,
+
+    /// Print the JSON Schema of the structured JSON output and exit
+    #[arg(long, default_value_t = false)]
+    pub print_schema: bool,
+
+    /// Print the resolved configuration of the run as JSON and exit
+    #[arg(long, default_value_t = false)]
+    pub dump_config: bool,
+
+    /// Exit with status 0 even if some files couldn't be read or processed
+    #[arg(long, default_value_t = false)]
+    pub allow_errors: bool,
+
+    /// Don't show a progress bar, status messages, warnings or non-fatal errors
+    #[arg(long, default_value_t = false)]
+    pub quiet: bool,
+
+    /// Log why each file is selected or skipped to stderr; give it twice (-vv) for more detail
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// When to color terminal output
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Disable colored terminal output, same as --color=never
+    #[arg(long, default_value_t = false, conflicts_with = "color")]
+    pub no_color: bool,
 }
\ No newline at end of file
@@ -0,0 +1,51 @@
+use std::env;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR_ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Decides once per run whether terminal output is colored. `NO_COLOR` disables
+/// color unless it is explicitly forced with `--color=always`.
+pub fn init_color(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            !no_color && std::io::stderr().is_terminal()
+        }
+    };
+    let _ = COLOR_ENABLED.set(enabled);
+}
+
+pub fn color_enabled() -> bool {
+    *COLOR_ENABLED.get().unwrap_or(&false)
+}
+
+fn paint(text: &str, code: &str) -> String {
+    if color_enabled() {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn red(text: &str) -> String {
+    paint(text, "31")
+}
+
+pub fn green(text: &str) -> String {
+    paint(text, "32")
+}
+
+pub fn yellow(text: &str) -> String {
+    paint(text, "33")
+}
@@ -0,0 +1,73 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use crate::cli::Cli;
+use crate::output::OutputFormat;
+use crate::summary::Provider;
+
+/// Name of the config file that is picked up from the current directory
+pub const CONFIG_FILE_NAME: &str = ".dirscribe.toml";
+
+/// Defaults for a run, read from a TOML config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub suffixes: Option<Vec<String>>,
+    pub exclude_paths: Option<Vec<String>>,
+    pub provider: Option<String>,
+    pub concurrency: Option<usize>,
+    pub temperature: Option<f32>,
+    pub format: Option<OutputFormat>,
+}
+
+/// Reads the config from `path`, or from `.dirscribe.toml` if no path is given.
+/// Only an explicitly given config file has to exist.
+pub fn load_config(path: Option<&str>) -> Result<Option<Config>> {
+    let path = match path {
+        Some(path) => Path::new(path),
+        None if Path::new(CONFIG_FILE_NAME).is_file() => Path::new(CONFIG_FILE_NAME),
+        None => return Ok(None),
+    };
+
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let config: Config = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+    if let Some(provider) = &config.provider {
+        Provider::from_str(provider)
+            .with_context(|| format!("Invalid provider in config file {}", path.display()))?;
+    }
+    Ok(Some(config))
+}
+
+impl Config {
+    /// Fills in the options that were not given on the command line. Command line flags
+    /// take precedence over the config, which takes precedence over the built-in defaults.
+    pub fn apply(self, cli: &mut Cli, matches: &ArgMatches) {
+        if cli.suffixes.is_none() {
+            cli.suffixes = self.suffixes.map(|suffixes| suffixes.join(","));
+        }
+        if cli.exclude_paths.is_none() {
+            cli.exclude_paths = self.exclude_paths.map(|paths| paths.join(","));
+        }
+        if cli.concurrency.is_none() {
+            cli.concurrency = self.concurrency;
+        }
+        // The temperature only matters, and is only accepted, when summarizing
+        if cli.temperature.is_none() && (cli.summarize || cli.summarize_keywords || cli.summarize_stdin) {
+            cli.temperature = self.temperature;
+        }
+        if let (Some(format), false) = (self.format, matches.value_source("format") == Some(ValueSource::CommandLine)) {
+            cli.format = format;
+        }
+        // An exported DIRSCRIBE_PROVIDER wins over the config; the name was checked when loading
+        if let (Some(provider), None) = (self.provider, env::var_os("DIRSCRIBE_PROVIDER")) {
+            cli.config_provider = Provider::from_str(&provider).ok();
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::{HashMap, HashSet};
+use crate::color::yellow;
+
+const DUPLICATE_SIMILARITY_THRESHOLD: f64 = 0.8;
+
+fn token_set(content: &str) -> HashSet<&str> {
+    content
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+fn jaccard_similarity(a: &HashSet<&str>, b: &HashSet<&str>) -> f64 {
+    let intersection = a.intersection(b).count();
+    let union = a.len() + b.len() - intersection;
+    if union == 0 {
+        return 0.0;
+    }
+    intersection as f64 / union as f64
+}
+
+fn find_root(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+/// Groups files whose token sets have a Jaccard similarity above the threshold.
+/// Only clusters with more than one file are returned, in the order of `valid_files`.
+pub fn find_duplicate_clusters(
+    valid_files: &[String],
+    file_contents: &HashMap<String, String>,
+) -> Vec<Vec<String>> {
+    let token_sets: Vec<HashSet<&str>> = valid_files.iter()
+        .map(|file| file_contents.get(file).map(|content| token_set(content)).unwrap_or_default())
+        .collect();
+
+    let mut parents: Vec<usize> = (0..valid_files.len()).collect();
+    for i in 0..valid_files.len() {
+        if token_sets[i].is_empty() {
+            continue;
+        }
+        for j in (i + 1)..valid_files.len() {
+            if jaccard_similarity(&token_sets[i], &token_sets[j]) >= DUPLICATE_SIMILARITY_THRESHOLD {
+                let root_i = find_root(&mut parents, i);
+                let root_j = find_root(&mut parents, j);
+                parents[root_j] = root_i;
+            }
+        }
+    }
+
+    let mut clusters: Vec<Vec<String>> = Vec::new();
+    let mut cluster_index: HashMap<usize, usize> = HashMap::new();
+    for (i, file) in valid_files.iter().enumerate() {
+        let root = find_root(&mut parents, i);
+        let index = *cluster_index.entry(root).or_insert_with(|| {
+            clusters.push(Vec::new());
+            clusters.len() - 1
+        });
+        clusters[index].push(file.clone());
+    }
+
+    clusters.into_iter().filter(|cluster| cluster.len() > 1).collect()
+}
+
+pub fn report_duplicate_clusters(clusters: &[Vec<String>]) {
+    if clusters.is_empty() {
+        eprintln!("No duplicate files detected");
+        return;
+    }
+
+    eprintln!("{}", yellow("Possible duplicate files detected:"));
+    for (i, cluster) in clusters.iter().enumerate() {
+        eprintln!("Cluster {}:", i + 1);
+        for file in cluster {
+            eprintln!("  {}", file);
+        }
+    }
+}
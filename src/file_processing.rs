@@ -1,34 +1,355 @@
 use std::fs;
 use std::io::{self, Write, Cursor};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use anyhow::Context;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use globset::{GlobBuilder, GlobMatcher};
 use ignore::WalkBuilder;
-use std::collections::HashMap;
-use git2::{Repository, Tree};
-use chrono::Local;
-use crate::git::{get_diff_list, get_diff_str, filter_diff_for_file};
-use crate::summary::{get_summaries, check_summary};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::process::{Command, Stdio};
+use git2::Repository;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use regex::{Regex, RegexBuilder};
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::debug;
+use rayon::prelude::*;
+use crate::git::{get_diff, get_diff_list, get_diff_stats, get_commits_in_range, format_change_counts, DiffTarget, get_diff_str, filter_diff_for_file, describe_changed_lines, get_commit_messages_by_path, format_commit_messages};
+use crate::summary::{get_summaries, compare_summaries, render_comparison, get_directory_summaries, get_overview, check_summary, ProviderSpec, SummaryOptions, SummaryOutcome};
+use crate::color::yellow;
+use crate::output::{BlockWriter, format_block, heading, mirrored_path, number_lines, render_tree, build_summary_index, truncate_blocks, drop_largest_blocks, BYTES_PER_TOKEN, FileDigest, JsonFile, JsonOutput, OutputFormat};
+use crate::duplicates::{find_duplicate_clusters, report_duplicate_clusters};
+use crate::prompt_handling::get_prompt;
 
 
+pub const OUTPUT_SECTIONS: &[&str] = &["paths", "tree", "contents"];
+
+/// Kind of comment a summary is written as, for files without a known comment syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CommentStyle {
+    /// Every line starts with --comment-prefix
+    Line,
+    /// The summary is enclosed in --comment-open and --comment-close
+    Block,
+}
+
+/// Where `--apply` writes the summary into a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryPosition {
+    /// Before the code
+    #[default]
+    Top,
+    /// After the code, e.g. to keep a license header first
+    Bottom,
+}
+
+/// Order of the files in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// By file name, then by path
+    Name,
+    /// By full path
+    #[default]
+    Path,
+    /// By size, smallest first
+    Size,
+    /// By modification time, oldest first
+    Mtime,
+}
+
+// Normalizations applied to each file's content before it is emitted
+#[derive(Debug, Clone, Default)]
+pub struct ContentOptions {
+    pub trim_whitespace: bool,
+    pub minify: bool,
+    pub strip_lines: Vec<Regex>,
+}
+
+/// Comment delimiters per file extension as (start, end), line comments end with "\n".
+/// The built-in ones are borrowed, those given on the command line are owned.
+pub type CommentMap = HashMap<Cow<'static, str>, Vec<(Cow<'static, str>, Cow<'static, str>)>>;
+
+const SIDECAR_SUFFIX: &str = ".dirscribe.md";
+
+// Files read in parallel at a time when the output is written file by file
+const STREAM_CHUNK_FILES: usize = 64;
+
+/// Everything that controls a run of [`process_directory`] apart from the directory itself
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    /// File extensions to include, or `["*"]` for all files
+    pub suffixes: Vec<String>,
+    pub dont_use_gitignore: bool,
+    /// Descend into symlinked directories; symlink loops are reported and skipped
+    pub follow_symlinks: bool,
+    /// Replace file contents with summaries from the LLM provider
+    pub summarize: bool,
+    /// Summarize with the keyword prompt instead of the default summary prompt
+    pub summarize_keywords: bool,
+    /// Prompt templates by name, as returned by `load_prompts`; only needed when summarizing
+    pub prompt_templates: HashMap<String, String>,
+    /// Name of the prompt template to use instead of the default for the mode
+    pub prompt_version: Option<String>,
+    pub apply: bool,
+    /// Write each summary to a sidecar file next to its file instead of into the file
+    pub sidecar: bool,
+    /// Comment delimiters used for every file instead of those from `create_comment_map`;
+    /// line comments are given as the prefix and "\n"
+    pub comment_delimiters: Option<(String, String)>,
+    /// Where summaries are written into the files with `apply`
+    pub summary_position: SummaryPosition,
+    pub retrieve: bool,
+    pub diff_only: bool,
+    pub exclude_paths: Vec<PathBuf>,
+    pub include_paths: Vec<PathBuf>,
+    pub or_keywords: Vec<String>,
+    pub and_keywords: Vec<String>,
+    pub exclude_keywords: Vec<String>,
+    /// Interpret the keywords as regular expressions instead of literal text
+    pub regex_keywords: bool,
+    /// Match the keywords regardless of case
+    pub ignore_case: bool,
+    pub start_commit_id: Option<String>,
+    pub end_commit_id: Option<String>,
+    /// Diff HEAD against the index instead of the working directory, so only staged changes show
+    pub staged: bool,
+    /// Show untracked files as added in diffs against the working directory
+    pub include_untracked: bool,
+    /// Leave out changes that only touch whitespace from the diffs
+    pub ignore_whitespace: bool,
+    pub detect_duplicates: bool,
+    /// Only files matching these patterns are included, see `build_whitelist`
+    pub whitelist: Option<Gitignore>,
+    pub diff_line_ranges: bool,
+    /// Show the inserted and deleted lines of every changed file before the diffs
+    pub diffstat: bool,
+    pub diff_with_content: bool,
+    pub summarize_binary: bool,
+    pub summary_options: SummaryOptions,
+    /// Order of the output sections, a subset of `OUTPUT_SECTIONS`
+    pub section_order: Vec<String>,
+    pub sort: SortOrder,
+    /// List the file paths as a directory tree
+    pub tree: bool,
+    pub case_insensitive_suffixes: bool,
+    pub exclude_dirs: Vec<String>,
+    /// Extensions to leave out when `suffixes` is `["*"]`
+    pub exclude_extensions: Vec<String>,
+    pub preprocess_cmd: Option<String>,
+    pub format: OutputFormat,
+    pub keyword_report: bool,
+    /// Print how many of the selected files contain each OR keyword to stderr
+    pub keyword_stats: bool,
+    pub include_commit_messages: bool,
+    /// List the commits of the diffed range before the diffs
+    pub list_commits: bool,
+    pub content_options: ContentOptions,
+    /// Path the markdown summary index is written to
+    pub index_path: Option<String>,
+    /// Write every file's content or summary to its own file below this directory, mirroring the
+    /// source tree, instead of returning the combined output
+    pub output_dir: Option<PathBuf>,
+    pub classify: bool,
+    /// Annotate the file paths with their size and modification time
+    pub show_metadata: bool,
+    pub content_only: bool,
+    /// Prefix each line of the file contents with its line number; diffs and summaries are left as they are
+    pub line_numbers: bool,
+    pub max_output_bytes: Option<usize>,
+    /// Approximate token budget for the output; the largest files are left out first
+    pub max_output_tokens: Option<usize>,
+    pub compare_providers: Vec<ProviderSpec>,
+    pub skip_minified: bool,
+    pub hierarchical_summary: bool,
+    /// Prepend an architectural overview of all files, written by the provider in one extra request
+    pub overview: bool,
+    /// Model for the overview request instead of the one configured for the summaries
+    pub overview_model: Option<String>,
+    /// Don't print warnings and non-fatal errors to stderr
+    pub quiet: bool,
+    /// Stop after selecting the files and return only the `File Paths` section
+    pub dry_run: bool,
+    /// Process exactly these files instead of walking the directory
+    pub files_from: Option<Vec<PathBuf>>,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            suffixes: Vec::new(),
+            dont_use_gitignore: false,
+            follow_symlinks: false,
+            summarize: false,
+            summarize_keywords: false,
+            prompt_templates: HashMap::new(),
+            prompt_version: None,
+            apply: false,
+            sidecar: false,
+            comment_delimiters: None,
+            summary_position: SummaryPosition::Top,
+            retrieve: false,
+            diff_only: false,
+            exclude_paths: Vec::new(),
+            include_paths: Vec::new(),
+            or_keywords: Vec::new(),
+            and_keywords: Vec::new(),
+            exclude_keywords: Vec::new(),
+            regex_keywords: false,
+            ignore_case: false,
+            start_commit_id: None,
+            end_commit_id: None,
+            staged: false,
+            include_untracked: false,
+            ignore_whitespace: false,
+            detect_duplicates: false,
+            whitelist: None,
+            diff_line_ranges: false,
+            diffstat: false,
+            diff_with_content: false,
+            summarize_binary: false,
+            summary_options: SummaryOptions::default(),
+            section_order: vec!["paths".to_string(), "contents".to_string()],
+            sort: SortOrder::Path,
+            tree: false,
+            case_insensitive_suffixes: false,
+            exclude_dirs: Vec::new(),
+            exclude_extensions: Vec::new(),
+            preprocess_cmd: None,
+            format: OutputFormat::Text,
+            keyword_report: false,
+            keyword_stats: false,
+            include_commit_messages: false,
+            list_commits: false,
+            content_options: ContentOptions::default(),
+            index_path: None,
+            output_dir: None,
+            classify: false,
+            show_metadata: false,
+            content_only: false,
+            line_numbers: false,
+            max_output_bytes: None,
+            max_output_tokens: None,
+            compare_providers: Vec::new(),
+            skip_minified: false,
+            hierarchical_summary: false,
+            overview: false,
+            overview_model: None,
+            quiet: false,
+            dry_run: false,
+            files_from: None,
+        }
+    }
+}
+
+/// The result of [`process_directory_with_files`]
+#[derive(Debug, Clone, Default)]
+pub struct ProcessedDirectory {
+    /// The selected files in output order
+    pub files: Vec<PathBuf>,
+    /// The output, as returned by [`process_directory`]
+    pub blocks: Vec<String>,
+    /// Selected files that couldn't be read or processed and are missing from the output
+    pub failed_files: Vec<PathBuf>,
+}
+
 pub async fn process_directory(
     dir_path: &str,
-    suffixes: &[String],
-    dont_use_gitignore: bool,
-    summarize: bool,
-    summarize_keywords: bool,
-    summarize_prompt_templates: HashMap<String, String>,
-    apply: bool,
-    retrieve: bool,
-    diff_only: bool,
-    exclude_paths: &[PathBuf],
-    include_paths: &[PathBuf],
-    or_keywords: &[String],
-    and_keywords: &[String],
-    exclude_keywords: &[String],
-    start_commit_id: Option<&str>,
-    end_commit_id: Option<&str>
-) -> anyhow::Result<String> {
-    let mut output = Cursor::new(Vec::new());
+    options: &ProcessOptions
+) -> anyhow::Result<Vec<String>> {
+    Ok(process_directory_with_files(dir_path, options).await?.blocks)
+}
+
+/// Like [`process_directory`], but also returns the selected files and the ones that failed
+pub async fn process_directory_with_files(
+    dir_path: &str,
+    options: &ProcessOptions
+) -> anyhow::Result<ProcessedDirectory> {
+    let mut blocks = Vec::new();
+    let processed = process_directory_into(dir_path, options, &mut blocks).await?;
+    Ok(ProcessedDirectory { blocks, ..processed })
+}
+
+/// Like [`process_directory_with_files`], but hands each block to `output` as soon as it is
+/// built, so the output of plain file contents and diffs is never held in memory as a whole.
+/// The returned blocks are empty.
+pub async fn process_directory_into(
+    dir_path: &str,
+    options: &ProcessOptions,
+    output: &mut impl BlockWriter
+) -> anyhow::Result<ProcessedDirectory> {
+    let ProcessOptions {
+        ref suffixes,
+        dont_use_gitignore,
+        follow_symlinks,
+        summarize,
+        summarize_keywords,
+        ref prompt_templates,
+        ref prompt_version,
+        apply,
+        sidecar,
+        ref comment_delimiters,
+        summary_position,
+        retrieve,
+        diff_only,
+        ref exclude_paths,
+        ref include_paths,
+        ref or_keywords,
+        ref and_keywords,
+        ref exclude_keywords,
+        regex_keywords,
+        ignore_case,
+        ref start_commit_id,
+        ref end_commit_id,
+        staged,
+        include_untracked,
+        ignore_whitespace,
+        detect_duplicates,
+        ref whitelist,
+        diff_line_ranges,
+        diffstat,
+        diff_with_content,
+        summarize_binary,
+        ref summary_options,
+        ref section_order,
+        sort,
+        tree,
+        case_insensitive_suffixes,
+        ref exclude_dirs,
+        ref exclude_extensions,
+        ref preprocess_cmd,
+        format,
+        keyword_report,
+        keyword_stats,
+        include_commit_messages,
+        list_commits,
+        ref content_options,
+        ref index_path,
+        ref output_dir,
+        classify,
+        show_metadata,
+        content_only,
+        line_numbers,
+        max_output_bytes,
+        max_output_tokens,
+        ref compare_providers,
+        skip_minified,
+        hierarchical_summary,
+        overview,
+        ref overview_model,
+        quiet,
+        dry_run,
+        ref files_from,
+    } = *options;
+    let (start_commit_id, end_commit_id) = (start_commit_id.as_deref(), end_commit_id.as_deref());
+    let diff_target = DiffTarget { start_commit_id, end_commit_id, staged, include_untracked, ignore_whitespace };
+    let (preprocess_cmd, index_path) = (preprocess_cmd.as_deref(), index_path.as_deref());
+    let whitelist = whitelist.as_ref();
     let dir_path = Path::new(dir_path);
     
     let repo = if diff_only {
@@ -44,183 +365,911 @@ pub async fn process_directory(
     let mut diff_list = Vec::new();
     if diff_only {
         if let Some(repo) = &repo {
-            diff_list = get_diff_list(repo, start_commit_id, end_commit_id)?;
+            diff_list = get_diff_list(repo, &diff_target)?;
+        }
+    }
+
+    let mut commit_messages = HashMap::new();
+    if diff_only && include_commit_messages {
+        if let Some(repo) = &repo {
+            commit_messages = get_commit_messages_by_path(repo, start_commit_id, end_commit_id)?;
         }
     }
+    // Paths are relative to the repository root, files are keyed by their walked path
+    let commit_messages_for = |file: &Path| -> Option<String> {
+        let relative_path = file.strip_prefix(dir_path).ok()?;
+        commit_messages.get(relative_path).map(|messages| format_commit_messages(messages))
+    };
+
+    // Keywords are compiled once here and matched against every file
+    let or_keywords = compile_keywords(or_keywords, regex_keywords, ignore_case)?;
+    let and_keywords = compile_keywords(and_keywords, regex_keywords, ignore_case)?;
+    let exclude_keywords = compile_keywords(exclude_keywords, regex_keywords, ignore_case)?;
+    // And so are the glob entries of the path filters
+    let exclude_paths = exclude_paths.iter().map(|path| PathFilter::new(path)).collect::<anyhow::Result<Vec<_>>>()?;
+    let include_paths = include_paths.iter().map(|path| PathFilter::new(path)).collect::<anyhow::Result<Vec<_>>>()?;
 
     // First, collect all valid file paths
     let mut valid_files = Vec::new();
+    let mut keyword_matches = Vec::new();
+    let mut file_bytes: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+    // Size and modification time of the selected files, for --show-metadata
+    let mut file_metadata: HashMap<PathBuf, (u64, DateTime<Utc>)> = HashMap::new();
     
-    let walker = WalkBuilder::new(dir_path)
-        .hidden(false)
-        .git_ignore(!dont_use_gitignore)
-        .build();
-
-    for result in walker {
-        match result {
-            Ok(entry) => {
-                let path = entry.path();
-                
-                // Skip if diff_only is true and path is not in diff_list
-                if diff_only {
-                    if let Ok(relative_path) = path.strip_prefix(dir_path) {
-                        if !diff_list.contains(&relative_path.to_path_buf()) {
-                            continue;
-                        }
-                    }
+    // A file list given with --files-from replaces the walk; the listed files skip the suffix and path filters
+    let candidates: Vec<PathBuf> = match files_from {
+        Some(files) => {
+            let mut candidates = Vec::new();
+            for file in files {
+                let path = if file.is_absolute() {
+                    // Absolute paths inside the directory are made relative to it, like the walked paths
+                    fs::canonicalize(file).ok()
+                        .zip(fs::canonicalize(dir_path).ok())
+                        .and_then(|(file, root)| file.strip_prefix(root).ok().map(|relative| dir_path.join(relative)))
+                        .unwrap_or_else(|| file.clone())
+                } else {
+                    dir_path.join(file.strip_prefix(".").unwrap_or(file))
+                };
+                if !path.is_file() {
+                    return Err(anyhow::anyhow!("File not found: {}", file.display()));
                 }
+                candidates.push(path);
+            }
+            candidates.sort();
+            candidates.dedup();
+            candidates
+        },
+        None => {
+            // Directories named in exclude_dirs are pruned at any depth, so they are never descended into
+            let pruned_dirs = exclude_dirs.to_vec();
+            // .dirscribeignore files apply at any depth, also when .gitignore rules are turned off.
+            // The global excludes file and .git/info/exclude are git ignore rules as well.
+            let walker = WalkBuilder::new(dir_path)
+                .hidden(false)
+                .git_ignore(!dont_use_gitignore)
+                .git_global(!dont_use_gitignore)
+                .git_exclude(!dont_use_gitignore)
+                .follow_links(follow_symlinks)
+                .add_custom_ignore_filename(".dirscribeignore")
+                .filter_entry(move |entry| {
+                    let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                    !(is_dir && entry.depth() > 0 && pruned_dirs.iter().any(|d| entry.file_name() == d.as_str()))
+                })
+                .build();
 
-                let should_include = if path.is_dir() {
-                    false
-                } else if suffixes.contains(&"*".to_string()) {
-                    // If wildcard is specified, check if it's a text-like file
-                    is_likely_text_file(path)
-                } else if let Some(file_suffix) = path.extension() {
-                    suffixes.iter().any(|s| s == file_suffix.to_str().unwrap_or(""))
-                } else {
-                    if let Some(filename) = path.file_name() {
-                        suffixes.iter().any(|s| s == filename.to_str().unwrap_or(""))
-                    } else {
-                        false
+            let mut candidates = Vec::new();
+            for result in walker {
+                match result {
+                    Ok(entry) => candidates.push(entry.into_path()),
+                    Err(err) if !quiet => eprintln!("Error walking directory: {}", err),
+                    Err(_) => {},
+                }
+            }
+            // Files deleted in the diff are gone from disk, so the walk never finds them
+            if diff_only {
+                for relative_path in &diff_list {
+                    let path = dir_path.join(relative_path);
+                    let pruned = relative_path.components().any(|c| exclude_dirs.iter().any(|d| c.as_os_str() == d.as_str()));
+                    if !pruned && fs::symlink_metadata(&path).is_err() {
+                        candidates.push(path);
                     }
-                };
+                }
+            }
+            candidates
+        },
+    };
+    let listed = files_from.is_some();
+    let mut failed_files = Vec::new();
+    // Only the minified check and the keyword filters look at the contents while selecting
+    let content_filtered = skip_minified || keyword_report || keyword_stats
+        || !or_keywords.is_empty() || !and_keywords.is_empty() || !exclude_keywords.is_empty();
 
-                if should_include {
-                    // Get relative path from base directory
-                    if let Ok(relative_path) = path.strip_prefix(dir_path) {
-                        let relative_path_str = relative_path.to_string_lossy();
-                        
-                        // Skip if path matches any exclude pattern
-                        if exclude_paths.iter().any(|excluded| 
-                            relative_path_str.starts_with(&excluded.to_string_lossy().as_ref())
-                        ) {
-                            continue;
-                        }
-                        
-                        // Skip if include patterns exist and path doesn't match any
-                        if !include_paths.is_empty() {
-                            let is_included = include_paths.iter().any(|included|
-                                relative_path_str.starts_with(&included.to_string_lossy().as_ref())
-                            );
-                            if !is_included {
-                                continue;
-                            }
-                        }
+    for path in &candidates {
+        let path = path.as_path();
 
-                        // Check keyword filters before adding to valid files
-                        if check_for_keywords(
-                            &path.to_path_buf(),
-                            or_keywords,
-                            and_keywords,
-                            exclude_keywords,
-                        )? {
-                            valid_files.push(path.to_path_buf());
-                        }
+        if path.is_dir() {
+            continue;
+        }
+
+        // Every skipped file is logged with the reason, shown with --verbose
+        // Skip if diff_only is true and path is not in diff_list; a listed file outside the directory can't be in it
+        if diff_only {
+            let changed = path.strip_prefix(dir_path)
+                .is_ok_and(|relative_path| diff_list.contains(&relative_path.to_path_buf()));
+            if !changed {
+                debug!(file = %path.display(), reason = "unchanged in the diff", "Skipped file");
+                continue;
+            }
+        }
+
+        // Sidecar files hold summaries of other files, they are never summarized themselves
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        let skip_reason = if listed {
+            None
+        } else if path.to_string_lossy().ends_with(SIDECAR_SUFFIX) {
+            Some("sidecar file")
+        } else if suffixes.contains(&"*".to_string()) {
+            // If wildcard is specified, check if it's a text-like file that isn't excluded by its extension
+            if !is_likely_text_file(path) {
+                Some("not a known text file")
+            } else if exclude_extensions.iter().any(|e| suffix_matches(e, extension.unwrap_or(""), case_insensitive_suffixes)) {
+                Some("excluded extension")
+            } else {
+                None
+            }
+        } else {
+            // Files without an extension are matched by their whole name
+            let candidate = extension.or_else(|| path.file_name().and_then(|name| name.to_str())).unwrap_or("");
+            if suffixes.iter().any(|s| suffix_matches(s, candidate, case_insensitive_suffixes)) {
+                None
+            } else {
+                Some("suffix mismatch")
+            }
+        };
+        if let Some(reason) = skip_reason {
+            debug!(file = %path.display(), reason, "Skipped file");
+            continue;
+        }
+
+        if !listed {
+            // Get relative path from base directory
+            let relative_path = path.strip_prefix(dir_path).unwrap_or(path);
+
+            // Skip if path matches any exclude pattern
+            if exclude_paths.iter().any(|excluded| excluded.covers(relative_path)) {
+                debug!(file = %path.display(), reason = "excluded path", "Skipped file");
+                continue;
+            }
+            
+            // Skip if a whitelist exists and path doesn't match it; it replaces the include patterns
+            if let Some(whitelist) = whitelist {
+                if !whitelist.matched_path_or_any_parents(relative_path, false).is_ignore() {
+                    debug!(file = %path.display(), reason = "not in whitelist", "Skipped file");
+                    continue;
+                }
+            } else if !include_paths.is_empty() {
+                // Skip if include patterns exist and path doesn't match any
+                let is_included = include_paths.iter().any(|included| included.covers(relative_path));
+                if !is_included {
+                    debug!(file = %path.display(), reason = "not in include paths", "Skipped file");
+                    continue;
+                }
+            }
+        }
+
+        // Images are described by the provider, so keyword filters don't apply to them
+        let is_image = summarize_binary && image_media_type(path).is_some();
+        // A file deleted in the diff is still shown, it just has no contents left
+        let deleted = diff_only && fs::symlink_metadata(path).is_err();
+
+        // Files are read here only for a content filter, the bytes are then reused for their
+        // contents below. All other files are read in parallel once the selection is done.
+        let bytes = if is_image || !content_filtered {
+            None
+        } else if deleted {
+            Some(Vec::new())
+        } else {
+            match fs::read(path) {
+                Ok(bytes) => Some(bytes),
+                Err(e) => {
+                    if !quiet {
+                        eprintln!("Error processing file {}: {}", path.display(), e);
                     }
+                    failed_files.push(path.to_path_buf());
+                    continue;
+                }
+            }
+        };
+
+        if skip_minified && bytes.as_deref().is_some_and(is_minified) {
+            debug!(file = %path.display(), reason = "minified", "Skipped file");
+            continue;
+        }
+
+        // Check keyword filters before adding to valid files; binary files can still
+        // show up in diff mode, they simply never contain the keywords
+        let keyword_match = bytes.as_deref().map(|bytes| check_for_keywords(
+            &String::from_utf8_lossy(bytes),
+            &or_keywords,
+            &and_keywords,
+            &exclude_keywords,
+        ));
+        if let (true, Some(keyword_match)) = (keyword_report || keyword_stats, &keyword_match) {
+            keyword_matches.push((path.to_path_buf(), keyword_match.clone()));
+        }
+        if keyword_match.as_ref().is_none_or(|m| m.included) {
+            debug!(file = %path.display(), "Selected file");
+            valid_files.push(path.to_path_buf());
+            // Without readable metadata the file is still processed, only its annotation is left out
+            if show_metadata && !deleted {
+                match fs::metadata(path).and_then(|metadata| Ok((metadata.len(), metadata.modified()?))) {
+                    Ok((size, modified)) => {
+                        file_metadata.insert(path.to_path_buf(), (size, modified.into()));
+                    },
+                    Err(e) if !quiet => eprintln!("{} can't read the metadata of {}: {}", yellow("Warning:"), path.display(), e),
+                    Err(_) => {},
                 }
             }
-            Err(err) => eprintln!("Error walking directory: {}", err),
+            // Diffs come from git, so the contents are only needed outside diff mode or for the JSON output
+            if let (true, Some(bytes)) = (!diff_only || format == OutputFormat::Json, bytes) {
+                file_bytes.insert(path.to_path_buf(), bytes);
+            }
+        } else {
+            debug!(file = %path.display(), reason = %format!("keyword filter: {}", keyword_match.as_ref().map(KeywordMatch::describe).unwrap_or_default()), "Skipped file");
+        }
+    }
+
+    sort_files(&mut valid_files, sort);
+
+    if keyword_report {
+        report_keyword_matches(&keyword_matches);
+    }
+    if keyword_stats {
+        let selected: Vec<&KeywordMatch> = keyword_matches.iter()
+            .map(|(_, keyword_match)| keyword_match)
+            .filter(|keyword_match| keyword_match.included)
+            .collect();
+        report_keyword_stats(&count_keyword_matches(&or_keywords, &selected));
+    }
+
+    // Sections are assembled in the requested order once all of them are built
+    let mut paths_section = Cursor::new(Vec::new());
+    writeln!(paths_section, "{}", heading("File Paths", 1, format))?;
+    if format == OutputFormat::Markdown {
+        writeln!(paths_section)?;
+    }
+    let list_marker = if format == OutputFormat::Markdown { "- " } else { "" };
+    // Like `ls -F`, executable files are marked with a trailing `*`, metadata follows in parentheses
+    let marker = |file_path: &Path| {
+        let mut marker = if classify && is_executable(file_path) { "*".to_string() } else { String::new() };
+        if let Some((size, modified)) = file_metadata.get(file_path) {
+            marker += &format!(" ({} bytes, modified {})", size, modified.to_rfc3339_opts(SecondsFormat::Secs, true));
+        }
+        marker
+    };
+    let write_tree = |section: &mut Cursor<Vec<u8>>| -> io::Result<()> {
+        let paths: Vec<Vec<String>> = valid_files.iter()
+            .map(|file_path| {
+                let relative_path = file_path.strip_prefix(dir_path).unwrap_or(file_path);
+                let mut components: Vec<String> = relative_path.components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                if let Some(file_name) = components.last_mut() {
+                    file_name.push_str(&marker(file_path));
+                }
+                components
+            })
+            .collect();
+        // Markdown would turn the indentation into a code block anyway, so it's fenced explicitly
+        if format == OutputFormat::Markdown {
+            writeln!(section, "```text")?;
+        }
+        for line in render_tree(&dir_path.display().to_string(), &paths) {
+            writeln!(section, "{}", line)?;
+        }
+        if format == OutputFormat::Markdown {
+            writeln!(section, "```")?;
+        }
+        Ok(())
+    };
+    if tree {
+        write_tree(&mut paths_section)?;
+    } else {
+        for file_path in &valid_files {
+            writeln!(paths_section, "{}{}{}", list_marker, file_path.display(), marker(file_path))?;
+        }
+    }
+    writeln!(paths_section)?;
+
+    // The tree can also be a section of its own, e.g. ahead of the plain path list
+    let mut tree_section = Cursor::new(Vec::new());
+    if section_order.iter().any(|section| section == "tree") {
+        writeln!(tree_section, "{}", heading("File Tree", 1, format))?;
+        if format == OutputFormat::Markdown {
+            writeln!(tree_section)?;
+        }
+        write_tree(&mut tree_section)?;
+        writeln!(tree_section)?;
+    }
+
+    output.begin(&valid_files)?;
+
+    // Nothing is processed or sent to a provider, the selection itself is the result
+    if dry_run {
+        if !quiet {
+            eprintln!("{} file{} selected", valid_files.len(), if valid_files.len() == 1 { "" } else { "s" });
         }
+        let paths_section = String::from_utf8(paths_section.into_inner())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        output.write_block(paths_section)?;
+        return Ok(ProcessedDirectory { files: valid_files, blocks: Vec::new(), failed_files });
     }
 
-    // Write all file paths at the top
-    writeln!(output, "File Paths:")?;
-    for file_path in &valid_files {
-        writeln!(output, "{}", file_path.display())?;
+    // The commit list and diffstat give an overview of the changes ahead of the diffs themselves
+    let mut contents_heading = Cursor::new(Vec::new());
+    if let (true, Some(repo), Some(start_commit_id)) = (list_commits, &repo, start_commit_id) {
+        writeln!(contents_heading, "{}", heading("Commits in range", 1, format))?;
+        if format == OutputFormat::Markdown {
+            writeln!(contents_heading)?;
+        }
+        for (short_id, subject) in get_commits_in_range(repo, start_commit_id, end_commit_id)? {
+            writeln!(contents_heading, "{}{} {}", list_marker, short_id, subject)?;
+        }
+        writeln!(contents_heading)?;
+    }
+    if let (true, Some(repo)) = (diffstat, &repo) {
+        let stats = get_diff_stats(repo, &diff_target)?;
+        let (mut total_insertions, mut total_deletions) = (0, 0);
+        writeln!(contents_heading, "{}", heading("Diffstat", 1, format))?;
+        if format == OutputFormat::Markdown {
+            writeln!(contents_heading)?;
+        }
+        for file_path in &valid_files {
+            let relative_path = file_path.strip_prefix(dir_path).unwrap_or(file_path);
+            let (insertions, deletions) = stats.get(relative_path).copied().unwrap_or_default();
+            total_insertions += insertions;
+            total_deletions += deletions;
+            writeln!(contents_heading, "{}{} | {}", list_marker, file_path.display(), format_change_counts(insertions, deletions))?;
+        }
+        let files_changed = format!("{} file{} changed", valid_files.len(), if valid_files.len() == 1 { "" } else { "s" });
+        writeln!(contents_heading, "{}, {}", files_changed, format_change_counts(total_insertions, total_deletions))?;
+        writeln!(contents_heading)?;
     }
-    writeln!(output)?;
     if !summarize && !summarize_keywords {
-        writeln!(output, "File Contents:")?;
+        writeln!(contents_heading, "{}", heading("File Contents", 1, format))?;
     } else {
-        writeln!(output, "File Summaries:")?;
+        writeln!(contents_heading, "{}", heading("File Summaries", 1, format))?;
     }
-    writeln!(output)?;
+    writeln!(contents_heading)?;
+
+    let paths_section = String::from_utf8(paths_section.into_inner())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let tree_section = String::from_utf8(tree_section.into_inner())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let contents_heading = String::from_utf8(contents_heading.into_inner())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-    let file_contents: HashMap<String, String> = valid_files
-        .iter()
-        .filter_map(|file_path| {
-            let path_string = file_path.to_string_lossy().into_owned();
-            match process_file(
+    // The repository is passed in rather than captured, so the closure can be shared across threads
+    let read_contents = |file_path: &PathBuf, bytes: Option<Vec<u8>>, repo: Option<&Repository>| -> (io::Result<String>, Option<FileDigest>) {
+        let reads_file = !diff_only && (!summarize_binary || image_media_type(file_path).is_none());
+        let bytes = match bytes {
+            None if reads_file || format == OutputFormat::Json => Some(fs::read(file_path)),
+            bytes => bytes.map(Ok),
+        };
+        // The JSON output describes the files as they were read, before any content options
+        let digest = match (&bytes, format) {
+            (Some(Ok(bytes)), OutputFormat::Json) => Some(FileDigest::new(bytes)),
+            _ => None,
+        };
+        let processed = if summarize_binary && image_media_type(file_path).is_some() {
+            read_image_base64(file_path)
+        } else if diff_only {
+            process_file(
                 file_path,
                 diff_only,
-                repo.as_ref(),
-                start_commit_id,
-                end_commit_id
-            ) {
-                Ok(content) => Some((path_string, content)),
+                repo,
+                &diff_target,
+                content_options
+            )
+        } else {
+            bytes.unwrap_or_else(|| fs::read(file_path))
+                .and_then(|bytes| String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+                .map(|contents| apply_content_options(file_path, strip_bom(contents), content_options))
+        };
+        let processed = match (processed, preprocess_cmd) {
+            (Ok(content), Some(cmd)) if !diff_only && image_media_type(file_path).is_none() => {
+                Ok(preprocess_content(cmd, file_path, content, quiet))
+            },
+            (processed, _) => processed,
+        };
+        (processed, digest)
+    };
+    // Files are read and preprocessed in parallel, diffs come one by one from the shared repository
+    let read_chunk = |chunk: Vec<(usize, Option<Vec<u8>>)>| -> Vec<(usize, io::Result<String>, Option<FileDigest>)> {
+        if diff_only {
+            chunk.into_iter()
+                .map(|(i, bytes)| {
+                    let (processed, digest) = read_contents(&valid_files[i], bytes, repo.as_ref());
+                    (i, processed, digest)
+                })
+                .collect()
+        } else {
+            chunk.into_par_iter()
+                .map(|(i, bytes)| {
+                    let (processed, digest) = read_contents(&valid_files[i], bytes, None);
+                    (i, processed, digest)
+                })
+                .collect()
+        }
+    };
+    let inputs: Vec<(usize, Option<Vec<u8>>)> = valid_files.iter()
+        .enumerate()
+        .map(|(i, file_path)| (i, file_bytes.remove(file_path)))
+        .collect();
+
+    // Files whose current content can't be read still get their diff, but count as failed
+    let diff_file_block = |file: &PathBuf, content: &String, failed_files: &mut Vec<PathBuf>| -> String {
+        if content_only {
+            return content.clone();
+        }
+        let diff_block = format_block(content, "diff", format);
+        let diff_block = match commit_messages_for(file) {
+            Some(messages) => format!("{}\n{}", messages, diff_block),
+            None => diff_block,
+        };
+        let diff_heading = heading(&format!("Diff of {}", file.display()), 2, format);
+        let diff_section = if diff_line_ranges {
+            let ranges = describe_changed_lines(content);
+            format!("\n{}\n{}\n\n{}\n", diff_heading, ranges, diff_block)
+        } else {
+            format!("\n{}\n\n{}\n", diff_heading, diff_block)
+        };
+
+        if diff_with_content {
+            // A file deleted in the diff, or since it was selected, has no current content to show
+            let current_content = match process_file(file, false, None, &DiffTarget::default(), content_options) {
+                Ok(current_content) => current_content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => "File was deleted".to_string(),
                 Err(e) => {
+                    if !quiet {
+                        eprintln!("Error processing file {}: {}", file.display(), e);
+                    }
+                    failed_files.push(file.clone());
+                    return diff_section;
+                },
+            };
+            let content_block = format_block(&current_content, language_hint(file), format);
+            let content_heading = heading(&format!("Current Content of {}", file.display()), 3, format);
+            format!("{}\n{}\n\n{}\n", diff_section, content_heading, content_block)
+        } else {
+            diff_section
+        }
+    };
+    let content_file_block = |file: &PathBuf, content: &String| -> String {
+        let content = if line_numbers { number_lines(content) } else { content.clone() };
+        if content_only {
+            return content;
+        }
+        // In markdown the path alone makes the heading, the fence shows it is the content
+        let content_heading = match format {
+            OutputFormat::Text | OutputFormat::Json => heading(&format!("File Content of {}", file.display()), 2, format),
+            OutputFormat::Markdown => heading(&file.display().to_string(), 2, format),
+        };
+        format!("\n{}\n\n{}\n", content_heading, format_block(&content, language_hint(file), format))
+    };
+
+    // Plain contents and diffs are written file by file, each chunk of files is read right before
+    // it is written, so the output is never held in memory as a whole. Only the contents read for
+    // a content filter while selecting are kept until then. Everything else needs all files first.
+    let stream_files = !summarize && !summarize_keywords && !detect_duplicates && output_dir.is_none() && !overview
+        && format != OutputFormat::Json && max_output_bytes.is_none() && max_output_tokens.is_none() && !content_only;
+    if stream_files {
+        let mut inputs = inputs.into_iter();
+        for section in section_order {
+            match section.as_str() {
+                "paths" => output.write_block(paths_section.clone())?,
+                "tree" => output.write_block(tree_section.clone())?,
+                "contents" => {
+                    output.write_block(contents_heading.clone())?;
+                    loop {
+                        let chunk: Vec<(usize, Option<Vec<u8>>)> = inputs.by_ref().take(STREAM_CHUNK_FILES).collect();
+                        if chunk.is_empty() {
+                            break;
+                        }
+                        for (i, processed, _) in read_chunk(chunk) {
+                            let file_path = &valid_files[i];
+                            match processed {
+                                Ok(content) => {
+                                    debug!(file = %file_path.display(), bytes = content.len(), "Read file");
+                                    let block = if diff_only { diff_file_block(file_path, &content, &mut failed_files) } else { content_file_block(file_path, &content) };
+                                    output.write_block(block)?;
+                                },
+                                Err(e) => {
+                                    if !quiet {
+                                        eprintln!("Error processing file {}: {}", file_path.display(), e);
+                                    }
+                                    failed_files.push(file_path.clone());
+                                }
+                            }
+                        }
+                    }
+                },
+                _ => return Err(anyhow::anyhow!("Unknown output section: {}", section)),
+            }
+        }
+        return Ok(ProcessedDirectory { files: valid_files, blocks: Vec::new(), failed_files });
+    }
+
+    // Errors are reported afterwards, so they appear in file order
+    let mut file_contents: HashMap<String, String> = HashMap::new();
+    let mut file_digests: HashMap<usize, FileDigest> = HashMap::new();
+    for (i, processed, digest) in read_chunk(inputs) {
+        let file_path = &valid_files[i];
+        match processed {
+            Ok(content) => {
+                debug!(file = %file_path.display(), bytes = content.len(), "Read file");
+                file_contents.insert(file_path.to_string_lossy().into_owned(), content);
+                if let Some(digest) = digest {
+                    file_digests.insert(i, digest);
+                }
+            },
+            Err(e) => {
+                if !quiet {
                     eprintln!("Error processing file {}: {}", file_path.display(), e);
-                    None
                 }
+                failed_files.push(file_path.clone());
             }
-        })
-        .collect();
+        }
+    }
+
+    if detect_duplicates {
+        let valid_file_strings: Vec<String> = valid_files.iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        report_duplicate_clusters(&find_duplicate_clusters(&valid_file_strings, &file_contents));
+    }
 
     // Generate output string maintaining file path order
-    let result = if summarize | summarize_keywords {
+    let mut file_summaries = None;
+    // Directory summaries follow the file blocks, they aren't counted as files when truncating
+    let mut directory_blocks = 0;
+    let file_blocks = if summarize | summarize_keywords {
         let valid_file_strings: Vec<String> = valid_files.iter()
             .map(|path| path.to_string_lossy().into_owned())
             .collect();
         
 
-        let suffix_map = create_comment_map();
+        let mut suffix_map = create_comment_map();
+        if let Some((start, end)) = comment_delimiters {
+            let delimiters = (Cow::Owned(start.clone()), Cow::Owned(end.clone()));
+            let extensions: HashSet<&str> = valid_files.iter()
+                .map(|file| file.extension().and_then(|ext| ext.to_str()).unwrap_or(""))
+                .collect();
+            for extension in extensions {
+                suffix_map.insert(Cow::Owned(extension.to_string()), vec![delimiters.clone()]);
+            }
+        }
+        let summary_options = SummaryOptions {
+            image_prompt_template: if summarize_binary {
+                Some(get_prompt(prompt_templates, "summary-image-0.1")?)
+            } else {
+                None
+            },
+            ..summary_options.clone()
+        };
 
-        let summaries = if !diff_only {
-            if !retrieve {
-                if summarize {
-                    get_summaries(valid_file_strings.clone(), file_contents.clone(), summarize_prompt_templates["summary-0.2"].clone(), suffix_map.clone(), diff_only).await?
-                } else { // if summarize_keywords 
-                    get_summaries(valid_file_strings.clone(), file_contents.clone(), summarize_prompt_templates["summary-keywords-0.1"].clone(), suffix_map.clone(), diff_only).await?
-                }
+        // One outcome per file and provider, a single provider unless comparing
+        let outcomes: Vec<Vec<SummaryOutcome>> = if retrieve && !diff_only {
+            get_summaries_from_files(valid_file_strings.clone(), file_contents.clone())
+                .into_iter()
+                .map(|summary| vec![SummaryOutcome::Ok(summary)])
+                .collect()
+        } else {
+            let (prompt_template, contents) = if diff_only {
+                // Commit messages give the summarizer the intent behind each diff
+                let diff_contents: HashMap<String, String> = valid_files.iter()
+                    .filter_map(|file| {
+                        let path_string = file.to_string_lossy().into_owned();
+                        let content = file_contents.get(&path_string)?;
+                        let content = match commit_messages_for(file) {
+                            Some(messages) => format!("{}\n{}", messages, content),
+                            None => content.clone(),
+                        };
+                        Some((path_string, content))
+                    })
+                    .collect();
+                (get_prompt(prompt_templates, prompt_version.as_deref().unwrap_or("summary-diff-0.1"))?, diff_contents)
+            } else if summarize {
+                (get_prompt(prompt_templates, prompt_version.as_deref().unwrap_or("summary-0.2"))?, file_contents.clone())
+            } else { // if summarize_keywords
+                (get_prompt(prompt_templates, prompt_version.as_deref().unwrap_or("summary-keywords-0.1"))?, file_contents.clone())
+            };
+
+            if compare_providers.is_empty() {
+                get_summaries(valid_file_strings, contents, prompt_template, suffix_map.clone(), diff_only, &summary_options).await?
+                    .into_iter()
+                    .map(|outcome| vec![outcome])
+                    .collect()
             } else {
-                get_summaries_from_files(valid_file_strings.clone(), file_contents.clone())
+                compare_summaries(valid_file_strings, contents, prompt_template, suffix_map.clone(), diff_only, &summary_options, compare_providers).await?
             }
-        } else {
-            get_summaries(valid_file_strings, file_contents.clone(), summarize_prompt_templates["summary-diff-0.1"].clone(), suffix_map.clone(), diff_only).await?
         };
         
-        if apply && !diff_only {
+        let summaries: Vec<String> = outcomes.iter()
+            .map(|outcomes| if compare_providers.is_empty() {
+                outcomes[0].to_string()
+            } else {
+                render_comparison(compare_providers, outcomes)
+            })
+            .collect();
+
+        if let Some(index_path) = index_path {
+            let entries: Vec<(PathBuf, SummaryOutcome)> = valid_files.iter().cloned()
+                .zip(outcomes.iter().map(|outcomes| outcomes[0].clone()))
+                .collect();
+            let index_dir = Path::new(index_path).parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            fs::write(index_path, build_summary_index(&entries, index_dir))
+                .with_context(|| format!("Failed to write index to {}", index_path))?;
+        }
+
+        // Files without a summary, whether skipped after --per-file-timeout or failed, count as failed for the exit status
+        for (file_path, outcomes) in valid_files.iter().zip(&outcomes) {
+            if !outcomes.iter().all(SummaryOutcome::is_ok) {
+                failed_files.push(file_path.clone());
+            }
+        }
+        file_summaries = Some(summaries.clone());
+
+        if (apply || sidecar) && !diff_only {
             // Zip together the files and their summaries
-            for (file_path, summary) in valid_files.iter().zip(summaries.iter()) {
-                if let Err(e) = write_summary_to_file(file_path, summary, suffix_map.clone()) {
-                    eprintln!("Error writing summary to {}: {}", file_path.display(), e);
+            for ((file_path, summary), outcomes) in valid_files.iter().zip(summaries.iter()).zip(&outcomes) {
+                // Image descriptions are only part of the output, never written into the image,
+                // and errors are never written into a file in place of its summary
+                if (!sidecar && image_media_type(file_path).is_some()) || !outcomes.iter().all(SummaryOutcome::is_ok) {
+                    continue;
+                }
+                let written = if sidecar {
+                    write_summary_to_sidecar(file_path, summary).map(|_| ())
+                } else {
+                    write_summary_to_file(file_path, summary, suffix_map.clone(), summary_position)
+                };
+                if let Err(e) = written {
+                    if !quiet {
+                        eprintln!("Error writing summary to {}: {}", file_path.display(), e);
+                    }
+                    failed_files.push(file_path.clone());
                 }
             }
             
         }
     
         // Use the original valid_files order
-        valid_files.iter().zip(summaries.iter())
-            .map(|(file, summary)| {
-                format!("\nSummary of {}:\n\n{}\n", file.display(), summary)
-            })
-            .collect::<Vec<String>>()
-            .join("")
-    } else if diff_only {
-        valid_files.iter()
-            .filter_map(|file| {
-                let path_string = file.to_string_lossy().into_owned();
-                file_contents.get(&path_string)
-                    .map(|content| format!("\nDiff of {}:\n\n{}\n", file.display(), content))
+        let mut summary_blocks = valid_files.iter().zip(summaries.iter())
+            .map(|(file, summary)| if content_only {
+                summary.clone()
+            } else {
+                format!("\n{}\n\n{}\n", heading(&format!("Summary of {}", file.display()), 2, format), summary)
             })
-            .collect::<Vec<String>>()
-            .join("")
+            .collect::<Vec<String>>();
+
+        // Directory summaries follow the file summaries, each directory before its subdirectories
+        if hierarchical_summary {
+            let valid_file_strings: Vec<String> = valid_files.iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            let file_outcomes: Vec<SummaryOutcome> = outcomes.iter().map(|outcomes| outcomes[0].clone()).collect();
+            let directory_summaries = get_directory_summaries(
+                dir_path,
+                &valid_file_strings,
+                &file_outcomes,
+                get_prompt(prompt_templates, "summary-directory-0.1")?,
+                &summary_options
+            ).await?;
+            // The heading goes with the first directory summary, so it is never dropped on its own
+            directory_blocks = directory_summaries.len();
+            for (i, (directory, outcome)) in directory_summaries.into_iter().enumerate() {
+                // Directories that were skipped had only failed files below them, which are already counted
+                if let SummaryOutcome::Failed(_) = outcome {
+                    failed_files.push(PathBuf::from(&directory));
+                }
+                let summary = outcome.to_string();
+                summary_blocks.push(if content_only {
+                    summary
+                } else {
+                    let section_heading = if i == 0 { format!("\n{}\n", heading("Directory Summaries", 1, format)) } else { String::new() };
+                    format!("{}\n{}\n\n{}\n", section_heading, heading(&format!("Summary of directory {}", directory), 2, format), summary)
+                });
+            }
+        }
+        summary_blocks
     } else {
         valid_files.iter()
             .filter_map(|file| {
-                let path_string = file.to_string_lossy().into_owned();
-                file_contents.get(&path_string)
-                    .map(|content| format!("\nFile Content of {}:\n\n{}\n", file.display(), content))
+                let content = file_contents.get(file.to_string_lossy().as_ref())?;
+                Some(if diff_only { diff_file_block(file, content, &mut failed_files) } else { content_file_block(file, content) })
             })
             .collect::<Vec<String>>()
-            .join("")
     };
 
-    write!(output, "{}", result)?;
-    
-    String::from_utf8(output.into_inner())
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-        .map_err(Into::into)
+    // Each file gets only its own content or summary, so the blocks above with their headings aren't used
+    if let Some(output_dir) = output_dir {
+        for (i, file) in valid_files.iter().enumerate() {
+            let entry = match &file_summaries {
+                Some(summaries) => summaries[i].clone(),
+                None => match file_contents.get(file.to_string_lossy().as_ref()) {
+                    Some(content) if diff_only => format_block(content, "diff", format),
+                    Some(content) if line_numbers => format_block(&number_lines(content), language_hint(file), format),
+                    Some(content) => format_block(content, language_hint(file), format),
+                    None => continue,
+                },
+            };
+            let output_path = mirrored_path(output_dir, file.strip_prefix(dir_path).unwrap_or(file), format);
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            }
+            fs::write(&output_path, entry)
+                .with_context(|| format!("Failed to write {}", output_path.display()))?;
+        }
+        return Ok(ProcessedDirectory { files: valid_files, blocks: Vec::new(), failed_files });
+    }
+
+    // The overview is written from the summaries when there are any, otherwise from the contents or diffs
+    let overview_section = if overview {
+        let valid_file_strings: Vec<String> = valid_files.iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        let contents = match &file_summaries {
+            Some(summaries) => summaries.clone(),
+            None => valid_file_strings.iter()
+                .map(|file| file_contents.get(file).cloned().unwrap_or_default())
+                .collect(),
+        };
+        let overview = get_overview(&valid_file_strings, &contents, get_prompt(prompt_templates, "overview-0.1")?, overview_model.as_deref(), summary_options).await?;
+        format!("{}\n\n{}\n\n", heading("Overview", 1, format), overview.trim_end())
+    } else {
+        String::new()
+    };
+
+    // The structured output is built from the same contents and summaries as the text blocks
+    if format == OutputFormat::Json {
+        // Files that couldn't be read are still listed, only without their size and hash
+        let files: Vec<JsonFile> = valid_files.iter().enumerate()
+            .map(|(i, file)| JsonFile::new(
+                file,
+                file.strip_prefix(dir_path).unwrap_or(file),
+                file_digests.remove(&i),
+                file_contents.get(file.to_string_lossy().as_ref()).cloned(),
+                file_summaries.as_ref().and_then(|summaries: &Vec<String>| summaries.get(i).cloned()),
+            ))
+            .collect();
+        let json_output = JsonOutput {
+            paths: files.iter().map(|file| file.path.clone()).collect(),
+            files,
+        };
+        output.write_block(serde_json::to_string_pretty(&json_output)? + "\n")?;
+        return Ok(ProcessedDirectory { files: valid_files, blocks: Vec::new(), failed_files });
+    }
+
+    let mut fixed_bytes = overview_section.len();
+    if !content_only {
+        for section in section_order {
+            fixed_bytes += match section.as_str() {
+                "paths" => paths_section.len(),
+                "tree" => tree_section.len(),
+                "contents" => contents_heading.len(),
+                _ => 0,
+            };
+        }
+    }
+
+    // Files that don't fit are dropped from the end, the sections around them are always kept
+    let file_blocks = match max_output_bytes {
+        Some(max_bytes) => truncate_blocks(&file_blocks, file_blocks.len() - directory_blocks, max_bytes.saturating_sub(fixed_bytes), max_bytes),
+        None => file_blocks,
+    };
+
+    // A token budget instead drops the largest files, so that as many files as possible make it in
+    let file_blocks = match max_output_tokens {
+        Some(max_tokens) => {
+            let budget = max_tokens.saturating_mul(BYTES_PER_TOKEN);
+            drop_largest_blocks(&file_blocks, budget.saturating_sub(fixed_bytes))
+        },
+        None => file_blocks,
+    };
+
+    // Without any scaffolding, the file texts are only separated by a blank line
+    if content_only {
+        let has_blocks = !file_blocks.is_empty();
+        for (i, block) in file_blocks.into_iter().enumerate() {
+            let separator = if i == 0 { "" } else { "\n\n" };
+            output.write_block(format!("{}{}", separator, block.trim_end_matches('\n')))?;
+        }
+        if has_blocks {
+            output.write_block("\n".to_string())?;
+        }
+        return Ok(ProcessedDirectory { files: valid_files, blocks: Vec::new(), failed_files });
+    }
+
+    if overview {
+        output.write_block(overview_section)?;
+    }
+    // Every section is listed at most once, so the file blocks are moved rather than copied
+    let mut file_blocks = Some(file_blocks);
+    for section in section_order {
+        match section.as_str() {
+            "paths" => output.write_block(paths_section.clone())?,
+            "tree" => output.write_block(tree_section.clone())?,
+            "contents" => {
+                output.write_block(contents_heading.clone())?;
+                for block in file_blocks.take().unwrap_or_default() {
+                    output.write_block(block)?;
+                }
+            },
+            _ => return Err(anyhow::anyhow!("Unknown output section: {}", section)),
+        }
+    }
+
+    Ok(ProcessedDirectory { files: valid_files, blocks: Vec::new(), failed_files })
+}
+
+// The walk order depends on the file system, so files are always sorted; ties are broken by path
+fn sort_files(files: &mut [PathBuf], sort: SortOrder) {
+    match sort {
+        SortOrder::Name => files.sort_by(|a, b| a.file_name().cmp(&b.file_name()).then_with(|| a.cmp(b))),
+        SortOrder::Path => files.sort(),
+        SortOrder::Size => files.sort_by_cached_key(|file| (fs::metadata(file).map(|m| m.len()).unwrap_or(0), file.clone())),
+        SortOrder::Mtime => files.sort_by_cached_key(|file| (fs::metadata(file).and_then(|m| m.modified()).ok(), file.clone())),
+    }
+}
+
+fn suffix_matches(suffix: &str, candidate: &str, case_insensitive: bool) -> bool {
+    if case_insensitive {
+        suffix.eq_ignore_ascii_case(candidate)
+    } else {
+        suffix == candidate
+    }
+}
+
+/// An entry of the include or exclude paths, either a path or a glob like "src/**/*.rs"
+enum PathFilter {
+    Path(PathBuf),
+    Glob(GlobMatcher),
+}
+
+impl PathFilter {
+    fn new(entry: &Path) -> anyhow::Result<PathFilter> {
+        let entry: PathBuf = entry.components()
+            .filter(|c| *c != Component::CurDir)
+            .collect();
+        let pattern = entry.to_string_lossy();
+        if !pattern.contains(['*', '?', '[', '{']) {
+            return Ok(PathFilter::Path(entry));
+        }
+        // Like in gitignore patterns, only "**" matches across directories
+        let glob = GlobBuilder::new(&pattern)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Invalid path pattern '{}'", pattern))?;
+        Ok(PathFilter::Glob(glob.compile_matcher()))
+    }
+
+    // Compared by path components, so "src" covers "src/main.rs" but not "src2/main.rs";
+    // a glob covers the paths it matches and everything below them
+    fn covers(&self, path: &Path) -> bool {
+        match self {
+            PathFilter::Path(prefix) => path.starts_with(prefix),
+            PathFilter::Glob(glob) => path.ancestors().any(|ancestor| glob.is_match(ancestor)),
+        }
+    }
+}
+
+/// Builds a matcher from a file of gitignore-style patterns. Files "ignored" by it are the ones to keep.
+pub fn build_whitelist(dir_path: &str, whitelist_file: &str) -> anyhow::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir_path);
+    if let Some(e) = builder.add(whitelist_file) {
+        return Err(anyhow::anyhow!("Failed to read whitelist file {}: {}", whitelist_file, e));
+    }
+    builder.build().context("Failed to build whitelist patterns")
+}
+
+/// Reads newline-separated paths from a file, or from stdin if `paths_file` is "-"
+pub fn read_paths_file(paths_file: &str) -> io::Result<Vec<PathBuf>> {
+    let contents = if paths_file == "-" {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(paths_file)?
+    };
+
+    // One path per line, skipping blank lines and '#' comments
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
 }
 
 fn check_prefix(s: &str) -> bool {
@@ -249,72 +1298,92 @@ fn get_summaries_from_files(
 
 pub fn filter_dirscribe_sections(content: &str, exclude: bool) -> String {
     let lines: Vec<&str> = content.lines().collect();
-    if lines.is_empty() {
-        return String::new();
-    }
-
-    // Create iterator tuples with (previous, current, next) lines
-    let with_context = (0..lines.len()).map(|i| {
-        let prev = if i > 0 { Some(lines[i - 1]) } else { None };
-        let current = lines[i];
-        let next = if i < lines.len() - 1 { Some(lines[i + 1]) } else { None };
-        (prev, current, next)
-    });
-    let mut line_number = 0;
-    let mut in_dirscribe = false;
-    let filtered_lines: Vec<&str> = with_context
-        .filter(|(prev, _, next)| {
-            line_number += 1;
-
-            if let Some(next_line) = next {
-                if line_number < 3 && next_line.contains("[DIRSCRIBE]") {
-                    in_dirscribe = true;
-                    if exclude {
-                        return false;
-                    } else {
-                        return true
-                    }
-                }
-            }
 
-            if let Some(prev_line) = prev {
-                if in_dirscribe && prev_line.contains("[/DIRSCRIBE]"){
-                    in_dirscribe = false;
-                    if exclude {
-                        return false;
-                    } else {
-                        return true
-                    }
-                }
-            }
+    // A section is kept or removed as a whole, from the line before its [DIRSCRIBE]
+    // marker to the line after its [/DIRSCRIBE] marker
+    let top = top_dirscribe_section(&lines);
+    let bottom = bottom_dirscribe_section(&lines)
+        .filter(|start| top.as_ref().is_none_or(|top| start > top.end()));
+    let in_section = |i: usize| top.as_ref().is_some_and(|top| top.contains(&i)) || bottom.is_some_and(|start| i >= start);
 
-            if exclude {
-                !in_dirscribe
-            } else {
-                in_dirscribe
-            }
-        })
-        .map(|(_, current, _)| current)
+    let filtered_lines: Vec<&str> = lines.iter()
+        .enumerate()
+        .filter(|(i, _)| in_section(*i) != exclude)
+        .map(|(_, line)| *line)
         .collect();
 
     filtered_lines.join("\n")
 }
 
+// Lines of a section at the top of the file. It may follow a shebang, which has to
+// stay the first line, and blank lines.
+fn top_dirscribe_section(lines: &[&str]) -> Option<RangeInclusive<usize>> {
+    let mut start = usize::from(lines.first().is_some_and(|line| line.starts_with("#!")));
+    while lines.get(start).is_some_and(|line| line.trim().is_empty()) {
+        start += 1;
+    }
+    if !lines.get(start + 1)?.contains("[DIRSCRIBE]") {
+        return None;
+    }
+    let end = start + 2 + lines[start + 2..].iter().position(|line| line.contains("[/DIRSCRIBE]"))?;
+    Some(start..=(end + 1).min(lines.len() - 1))
+}
+
+// Start of a section that ends the file, apart from trailing blank lines
+fn bottom_dirscribe_section(lines: &[&str]) -> Option<usize> {
+    let last = lines.iter().rposition(|line| !line.trim().is_empty())?;
+    if last < 1 || !lines[last - 1].contains("[/DIRSCRIBE]") {
+        return None;
+    }
+    let marker = lines[..last - 1].iter().rposition(|line| line.contains("[DIRSCRIBE]"))?;
+    marker.checked_sub(1)
+}
+
 
-fn insert_timestamp(input: &str) -> String {
+// The timestamp goes right above the closing [/DIRSCRIBE] marker and comment line
+fn insert_timestamp(input: &str) -> anyhow::Result<String> {
     let mut lines: Vec<&str> = input.lines().collect();
-    let timestamp = Local::now().to_rfc3339();
+    if lines.len() < 2 {
+        return Err(anyhow::anyhow!("Summary is too short to insert a timestamp, expected at least 2 lines but got {}", lines.len()));
+    }
+    // With line comments the marker is prefixed as well, and so is the timestamp
+    let closing_line = lines[lines.len() - 2];
+    let prefix = closing_line.find("[/DIRSCRIBE]").map_or("", |i| &closing_line[..i]);
+    let timestamp = format!("{}{}", prefix, Local::now().to_rfc3339());
     lines.insert(lines.len() - 2, &timestamp);
-    lines.join("\n")
+    Ok(lines.join("\n"))
 }
 
-pub fn write_summary_to_file(file_path: &Path, summary: &str, suffix_map: HashMap<&'static str, Vec<(&'static str, &'static str)>>) -> anyhow::Result<()> {
+/// Path of the sidecar file a summary of `file_path` is written to, e.g. `src/lib.rs.dirscribe.md`
+pub fn sidecar_path(file_path: &Path) -> PathBuf {
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(SIDECAR_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Writes the summary to a sidecar file next to `file_path`, leaving the file itself untouched
+pub fn write_summary_to_sidecar(file_path: &Path, summary: &str) -> anyhow::Result<PathBuf> {
+    let path = sidecar_path(file_path);
+    fs::write(&path, format!("{}\n", summary.trim()))
+        .with_context(|| format!("Failed to write sidecar file {}", path.display()))?;
+    Ok(path)
+}
+
+pub fn write_summary_to_file(file_path: &Path, summary: &str, suffix_map: CommentMap, position: SummaryPosition) -> anyhow::Result<()> {
     if check_summary(file_path, summary, &suffix_map) | check_prefix(summary) {
-        let content = fs::read_to_string(file_path)?;    
+        let content = strip_bom(fs::read_to_string(file_path)?);
+        // Removes a previous summary wherever it was written
         let processed_content = filter_dirscribe_sections(&content, true);
-        let summary_ts = insert_timestamp(summary);
-        let summary_block = format!("{}\n", summary_ts);
-        let new_content = summary_block + &processed_content;
+        let summary_ts = insert_timestamp(summary)?;
+        let new_content = match position {
+            // A shebang only works on the first line
+            SummaryPosition::Top => match processed_content.split_once('\n') {
+                Some((shebang, rest)) if shebang.starts_with("#!") => format!("{}\n{}\n{}", shebang, summary_ts, rest),
+                _ => format!("{}\n{}", summary_ts, processed_content),
+            },
+            SummaryPosition::Bottom if processed_content.trim().is_empty() => format!("{}\n", summary_ts),
+            SummaryPosition::Bottom => format!("{}\n\n{}\n", processed_content.trim_end(), summary_ts),
+        };
         fs::write(file_path, new_content)?;
         Ok(())
     } else {
@@ -324,19 +1393,155 @@ pub fn write_summary_to_file(file_path: &Path, summary: &str, suffix_map: HashMa
 }
 
 
+/// Pipes the content through an external shell command and returns its stdout.
+/// Falls back to the raw content if the command fails, with a warning unless `quiet` is set.
+fn preprocess_content(cmd: &str, file_path: &Path, content: String, quiet: bool) -> String {
+    let run = || -> io::Result<std::process::Output> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .env("DIRSCRIBE_FILE", file_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Write on a separate thread so a command producing output early can't block on a full pipe
+        let mut stdin = child.stdin.take()
+            .ok_or_else(|| io::Error::other("Failed to open stdin of preprocess command"))?;
+        let input = content.clone().into_bytes();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output()?;
+        match writer.join().map_err(|_| io::Error::other("Failed to write to preprocess command"))? {
+            // A command that reads the file itself may exit without reading its stdin
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe && output.status.success() => {},
+            written => written?,
+        }
+        Ok(output)
+    };
+
+    let warning = match run() {
+        Ok(output) if output.status.success() => match String::from_utf8(output.stdout) {
+            Ok(transformed) => return transformed,
+            Err(_) => format!("preprocess command returned invalid UTF-8 for {}, using raw content", file_path.display()),
+        },
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let details = if stderr.trim().is_empty() { String::new() } else { format!(": {}", stderr.trim()) };
+            format!("preprocess command failed for {} ({}), using raw content{}", file_path.display(), output.status, details)
+        },
+        Err(e) => format!("failed to run preprocess command for {}, using raw content: {}", file_path.display(), e),
+    };
+    if !quiet {
+        eprintln!("{} {}", yellow("Warning:"), warning);
+    }
+    content
+}
+
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(_path: &Path) -> bool {
+    false
+}
+
+/// Removes a leading UTF-8 byte order mark, as written by some Windows editors
+pub fn strip_bom(content: String) -> String {
+    match content.strip_prefix('\u{feff}') {
+        Some(stripped) => stripped.to_string(),
+        None => content,
+    }
+}
+
+/// Strips trailing spaces and tabs from every line, keeping line endings intact
+pub fn trim_trailing_whitespace(content: &str) -> String {
+    content
+        .split('\n')
+        .map(|line| match line.strip_suffix('\r') {
+            Some(line) => format!("{}\r", line.trim_end_matches([' ', '\t'])),
+            None => line.trim_end_matches([' ', '\t']).to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Removes every line that matches any of the patterns, keeping line endings intact
+pub fn strip_matching_lines(content: &str, patterns: &[Regex]) -> String {
+    content
+        .split_inclusive('\n')
+        .filter(|line| {
+            let line = line.trim_end_matches(['\n', '\r']);
+            !patterns.iter().any(|pattern| pattern.is_match(line))
+        })
+        .collect()
+}
+
+/// Re-serializes pretty-printed JSON and YAML files compactly, returning None for
+/// other files and for content that doesn't parse.
+pub fn minify_data(file_path: &Path, content: &str) -> Option<String> {
+    match file_path.extension()?.to_str()?.to_lowercase().as_str() {
+        "json" => {
+            let value: serde_json::Value = serde_json::from_str(content).ok()?;
+            serde_json::to_string(&value).ok()
+        },
+        "yaml" | "yml" => {
+            let value: serde_yaml::Value = serde_yaml::from_str(content).ok()?;
+            // Compact JSON is valid YAML; mappings with non-string keys keep the block style
+            serde_json::to_string(&value).ok()
+                .or_else(|| serde_yaml::to_string(&value).ok())
+        },
+        _ => None,
+    }
+}
+
+fn apply_content_options(file_path: &Path, contents: String, content_options: &ContentOptions) -> String {
+    let contents = if content_options.strip_lines.is_empty() {
+        contents
+    } else {
+        strip_matching_lines(&contents, &content_options.strip_lines)
+    };
+    let contents = match content_options.minify.then(|| minify_data(file_path, &contents)).flatten() {
+        Some(minified) => minified,
+        None => contents,
+    };
+    if content_options.trim_whitespace {
+        trim_trailing_whitespace(&contents)
+    } else {
+        contents
+    }
+}
+
+// A file deleted in the diff no longer exists, so its closest existing ancestor is resolved instead
+fn canonicalize_existing(path: &Path) -> io::Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                return Err(e);
+            };
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            Ok(canonicalize_existing(parent)?.join(name))
+        },
+        canonical => canonical,
+    }
+}
+
 pub fn process_file(
     file_path: &PathBuf,
     diff_only: bool,
     repo: Option<&Repository>,
-    start_commit_id: Option<&str>,
-    end_commit_id: Option<&str>
+    diff_target: &DiffTarget,
+    content_options: &ContentOptions
 ) -> io::Result<String> {
-    let _relative_path = if let Some(repo) = repo {
+    let relative_path = if let Some(repo) = repo {
         let repo_workdir = repo.workdir().ok_or_else(|| {
             io::Error::new(io::ErrorKind::Other, "Could not get repository working directory")
         })?;
         
-        let full_path = fs::canonicalize(file_path)?;
+        let full_path = canonicalize_existing(file_path)?;
         let relative_path = full_path.strip_prefix(fs::canonicalize(repo_workdir)?)
             .map_err(|_| io::Error::new(io::ErrorKind::Other, "File not in repository"))?;
             
@@ -346,48 +1551,12 @@ pub fn process_file(
     };
 
     let contents = if !diff_only {
-        fs::read_to_string(file_path)?
+        apply_content_options(file_path, strip_bom(fs::read_to_string(file_path)?), content_options)
     } else {
         if let Some(repo) = repo {
-            let get_tree = |commit_id: &str| -> io::Result<Tree> {
-                repo.revparse_single(commit_id)
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?
-                    .peel_to_commit()
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?
-                    .tree()
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))
-            };
-
-            let diff = match (start_commit_id, end_commit_id) {
-                (None, None) => {
-                    let head_tree = repo.head()
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?
-                        .peel_to_tree()
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?;
-                    
-                    repo.diff_tree_to_workdir_with_index(Some(&head_tree), None)
-                },
-                (Some(old_id), None) => {
-                    let old_tree = get_tree(old_id)?;
-                    repo.diff_tree_to_workdir_with_index(Some(&old_tree), None)
-                },
-                (Some(old_id), Some(new_id)) => {
-                    let old_tree = get_tree(old_id)?;
-                    let new_tree = get_tree(new_id)?;
-                    repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)
-                },
-                (None, Some(new_id)) => {
-                    let head_tree = repo.head()
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?
-                        .peel_to_tree()
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?;
-                    let new_tree = get_tree(new_id)?;
-                    repo.diff_tree_to_tree(Some(&head_tree), Some(&new_tree), None)
-                }
-            }.map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?;
-
+            let diff = get_diff(repo, diff_target)?;
             let diff_str = get_diff_str(&diff)?;
-            filter_diff_for_file(&diff_str, file_path) // Removed unnecessary semicolon
+            filter_diff_for_file(&diff_str, file_path, &relative_path)
         } else {
             String::new() // Added else branch for when repo is None
         }
@@ -397,41 +1566,183 @@ pub fn process_file(
 }
 
 
-pub fn check_for_keywords(
-    file_path: &PathBuf,
-    or_keywords: &[String],
-    and_keywords: &[String],
-    exclude_keywords: &[String],
-) -> io::Result<bool> {
+/// Outcome of the keyword filters for a single file
+#[derive(Debug, Clone, Default)]
+pub struct KeywordMatch {
+    pub included: bool,
+    pub matched_or: Vec<String>,
+    pub matched_and: Vec<String>,
+    pub matched_exclude: Vec<String>,
+    pub missing_and: Vec<String>,
+}
+
+impl KeywordMatch {
+    /// One-line description of why the file was kept or dropped
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.matched_exclude.is_empty() {
+            parts.push(format!("exclude keywords [{}]", self.matched_exclude.join(", ")));
+        }
+        if !self.matched_or.is_empty() {
+            parts.push(format!("or keywords [{}]", self.matched_or.join(", ")));
+        }
+        if !self.matched_and.is_empty() {
+            parts.push(format!("and keywords [{}]", self.matched_and.join(", ")));
+        }
+        if !self.missing_and.is_empty() {
+            parts.push(format!("missing and keywords [{}]", self.missing_and.join(", ")));
+        }
+        if parts.is_empty() {
+            parts.push("no keywords matched".to_string());
+        }
+        parts.join("; ")
+    }
+}
 
+/// Prints which keywords decided the inclusion of each checked file to stderr
+pub fn report_keyword_matches(keyword_matches: &[(PathBuf, KeywordMatch)]) {
+    eprintln!("{}", yellow("Keyword report:"));
+    for (file_path, keyword_match) in keyword_matches {
+        let status = if keyword_match.included { "included" } else { "excluded" };
+        eprintln!("  {} {}: {}", status, file_path.display(), keyword_match.describe());
+    }
+}
 
-    let contents = fs::read_to_string(file_path)?;
+/// A keyword to look for in file contents, either literal text or a regular expression.
+/// Patterns keep the keyword as it was given, which is what reports show.
+#[derive(Debug, Clone)]
+pub enum Keyword {
+    Literal(String),
+    Pattern(String, Regex),
+}
 
-    // Check exclude keywords - skip if any are present
-    if exclude_keywords.iter().any(|keyword| contents.contains(keyword)) {
-        return Ok(false);
+impl Keyword {
+    /// Case-insensitive keywords are always matched as a regex, literal ones with their text escaped
+    pub fn new(keyword: &str, regex: bool, ignore_case: bool) -> Result<Self, regex::Error> {
+        match (regex, ignore_case) {
+            (false, false) => Ok(Keyword::Literal(keyword.to_string())),
+            (true, _) => Ok(Keyword::Pattern(keyword.to_string(), RegexBuilder::new(keyword).case_insensitive(ignore_case).build()?)),
+            (false, true) => Ok(Keyword::Pattern(keyword.to_string(), RegexBuilder::new(&regex::escape(keyword)).case_insensitive(true).build()?)),
+        }
     }
-    
-    // Check OR keywords - at least one must be present
-    if !or_keywords.is_empty() {
-        let contains_or_keyword = or_keywords.iter().any(|keyword| contents.contains(keyword));
-        if !contains_or_keyword {
-            return Ok(false);
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Keyword::Literal(keyword) | Keyword::Pattern(keyword, _) => keyword,
         }
     }
 
-    // Check AND keywords - all must be present
-    if !and_keywords.is_empty() {
-        let contains_all_keywords = and_keywords.iter().all(|keyword| contents.contains(keyword));
-        if !contains_all_keywords {
-            return Ok(false);
+    fn is_in(&self, contents: &str) -> bool {
+        match self {
+            Keyword::Literal(keyword) => contents.contains(keyword.as_str()),
+            Keyword::Pattern(_, pattern) => pattern.is_match(contents),
         }
     }
+}
+
+/// Splits the values of a keyword flag into keywords. Patterns can contain commas, e.g. in `\w{1,3}`,
+/// so with `regex` every value is a single keyword and the flag is repeated instead.
+pub fn split_keywords(values: &[String], regex: bool) -> Vec<String> {
+    if regex {
+        return values.to_vec();
+    }
+    values.iter()
+        .flat_map(|value| value.split(','))
+        .map(String::from)
+        .collect()
+}
+
+fn compile_keywords(keywords: &[String], regex: bool, ignore_case: bool) -> anyhow::Result<Vec<Keyword>> {
+    keywords.iter()
+        .map(|keyword| Keyword::new(keyword, regex, ignore_case).with_context(|| format!("Invalid keyword pattern '{}'", keyword)))
+        .collect()
+}
 
-    Ok(true)
+/// Counts, for each OR keyword, the files whose match contains it
+pub fn count_keyword_matches(or_keywords: &[Keyword], keyword_matches: &[&KeywordMatch]) -> Vec<(String, usize)> {
+    or_keywords.iter()
+        .map(|keyword| {
+            let count = keyword_matches.iter()
+                .filter(|keyword_match| keyword_match.matched_or.iter().any(|matched| matched == keyword.as_str()))
+                .count();
+            (keyword.as_str().to_string(), count)
+        })
+        .collect()
+}
+
+/// Prints the number of selected files per keyword as a table to stderr
+pub fn report_keyword_stats(counts: &[(String, usize)]) {
+    let width = counts.iter().map(|(keyword, _)| keyword.len()).max().unwrap_or(0);
+    eprintln!("{}", yellow("Keyword stats:"));
+    for (keyword, count) in counts {
+        eprintln!("  {:<width$}  {} {}", keyword, count, if *count == 1 { "file" } else { "files" }, width = width);
+    }
+}
+
+pub fn check_for_keywords(
+    contents: &str,
+    or_keywords: &[Keyword],
+    and_keywords: &[Keyword],
+    exclude_keywords: &[Keyword],
+) -> KeywordMatch {
+    let matching = |keywords: &[Keyword]| -> Vec<String> {
+        keywords.iter()
+            .filter(|keyword| keyword.is_in(contents))
+            .map(|keyword| keyword.as_str().to_string())
+            .collect()
+    };
+
+    let matched_exclude = matching(exclude_keywords);
+    let matched_or = matching(or_keywords);
+    let matched_and = matching(and_keywords);
+    let missing_and: Vec<String> = and_keywords.iter()
+        .map(|keyword| keyword.as_str().to_string())
+        .filter(|keyword| !matched_and.contains(keyword))
+        .collect();
+
+    // Exclude keywords skip the file, at least one OR keyword and all AND keywords must be present
+    let included = matched_exclude.is_empty()
+        && (or_keywords.is_empty() || !matched_or.is_empty())
+        && missing_and.is_empty();
+
+    KeywordMatch {
+        included,
+        matched_or,
+        matched_and,
+        matched_exclude,
+        missing_and,
+    }
+}
+
+const MAX_IMAGE_BYTES: u64 = 5_000_000;
+
+/// Returns the media type of image files that can be sent to multimodal providers
+pub fn image_media_type(path: &Path) -> Option<&'static str> {
+    match path.extension().and_then(|e| e.to_str())?.to_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+fn read_image_base64(file_path: &Path) -> io::Result<String> {
+    let size = fs::metadata(file_path)?.len();
+    if size > MAX_IMAGE_BYTES {
+        return Err(io::Error::other(
+            format!("Image is too large to summarize ({} bytes, max {} bytes)", size, MAX_IMAGE_BYTES)
+        ));
+    }
+    Ok(BASE64.encode(fs::read(file_path)?))
+}
+
+const MINIFIED_LINE_LENGTH: usize = 5000;
+
+/// Minified files are recognized by a line longer than any hand-written code would have
+fn is_minified(bytes: &[u8]) -> bool {
+    bytes.split(|&b| b == b'\n').any(|line| line.len() > MINIFIED_LINE_LENGTH)
 }
 
-// Add this function at the top of file_processing.rs
 fn is_likely_text_file(path: &Path) -> bool {
     // Common text file extensions
     const TEXT_EXTENSIONS: &[&str] = &[
@@ -470,31 +1781,132 @@ fn is_likely_text_file(path: &Path) -> bool {
         }
     }
 
-    // For files without extension or unknown extensions, try to read a small sample
-    // and check if it contains only valid UTF-8 text
+    // For files without extension or unknown extensions, check a sample of the contents
     if let Ok(file) = std::fs::File::open(path) {
         use std::io::Read;
-        let mut buffer = [0u8; 1024];
-        let mut handle = file;
-        
-        // Read first 1024 bytes
-        if handle.read(&mut buffer).is_ok() {
-            // Check if content is valid UTF-8
-            return String::from_utf8(buffer.to_vec()).is_ok();
+        let mut sample = Vec::with_capacity(TEXT_SAMPLE_BYTES);
+        if file.take(TEXT_SAMPLE_BYTES as u64).read_to_end(&mut sample).is_ok() {
+            return is_text_sample(&sample);
         }
     }
 
     false
 }
 
+// Like git, files with a null byte in their first 8000 bytes are treated as binary
+const TEXT_SAMPLE_BYTES: usize = 8000;
 
+// A sample is text if it has no null bytes and is UTF-8, where a character cut off at the end of the sample is fine
+fn is_text_sample(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
 
-fn create_comment_map() -> HashMap<&'static str, Vec<(&'static str, &'static str)>> {
+
+
+/// Returns the language tag used for markdown code fences, or an empty string for unknown file types
+pub fn language_hint(path: &Path) -> &'static str {
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    match file_name {
+        "Dockerfile" | "Containerfile" => return "dockerfile",
+        "Makefile" | "makefile" => return "makefile",
+        "Jenkinsfile" => return "groovy",
+        "CMakeLists.txt" => return "cmake",
+        _ => {}
+    }
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "as" => "actionscript",
+        "ada" | "adb" | "ads" => "ada",
+        "scpt" | "applescript" => "applescript",
+        "asm" | "s" => "asm",
+        "awk" => "awk",
+        "sh" | "bash" => "bash",
+        "zsh" => "zsh",
+        "fish" => "fish",
+        "c" | "h" => "c",
+        "cs" => "csharp",
+        "cpp" | "hpp" | "cc" | "hh" | "cxx" | "hxx" => "cpp",
+        "cob" | "cbl" => "cobol",
+        "coffee" => "coffeescript",
+        "css" => "css",
+        "scss" => "scss",
+        "sass" => "sass",
+        "less" => "less",
+        "d" => "d",
+        "dart" => "dart",
+        "pas" | "dpr" => "pascal",
+        "ex" | "exs" => "elixir",
+        "erl" | "hrl" => "erlang",
+        "fs" | "fsx" => "fsharp",
+        "go" => "go",
+        "groovy" | "gvy" | "gradle" => "groovy",
+        "hs" | "lhs" => "haskell",
+        "html" | "htm" => "html",
+        "xml" | "xsl" | "xsd" | "svg" => "xml",
+        "java" => "java",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "jl" => "julia",
+        "kt" | "kts" => "kotlin",
+        "lisp" | "lsp" | "cl" => "lisp",
+        "lua" => "lua",
+        "m" | "mat" => "matlab",
+        "mm" => "objectivec",
+        "ml" | "mli" => "ocaml",
+        "pl" | "pm" => "perl",
+        "php" => "php",
+        "ps1" | "psm1" | "psd1" => "powershell",
+        "py" | "pyw" => "python",
+        "r" => "r",
+        "rb" | "rbw" => "ruby",
+        "rs" => "rust",
+        "scala" | "sc" => "scala",
+        "sql" => "sql",
+        "swift" => "swift",
+        "ts" => "typescript",
+        "tsx" => "tsx",
+        "vb" => "vbnet",
+        "tf" | "tfvars" | "hcl" => "hcl",
+        "yaml" | "yml" | "sls" => "yaml",
+        "ini" | "cfg" => "ini",
+        "conf" | "nginx" | "htaccess" => "conf",
+        "properties" | "prop" => "properties",
+        "json" => "json",
+        "jsonnet" | "libsonnet" => "jsonnet",
+        "pp" | "puppet" => "puppet",
+        "bicep" => "bicep",
+        "toml" => "toml",
+        "md" | "markdown" => "markdown",
+        "rst" => "rst",
+        "tex" => "latex",
+        "proto" => "protobuf",
+        "graphql" | "gql" => "graphql",
+        "vue" => "vue",
+        "svelte" => "svelte",
+        "dockerfile" | "containerfile" => "dockerfile",
+        "cmake" => "cmake",
+        "make" | "mak" => "makefile",
+        "diff" | "patch" => "diff",
+        _ => "",
+    }
+}
+
+pub fn create_comment_map() -> CommentMap {
     let mut map = HashMap::new();
     
     // Helper function to insert comment styles
     let mut insert = |ext: &'static str, comments: Vec<(&'static str, &'static str)>| {
-        map.insert(ext, comments);
+        let comments = comments.into_iter()
+            .map(|(start, end)| (Cow::Borrowed(start), Cow::Borrowed(end)))
+            .collect();
+        map.insert(Cow::Borrowed(ext), comments);
     };
 
     // ActionScript
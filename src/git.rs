@@ -1,14 +1,37 @@
+use std::collections::HashMap;
 use std::io;
 use std::path::{Path, PathBuf};
-use git2::{Repository, Tree, Diff, DiffFormat};
+use git2::{Repository, Tree, Diff, DiffFindOptions, DiffFormat, DiffOptions, Patch, Sort};
 
-pub fn get_diff_list(
-    repo: &Repository,
-    start_commit_id: Option<&str>,
-    end_commit_id: Option<&str>,
-) -> io::Result<Vec<PathBuf>> {
-    let mut diff_list = Vec::new();
-    
+/// Which changes a diff covers; without commit ids HEAD is compared with the working directory
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiffTarget<'a> {
+    pub start_commit_id: Option<&'a str>,
+    pub end_commit_id: Option<&'a str>,
+    /// Compare HEAD with the index instead of the working directory
+    pub staged: bool,
+    /// Show untracked files as entirely added in diffs against the working directory
+    pub include_untracked: bool,
+    /// Leave out changes that only touch whitespace
+    pub ignore_whitespace: bool,
+}
+
+impl DiffTarget<'_> {
+    // The untracked options only have an effect on diffs against the working directory
+    fn diff_options(&self) -> DiffOptions {
+        let mut options = DiffOptions::new();
+        options
+            .include_untracked(self.include_untracked)
+            .recurse_untracked_dirs(self.include_untracked)
+            .show_untracked_content(self.include_untracked)
+            .ignore_whitespace(self.ignore_whitespace)
+            .ignore_whitespace_change(self.ignore_whitespace)
+            .ignore_whitespace_eol(self.ignore_whitespace);
+        options
+    }
+}
+
+pub fn get_diff<'r>(repo: &'r Repository, target: &DiffTarget) -> io::Result<Diff<'r>> {
     // Helper function to get tree from commit ID
     let get_tree = |commit_id: &str| -> io::Result<Tree> {
         repo.revparse_single(commit_id)
@@ -19,26 +42,29 @@ pub fn get_diff_list(
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))
     };
 
-    // Get the diff based on provided arguments
-    let diff = match (start_commit_id, end_commit_id) {
-        // Both None: compare working directory with HEAD
+    let mut diff = match (target.start_commit_id, target.end_commit_id) {
+        // Both None: compare working directory with HEAD, or only the index when staged
         (None, None) => {
             let head_tree = repo.head()
                 .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?
                 .peel_to_tree()
                 .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?;
             
-            repo.diff_tree_to_workdir_with_index(
-                Some(&head_tree),
-                None
-            )
+            if target.staged {
+                repo.diff_tree_to_index(Some(&head_tree), None, Some(&mut target.diff_options()))
+            } else {
+                repo.diff_tree_to_workdir_with_index(
+                    Some(&head_tree),
+                    Some(&mut target.diff_options())
+                )
+            }
         },
         // Only old_commit provided: compare that commit with working directory
         (Some(old_id), None) => {
             let old_tree = get_tree(old_id)?;
             repo.diff_tree_to_workdir_with_index(
                 Some(&old_tree),
-                None
+                Some(&mut target.diff_options())
             )
         },
         // Both provided: compare the two commits directly
@@ -48,7 +74,7 @@ pub fn get_diff_list(
             repo.diff_tree_to_tree(
                 Some(&old_tree),
                 Some(&new_tree),
-                None
+                Some(&mut target.diff_options())
             )
         },
         // Invalid case: old None but new Some - treat as comparing HEAD to new commit
@@ -61,34 +87,161 @@ pub fn get_diff_list(
             repo.diff_tree_to_tree(
                 Some(&head_tree),
                 Some(&new_tree),
-                None
+                Some(&mut target.diff_options())
             )
         }
     }.map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?;
+
+    // Without this a renamed file shows up as a deletion plus an addition
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+        .map_err(|e| io::Error::other(e.message().to_string()))?;
+    Ok(diff)
+}
+
+pub fn get_diff_list(repo: &Repository, target: &DiffTarget) -> io::Result<Vec<PathBuf>> {
+    let mut diff_list = Vec::new();
+    let diff = get_diff(repo, target)?;
     
-    // Collect changed files
-    diff.foreach(
-        &mut |delta, _| {
-            if let Some(new_file) = delta.new_file().path() {
-                diff_list.push(new_file.to_path_buf());
+    // Collect changed files; with whitespace ignored, a file can be changed but have no hunks left
+    for (i, delta) in diff.deltas().enumerate() {
+        if let Some(new_file) = delta.new_file().path() {
+            if target.ignore_whitespace {
+                let patch = Patch::from_diff(&diff, i).map_err(|e| io::Error::other(e.message().to_string()))?;
+                if patch.is_some_and(|patch| patch.num_hunks() == 0) {
+                    continue;
+                }
             }
-            true
-        },
-        None,
-        None,
-        None,
-    ).map_err(|e| io::Error::new(io::ErrorKind::Other, e.message().to_string()))?;
+            diff_list.push(new_file.to_path_buf());
+        }
+    }
     
     Ok(diff_list)
 }
 
+/// Walks the commits after `start_commit_id` up to `end_commit_id` (or HEAD) and maps
+/// every path they touched to the messages of those commits, oldest first.
+pub fn get_commit_messages_by_path(
+    repo: &Repository,
+    start_commit_id: Option<&str>,
+    end_commit_id: Option<&str>,
+) -> io::Result<HashMap<PathBuf, Vec<String>>> {
+    let git_err = |e: git2::Error| io::Error::other(e.message().to_string());
+    let mut messages: HashMap<PathBuf, Vec<String>> = HashMap::new();
+
+    let mut revwalk = repo.revwalk().map_err(git_err)?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE).map_err(git_err)?;
+    match end_commit_id {
+        Some(end_id) => {
+            let end_commit = repo.revparse_single(end_id).map_err(git_err)?.peel_to_commit().map_err(git_err)?;
+            revwalk.push(end_commit.id()).map_err(git_err)?;
+        },
+        None => revwalk.push_head().map_err(git_err)?,
+    }
+    if let Some(start_id) = start_commit_id {
+        let start_commit = repo.revparse_single(start_id).map_err(git_err)?.peel_to_commit().map_err(git_err)?;
+        revwalk.hide(start_commit.id()).map_err(git_err)?;
+    }
+
+    for oid in revwalk {
+        let commit = repo.find_commit(oid.map_err(git_err)?).map_err(git_err)?;
+        let message = commit.message().unwrap_or_default().trim().to_string();
+        let tree = commit.tree().map_err(git_err)?;
+        let parent_tree = match commit.parents().next() {
+            Some(parent) => Some(parent.tree().map_err(git_err)?),
+            None => None,
+        };
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None).map_err(git_err)?;
+
+        for delta in diff.deltas() {
+            if let Some(path) = delta.new_file().path() {
+                messages.entry(path.to_path_buf()).or_default().push(message.clone());
+            }
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Lists the commits after `start_commit_id` up to `end_commit_id` (or HEAD), oldest first,
+/// as their abbreviated id and the first line of their message
+pub fn get_commits_in_range(
+    repo: &Repository,
+    start_commit_id: &str,
+    end_commit_id: Option<&str>,
+) -> io::Result<Vec<(String, String)>> {
+    let git_err = |e: git2::Error| io::Error::other(e.message().to_string());
+
+    let mut revwalk = repo.revwalk().map_err(git_err)?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE).map_err(git_err)?;
+    revwalk.push_range(&format!("{}..{}", start_commit_id, end_commit_id.unwrap_or("HEAD"))).map_err(git_err)?;
+
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid.map_err(git_err)?).map_err(git_err)?;
+        let short_id = commit.as_object().short_id().map_err(git_err)?;
+        commits.push((
+            short_id.as_str().unwrap_or_default().to_string(),
+            commit.summary().unwrap_or_default().to_string(),
+        ));
+    }
+    Ok(commits)
+}
+
+pub fn format_commit_messages(messages: &[String]) -> String {
+    let mut formatted = String::from("Commit messages:\n");
+    for message in messages {
+        // Continuation lines of multi-line messages are indented under their bullet
+        formatted.push_str(&format!("- {}\n", message.replace('\n', "\n  ")));
+    }
+    formatted
+}
+
+/// Returns the id of the commit where HEAD branched off `base`, e.g. the start of a pull request
+pub fn get_merge_base(repo: &Repository, base: &str) -> io::Result<String> {
+    let git_err = |e: git2::Error| io::Error::other(e.message().to_string());
+    let base_commit = repo.revparse_single(base).map_err(git_err)?.peel_to_commit().map_err(git_err)?;
+    let head_commit = repo.head().map_err(git_err)?.peel_to_commit().map_err(git_err)?;
+    let merge_base = repo.merge_base(base_commit.id(), head_commit.id()).map_err(git_err)?;
+    Ok(merge_base.to_string())
+}
+
+/// Finds the most recent tag reachable from HEAD, ignoring tags on HEAD itself so a
+/// freshly tagged release is compared to the one before it. Returns the tag name and commit id.
+pub fn get_latest_tag(repo: &Repository) -> io::Result<Option<(String, String)>> {
+    let git_err = |e: git2::Error| io::Error::other(e.message().to_string());
+    let head = repo.head().map_err(git_err)?.peel_to_commit().map_err(git_err)?;
+
+    let mut latest: Option<(String, git2::Commit)> = None;
+    for name in repo.tag_names(None).map_err(git_err)?.iter().flatten() {
+        let commit = match repo.revparse_single(name).and_then(|obj| obj.peel_to_commit()) {
+            Ok(commit) => commit,
+            Err(_) => continue,
+        };
+        if commit.id() == head.id() || !repo.graph_descendant_of(head.id(), commit.id()).map_err(git_err)? {
+            continue;
+        }
+        let is_later = match &latest {
+            None => true,
+            Some((_, current)) => repo.graph_descendant_of(commit.id(), current.id()).map_err(git_err)?
+                || (!repo.graph_descendant_of(current.id(), commit.id()).map_err(git_err)?
+                    && commit.time() > current.time()),
+        };
+        if is_later {
+            latest = Some((name.to_string(), commit));
+        }
+    }
+
+    Ok(latest.map(|(name, commit)| (name, commit.id().to_string())))
+}
+
 pub fn get_diff_str(diff: &Diff) -> io::Result<String> {
     let mut diff_output = Vec::new();
     
     diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
         let origin = line.origin();
         
-        // For header lines (not +, -, or space), just add the content
+        // For header lines (not +, -, or space), just add the content; this includes the
+        // "Binary files ... differ" marker libgit2 emits with origin 'B' in place of hunks
         if origin != '+' && origin != '-' && origin != ' ' {
             diff_output.extend_from_slice(line.content());
         } else {
@@ -109,30 +262,137 @@ pub fn get_diff_str(diff: &Diff) -> io::Result<String> {
     Ok(output)
 }
 
-pub fn filter_diff_for_file(diff_str: &str, file_path: &Path) -> String {
+/// Returns true for the line git emits in place of hunks when a binary file changed
+pub fn is_binary_diff_marker(line: &str) -> bool {
+    (line.starts_with("Binary files ") && line.ends_with(" differ")) || line == "GIT binary patch"
+}
+
+/// Returns the section of a diff that belongs to the file at `repo_path`, relative to the
+/// repository root. Sections are matched on the full paths in their `diff --git` header, so
+/// `old_config.rs` doesn't pick up the diff of `config.rs`.
+pub fn filter_diff_for_file(diff_str: &str, file_path: &Path, repo_path: &Path) -> String {
     let lines: Vec<&str> = diff_str.lines().collect();
     let mut result = Vec::new();
+    let binary_marker = format!("Binary file changed: {}", file_path.display());
     let mut current_file_section = false;
-    // Get just the filename component
-    let file_name = file_path.file_name()
-        .map(|s| s.to_string_lossy())
-        .unwrap_or_default();
+    let mut renamed_from = None;
+    // Git always separates path components with '/'
+    let repo_path = repo_path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let old_side = format!("diff --git a/{} b/", repo_path);
+    let new_side = format!(" b/{}", repo_path);
 
     for line in lines {
         if line.starts_with("diff --git") {
-            // Check if this section is for our file
-            current_file_section = line.contains(&*file_name);
+            // Check if this section is for our file, on either side of a rename
+            current_file_section = line.starts_with(&old_side) || line.ends_with(&new_side);
             if current_file_section {
-                result.push(line);
+                result.push(line.to_string());
             }
         } else if current_file_section {
-            // Keep adding lines until we hit the next diff section
-            if line.starts_with("diff --git") {
-                break;
+            if is_binary_diff_marker(line) {
+                result.push(binary_marker.clone());
+            } else if let Some(old_path) = line.strip_prefix("rename from ") {
+                // The "rename from" and "rename to" lines are folded into one
+                renamed_from = Some(old_path);
+            } else if let (Some(new_path), Some(old_path)) = (line.strip_prefix("rename to "), renamed_from.take()) {
+                result.push(format!("renamed: {} -> {}", old_path, new_path));
+            } else {
+                result.push(line.to_string());
             }
-            result.push(line);
         }
     }
 
     result.join("\n")
+}
+
+/// Counts the inserted and deleted lines of every file in the diff, keyed by path like [`get_diff_list`].
+/// Binary files count as having no changed lines.
+pub fn get_diff_stats(repo: &Repository, target: &DiffTarget) -> io::Result<HashMap<PathBuf, (usize, usize)>> {
+    let git_err = |e: git2::Error| io::Error::other(e.message().to_string());
+    let diff = get_diff(repo, target)?;
+    let mut stats = HashMap::new();
+
+    for (i, delta) in diff.deltas().enumerate() {
+        let Some(path) = delta.new_file().path() else {
+            continue;
+        };
+        let counts = match Patch::from_diff(&diff, i).map_err(git_err)? {
+            Some(patch) => {
+                let (_, insertions, deletions) = patch.line_stats().map_err(git_err)?;
+                (insertions, deletions)
+            },
+            None => (0, 0),
+        };
+        stats.insert(path.to_path_buf(), counts);
+    }
+
+    Ok(stats)
+}
+
+/// Formats line counts the way `git diff --stat` does, e.g. "3 insertions(+), 1 deletion(-)"
+pub fn format_change_counts(insertions: usize, deletions: usize) -> String {
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    format!("{} insertion{}(+), {} deletion{}(-)", insertions, plural(insertions), deletions, plural(deletions))
+}
+
+/// Parses the `@@ -a,b +c,d @@` hunk headers of a file diff and returns the
+/// changed line ranges on the new side, merging overlapping or adjacent ranges.
+pub fn get_diff_line_ranges(file_diff: &str) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for line in file_diff.lines().filter(|l| l.starts_with("@@")) {
+        let new_side = match line.split_whitespace().find(|part| part.starts_with('+')) {
+            Some(part) => &part[1..],
+            None => continue,
+        };
+        let mut parts = new_side.splitn(2, ',');
+        let start: usize = match parts.next().and_then(|p| p.parse().ok()) {
+            Some(start) => start,
+            None => continue,
+        };
+        let count: usize = parts.next().and_then(|p| p.parse().ok()).unwrap_or(1);
+        // A hunk at line 0 leaves no lines at all, there is no position to report
+        if start == 0 {
+            continue;
+        }
+        // Pure deletions have no lines on the new side; report the position they happened at
+        let end = if count == 0 { start } else { start + count - 1 };
+
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+}
+
+/// The changed lines of a file diff as shown by --diff-line-ranges. A file that was deleted,
+/// or had all its lines removed, has an empty new side (`+0,0`) and is reported as deleted.
+pub fn describe_changed_lines(file_diff: &str) -> String {
+    let emptied = file_diff.lines()
+        .filter(|l| l.starts_with("@@"))
+        .any(|l| l.split_whitespace().any(|part| part == "+0,0"));
+    if emptied {
+        return "Changed lines: deleted".to_string();
+    }
+    format_diff_line_ranges(&get_diff_line_ranges(file_diff))
+}
+
+pub fn format_diff_line_ranges(ranges: &[(usize, usize)]) -> String {
+    if ranges.is_empty() {
+        return "Changed lines: none".to_string();
+    }
+
+    let formatted: Vec<String> = ranges.iter()
+        .map(|(start, end)| if start == end {
+            start.to_string()
+        } else {
+            format!("{}-{}", start, end)
+        })
+        .collect();
+    format!("Changed lines: {}", formatted.join(", "))
 }
\ No newline at end of file
@@ -0,0 +1,39 @@
+//! Combines the contents of files in a directory into a single text, optionally
+//! replacing them with summaries generated by an LLM provider.
+//!
+//! The `dirscribe` binary is a thin layer over this library, which can be used to
+//! run dirscribe from other tools without shelling out:
+//!
+//! ```
+//! use dirscribe::{process_directory, ProcessOptions};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let dir = std::env::temp_dir().join("dirscribe-doctest");
+//! std::fs::create_dir_all(&dir)?;
+//! std::fs::write(dir.join("main.rs"), "fn main() {}\n")?;
+//!
+//! let options = ProcessOptions {
+//!     suffixes: vec!["rs".to_string()],
+//!     ..Default::default()
+//! };
+//! let blocks = process_directory(dir.to_str().unwrap(), &options).await?;
+//! assert!(blocks.concat().contains("fn main() {}"));
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok(())
+//! # }
+//! ```
+pub mod cache;
+pub mod cli;
+pub mod color;
+pub mod config;
+pub mod git;
+mod duplicates;
+pub mod file_processing;
+pub mod logging;
+pub mod output;
+pub mod prompt_handling;
+pub mod summary;
+pub mod validation;
+
+pub use file_processing::{process_directory, process_directory_into, process_directory_with_files, ProcessedDirectory, ProcessOptions};
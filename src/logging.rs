@@ -0,0 +1,21 @@
+use tracing_subscriber::EnvFilter;
+
+/// Sets up diagnostic logs on stderr. With `verbosity` from `--verbose` the level is debug,
+/// or trace when given twice. Otherwise it is read from `DIRSCRIBE_LOG`, falling back to
+/// `RUST_LOG`, and defaults to warnings only.
+pub fn init_logging(ansi: bool, verbosity: u8) {
+    let filter = match verbosity {
+        0 => EnvFilter::try_from_env("DIRSCRIBE_LOG")
+            .or_else(|_| EnvFilter::try_from_default_env())
+            .unwrap_or_else(|_| EnvFilter::new("warn")),
+        1 => EnvFilter::new("warn,dirscribe=debug"),
+        _ => EnvFilter::new("warn,dirscribe=trace"),
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .with_ansi(ansi)
+        .init();
+}
@@ -1,41 +1,90 @@
 use std::fs::File;
-mod cli;
-mod git;
-mod file_processing;
-mod output;
-mod prompt_handling;
-mod summary; 
-mod validation;
-use cli::Cli;
-use file_processing::process_directory;
-use output::{write_to_clipboard, process_with_template};
-use clap::Parser;
-use validation::validate_cli_args;
+use dirscribe::cli::Cli;
+use dirscribe::config::load_config;
+use dirscribe::color::{init_color, color_enabled, ColorChoice, green, red, yellow};
+use dirscribe::file_processing::{create_comment_map, CommentStyle, read_paths_file, build_whitelist, split_keywords, ContentOptions};
+use dirscribe::output::{write_to_clipboard, process_with_template, template_file_list, BlockWriter, TemplateValues, TemplateWriter, parse_size, split_into_chunks, chunk_budget, chunk_path, json_output_schema};
+use dirscribe::{process_directory_into, process_directory_with_files, ProcessedDirectory, ProcessOptions};
+use clap::{CommandFactory, FromArgMatches};
+use dirscribe::validation::validate_cli_args;
 use anyhow::{Result, Context};
-use std::io::Write;
+use std::io::{BufWriter, IsTerminal, Read, Write};
 use std::path::PathBuf;
-use prompt_handling::load_prompts;
+use dirscribe::prompt_handling::{get_prompt, load_prompts};
+use dirscribe::logging::init_logging;
+use dirscribe::summary::{get_summaries, check_models, describe_provider_config, concurrent_requests, ProviderSpec, SummaryOptions};
+use serde_json::json;
+use std::str::FromStr;
+use std::collections::HashMap;
+use dirscribe::git::{get_merge_base, get_latest_tag};
+use git2::Repository;
+use regex::Regex;
 
 
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    init_color(if cli.no_color { ColorChoice::Never } else { cli.color });
+    init_logging(color_enabled(), cli.verbose);
 
+    if cli.print_schema {
+        println!("{}", json_output_schema()?);
+        return Ok(());
+    }
+
+    // Variables from .env fill in what isn't set in the environment, before any provider reads them
+    if let Err(e) = dotenvy::from_path(".env") {
+        if !e.not_found() && !cli.quiet {
+            eprintln!("{} failed to load .env: {}", yellow("Warning:"), e);
+        }
+    }
+
+    match load_config(cli.config.as_deref()) {
+        Ok(Some(config)) => config.apply(&mut cli, &matches),
+        Ok(None) => {},
+        Err(e) => {
+            eprintln!("{} {:#}", red("Error:"), e);
+            std::process::exit(1);
+        }
+    }
+
+    let suffixes_arg = cli.suffixes.clone().unwrap_or_default();
     assert!(
-        cli.suffixes == "*" || !cli.suffixes.chars().any(|s| s == '*'),
+        suffixes_arg == "*" || !suffixes_arg.chars().any(|s| s == '*'),
         "\"*\" can only be used alone, file extensions are specified without wildcard, like 'py,toml,js'"
     );
 
+    // Pull request and release modes are diff-only processing from a derived start commit
+    if cli.pr_base.is_some() || cli.since_tag {
+        cli.diff_only = true;
+    }
+
     if let Err(e) = validate_cli_args(&cli) {
-        eprintln!("Error: {}", e);
+        eprintln!("{} {}", red("Error:"), e);
         std::process::exit(1);
     }
 
+    if let Some(pr_base) = &cli.pr_base {
+        let repo = Repository::open(".").context("Failed to open git repository")?;
+        cli.start_commit_id = Some(get_merge_base(&repo, pr_base).context("Failed to find merge base")?);
+    }
+
+    if cli.since_tag {
+        let repo = Repository::open(".").context("Failed to open git repository")?;
+        if let Some((tag, commit_id)) = get_latest_tag(&repo).context("Failed to find latest tag")? {
+            if !cli.quiet {
+                eprintln!("Processing changes since tag {}", tag);
+            }
+            cli.start_commit_id = Some(commit_id);
+        }
+    }
+
     
-    let suffixes: Vec<String> = cli.suffixes.split(',').map(String::from).collect();
+    let suffixes: Vec<String> = suffixes_arg.split(',').map(String::from).collect();
     
-    let exclude_paths: Vec<PathBuf> = match cli.exclude_paths {
+    let mut exclude_paths: Vec<PathBuf> = match cli.exclude_paths {
         Some(s) => {
             if s.contains(',') {
                 s.split(',').map(PathBuf::from).collect()
@@ -46,7 +95,7 @@ async fn main() -> Result<()> {
         None => Vec::new()
     };
 
-    let include_paths: Vec<PathBuf> = match cli.include_paths {
+    let mut include_paths: Vec<PathBuf> = match cli.include_paths {
         Some(s) => {
             if s.contains(',') {
                 s.split(',').map(PathBuf::from).collect()
@@ -57,67 +106,342 @@ async fn main() -> Result<()> {
         None => Vec::new()
     };
 
-    let or_keywords: Vec<String> = cli.or_keywords
-        .map(|s| if s.contains(',') {
-            s.split(',').map(String::from).collect()
-        } else {
-            vec![s]
-        })
-        .unwrap_or_default();
+    if let Some(exclude_paths_file) = &cli.exclude_paths_file {
+        exclude_paths.extend(read_paths_file(exclude_paths_file).context("Failed to read exclude paths file")?);
+    }
 
-    let and_keywords: Vec<String> = cli.and_keywords
-        .map(|s| if s.contains(',') {
-            s.split(',').map(String::from).collect()
-        } else {
-            vec![s]
-        })
+    if let Some(include_paths_file) = &cli.include_paths_file {
+        include_paths.extend(read_paths_file(include_paths_file).context("Failed to read include paths file")?);
+    }
+
+    let whitelist = match &cli.only_matching_file {
+        Some(only_matching_file) => Some(build_whitelist(".", only_matching_file)?),
+        None => None
+    };
+
+    let exclude_dirs: Vec<String> = cli.exclude_dirs
+        .map(|s| s.split(',').map(String::from).collect())
         .unwrap_or_default();
 
-    let exclude_keywords: Vec<String> = cli.exclude_keywords
-        .map(|s| if s.contains(',') {
-            s.split(',').map(String::from).collect()
-        } else {
-            vec![s]
-        })
+    let exclude_extensions: Vec<String> = cli.exclude_extensions
+        .map(|s| s.split(',').map(String::from).collect())
         .unwrap_or_default();
 
+    let or_keywords = split_keywords(&cli.or_keywords, cli.regex_keywords);
+
+    let and_keywords = split_keywords(&cli.and_keywords, cli.regex_keywords);
+
+    let exclude_keywords = split_keywords(&cli.exclude_keywords, cli.regex_keywords);
+
+    // Patterns were checked by validation, so compiling them again can't fail
+    let content_options = ContentOptions {
+        trim_whitespace: cli.trim_trailing_whitespace,
+        minify: cli.minify_data,
+        strip_lines: cli.strip_lines.iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?,
+    };
+
     // Read the file contents into a String
     let summarize_prompt_templates = load_prompts("prompts").context("Failed to load prompt templates")?;
-    // Process directory and get the content string
-    let content = process_directory(
-        ".",
-        &suffixes,
-        cli.dont_use_gitignore,
-        cli.summarize,
-        cli.summarize_keywords,
-        summarize_prompt_templates,
-        cli.apply,
-        cli.retrieve,
-        cli.diff_only,
-        &exclude_paths,
-        &include_paths,
-        &or_keywords,
-        &and_keywords,
-        &exclude_keywords,
-        cli.start_commit_id.as_deref(),
-        cli.end_commit_id.as_deref()
-    ).await?;
-
-    let final_content = if let Some(template_path) = cli.prompt_template_path {
-        process_with_template(&content, &template_path)?
-    } else {
-        content
+    let section_order: Vec<String> = cli.section_order
+        .split(',')
+        .map(String::from)
+        .collect();
+
+    // Specs were checked by validation
+    let compare_providers: Vec<ProviderSpec> = cli.compare_providers.as_deref()
+        .map(|s| s.split(',').map(ProviderSpec::from_str).collect::<Result<_>>())
+        .transpose()?
+        .unwrap_or_default();
+
+    let stream_responses = std::env::var("DIRSCRIBE_STREAM").is_ok_and(|v| v == "1") && std::io::stderr().is_terminal();
+    let summary_options = SummaryOptions {
+        per_file_timeout: cli.per_file_timeout,
+        resume_file: cli.resume_file.clone(),
+        strict: cli.strict,
+        refusal_patterns: cli.refusal_patterns.as_deref()
+            .map(|s| s.split(',').map(String::from).collect())
+            .unwrap_or_default(),
+        fallback_provider: cli.fallback_provider.clone(),
+        temperature: cli.temperature,
+        max_tokens: cli.max_tokens,
+        concurrency: Some(concurrent_requests(cli.concurrency)),
+        no_cache: cli.no_cache,
+        refresh_cache: cli.refresh_cache,
+        // Streamed responses take the place of the progress bar
+        stream: stream_responses,
+        show_usage: !cli.quiet,
+        show_cost: cli.show_cost,
+        plain_summaries: cli.sidecar,
+        provider_spec: cli.config_provider.map(ProviderSpec::primary),
+        quiet: cli.quiet,
+        progress: !stream_responses && !cli.quiet && std::io::stderr().is_terminal(),
+        ..Default::default()
     };
 
+    if cli.dump_config {
+        let config = json!({
+            "provider": describe_provider_config(&summary_options, &compare_providers),
+            "suffixes": suffixes,
+            "case_insensitive_suffixes": cli.case_insensitive_suffixes,
+            "filters": {
+                "use_gitignore": !cli.dont_use_gitignore,
+                "exclude_paths": exclude_paths,
+                "include_paths": include_paths,
+                "exclude_dirs": exclude_dirs,
+                "exclude_extensions": exclude_extensions,
+                "only_matching_file": cli.only_matching_file,
+                "files_from": cli.files_from,
+                "or_keywords": or_keywords,
+                "and_keywords": and_keywords,
+                "exclude_keywords": exclude_keywords,
+                "regex_keywords": cli.regex_keywords,
+                "ignore_case": cli.ignore_case,
+                "skip_minified": cli.skip_minified,
+            },
+            "mode": {
+                "summarize": cli.summarize,
+                "summarize_keywords": cli.summarize_keywords,
+                "summarize_stdin": cli.summarize_stdin,
+                "prompt_version": cli.prompt_version,
+                "check_model": cli.check_model,
+                "overview": cli.overview,
+                "overview_model": cli.overview_model,
+                "apply": cli.apply,
+                "summary_position": cli.summary_position,
+                "sidecar": cli.sidecar,
+                "retrieve": cli.retrieve,
+                "diff_only": cli.diff_only,
+                "start_commit_id": cli.start_commit_id,
+                "end_commit_id": cli.end_commit_id,
+                "staged": cli.staged,
+                "include_untracked": cli.include_untracked,
+                "ignore_whitespace": cli.ignore_whitespace,
+            },
+            "output": {
+                "format": cli.format,
+                "section_order": section_order,
+                "sort": cli.sort,
+                "content_only": cli.content_only,
+                "line_numbers": cli.line_numbers,
+                "output_path": cli.output_path,
+                "output_dir": cli.output_dir,
+                "split_size": cli.split_size,
+                "max_output_bytes": cli.max_output_bytes,
+                "max_output_tokens": cli.max_output_tokens,
+                "prompt_template_path": cli.prompt_template_path,
+            },
+        });
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
+
+    if cli.check_model {
+        if let Err(e) = check_models(&summary_options, &compare_providers).await {
+            eprintln!("{} {}", red("Error:"), e);
+            std::process::exit(1);
+        }
+    }
+
+    let mut template_values = TemplateValues {
+        date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+        cwd: std::env::current_dir()?.display().to_string(),
+        ..Default::default()
+    };
+
+    let ProcessedDirectory { files, blocks, failed_files } = if cli.summarize_stdin {
+        let mut stdin_content = String::new();
+        std::io::stdin().read_to_string(&mut stdin_content).context("Failed to read from stdin")?;
+
+        // The pseudo file name only carries the extension used for comment formatting
+        let stdin_file = format!("stdin.{}", cli.stdin_ext.as_deref().unwrap_or("txt"));
+        let file_contents = HashMap::from([(stdin_file.clone(), stdin_content)]);
+        let default_version = if cli.summarize_keywords { "summary-keywords-0.1" } else { "summary-0.2" };
+        let prompt_template = get_prompt(&summarize_prompt_templates, cli.prompt_version.as_deref().unwrap_or(default_version))?;
 
-    if let Some(output_path) = cli.output_path {
-        let mut output_file = File::create(&output_path)?;
-        output_file.write_all(final_content.as_bytes())?;
-        println!("Successfully processed directory and written output to {}", output_path);
+        let summaries = get_summaries(vec![stdin_file.clone()], file_contents, prompt_template, create_comment_map(), false, &summary_options).await?;
+        ProcessedDirectory {
+            blocks: summaries.iter().map(|summary| summary.to_string()).collect(),
+            failed_files: summaries.iter()
+                .filter(|summary| !summary.is_ok())
+                .map(|_| PathBuf::from(&stdin_file))
+                .collect(),
+            ..Default::default()
+        }
     } else {
-        write_to_clipboard(&final_content)?;
-        println!("Successfully processed directory and copied output to clipboard");
+        // Line comments are stored like those from the comment map, with "\n" as the end
+        let comment_delimiters = match cli.comment_style {
+            Some(CommentStyle::Line) => cli.comment_prefix.clone().map(|prefix| (prefix, "\n".to_string())),
+            Some(CommentStyle::Block) => cli.comment_open.clone().zip(cli.comment_close.clone()),
+            None => None,
+        };
+        let files_from = match &cli.files_from {
+            Some(files_from) => Some(read_paths_file(files_from).context("Failed to read file list")?),
+            None => None,
+        };
+        // Process directory and get the content string
+        let options = ProcessOptions {
+            suffixes,
+            dont_use_gitignore: cli.dont_use_gitignore,
+            follow_symlinks: cli.follow_symlinks,
+            summarize: cli.summarize,
+            summarize_keywords: cli.summarize_keywords,
+            prompt_templates: summarize_prompt_templates,
+            prompt_version: cli.prompt_version,
+            apply: cli.apply,
+            sidecar: cli.sidecar,
+            comment_delimiters,
+            summary_position: cli.summary_position,
+            retrieve: cli.retrieve,
+            diff_only: cli.diff_only,
+            exclude_paths,
+            include_paths,
+            or_keywords,
+            and_keywords,
+            exclude_keywords,
+            regex_keywords: cli.regex_keywords,
+            ignore_case: cli.ignore_case,
+            start_commit_id: cli.start_commit_id,
+            end_commit_id: cli.end_commit_id,
+            staged: cli.staged,
+            include_untracked: cli.include_untracked,
+            ignore_whitespace: cli.ignore_whitespace,
+            detect_duplicates: cli.detect_duplicates,
+            whitelist,
+            diff_line_ranges: cli.diff_line_ranges,
+            diffstat: cli.diffstat,
+            diff_with_content: cli.diff_with_content,
+            summarize_binary: cli.summarize_binary,
+            summary_options,
+            section_order,
+            sort: cli.sort,
+            tree: cli.tree,
+            case_insensitive_suffixes: cli.case_insensitive_suffixes,
+            exclude_dirs,
+            exclude_extensions,
+            preprocess_cmd: cli.preprocess_cmd,
+            format: cli.format,
+            keyword_report: cli.keyword_report,
+            keyword_stats: cli.keyword_stats,
+            include_commit_messages: cli.include_commit_messages,
+            list_commits: cli.list_commits,
+            content_options,
+            index_path: cli.index.clone(),
+            output_dir: cli.output_dir.as_ref().map(PathBuf::from),
+            classify: cli.classify,
+            show_metadata: cli.show_metadata,
+            content_only: cli.content_only,
+            line_numbers: cli.line_numbers,
+            max_output_bytes: cli.max_output_bytes,
+            max_output_tokens: cli.max_output_tokens,
+            compare_providers,
+            skip_minified: cli.skip_minified,
+            hierarchical_summary: cli.hierarchical_summary,
+            overview: cli.overview,
+            overview_model: cli.overview_model,
+            quiet: cli.quiet,
+            dry_run: cli.dry_run,
+            files_from,
+        };
+        // A single output file is written while the files are processed, instead of afterwards
+        match (&cli.output_path, &cli.split_size, cli.dry_run) {
+            (Some(output_path), None, false) => {
+                let template = cli.prompt_template_path.as_deref();
+                let mut writer = TemplateWriter::new(BufWriter::new(File::create(output_path)?), template, template_values.clone());
+                let processed = process_directory_into(".", &options, &mut writer).await?;
+                writer.finish()?;
+                processed
+            },
+            _ => process_directory_with_files(".", &options).await?,
+        }
     };
+    template_values.file_list = template_file_list(&files);
+
+    if cli.dry_run {
+        print!("{}", blocks.concat());
+    } else if let Some(output_dir) = &cli.output_dir {
+        if !cli.quiet {
+            eprintln!("{} {}", green("Successfully processed directory and written output to"), output_dir);
+        }
+    } else if let (Some(split_size), Some(output_path)) = (&cli.split_size, &cli.output_path) {
+        let max_bytes = parse_size(split_size)?;
+        let chunks = split_into_chunks(&blocks, chunk_budget(max_bytes, cli.prompt_template_path.as_deref(), &template_values)?);
+        if !cli.quiet {
+            eprintln!("{}", green("Successfully processed directory and written output to:"));
+        }
+        for (i, chunk) in chunks.iter().enumerate() {
+            let chunk_content = if let Some(template_path) = &cli.prompt_template_path {
+                process_with_template(chunk, template_path, &template_values)?
+            } else {
+                chunk.clone()
+            };
+            let chunk_path = chunk_path(output_path, i + 1);
+            File::create(&chunk_path)?.write_all(chunk_content.as_bytes())?;
+            // Only a single file that doesn't fit on its own makes a chunk larger than the limit
+            if chunk_content.len() > max_bytes && !cli.quiet {
+                eprintln!("{} {} is {} bytes, more than --split-size, since a file in it doesn't fit into a smaller part",
+                    yellow("Warning:"), chunk_path, chunk_content.len());
+            }
+            // The chunk paths go to stdout, so they can be piped on, even with --quiet
+            println!("{}", chunk_path);
+        }
+    } else if let Some(output_path) = &cli.output_path {
+        // Directories were written while they were processed, only a stdin summary is left
+        if cli.summarize_stdin {
+            let template = cli.prompt_template_path.as_deref();
+            let mut writer = TemplateWriter::new(BufWriter::new(File::create(output_path)?), template, template_values.clone());
+            writer.begin(&files)?;
+            for block in blocks {
+                writer.write_block(block)?;
+            }
+            writer.finish()?;
+        }
+        if !cli.quiet {
+            eprintln!("{} {}", green("Successfully processed directory and written output to"), output_path);
+        }
+    } else {
+        let content = blocks.concat();
+        let final_content = if let Some(template_path) = &cli.prompt_template_path {
+            process_with_template(&content, template_path, &template_values)?
+        } else {
+            content
+        };
+
+        // Status messages go to stderr, so only the output itself is piped on
+        if cli.stdout {
+            std::io::stdout().write_all(final_content.as_bytes())?;
+            if !cli.quiet {
+                eprintln!("{}", green("Successfully processed directory and written output to stdout"));
+            }
+        } else {
+            // Without a usable clipboard, e.g. on headless systems, the output goes to stdout so it isn't lost
+            match write_to_clipboard(&final_content) {
+                Ok(()) => if !cli.quiet {
+                    eprintln!("{}", green("Successfully processed directory and copied output to clipboard"));
+                },
+                Err(e) => {
+                    if !cli.quiet {
+                        eprintln!("{} {}, writing the output to stdout instead", yellow("Warning:"), e);
+                    }
+                    std::io::stdout().write_all(final_content.as_bytes())?;
+                }
+            }
+        }
+    }
+
+    // The output is written either way, failed files only decide the exit status
+    if !failed_files.is_empty() {
+        let count = format!("{} file{}", failed_files.len(), if failed_files.len() == 1 { "" } else { "s" });
+        if cli.allow_errors {
+            if !cli.quiet {
+                eprintln!("{} {} failed to process", yellow("Warning:"), count);
+            }
+        } else {
+            eprintln!("{} {} failed to process", red("Error:"), count);
+            std::process::exit(1);
+        }
+    }
     Ok(())
 }
 
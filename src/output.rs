@@ -1,6 +1,311 @@
 use std::fs;
+use std::io::Write;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use chrono::DateTime;
 use anyhow::{Result, bail};
 use clipboard::{ClipboardContext, ClipboardProvider};
+use clap::ValueEnum;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::file_processing::{is_executable, language_hint};
+use crate::summary::SummaryOutcome;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Markdown,
+    Json,
+}
+
+/// A single file in the structured JSON output
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct JsonFile {
+    /// Path of the file relative to the processed directory
+    pub path: String,
+    /// File content, or the file's diff in diff-only mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Summary of the file, only present when summarizing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Language hint derived from the file extension
+    pub language: String,
+    /// Size of the file in bytes, left out when the file couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// SHA-256 of the file content, hex encoded, left out when the file couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Whether the file is executable, as marked with `*` by --classify
+    pub executable: bool,
+}
+
+/// The structured JSON output of a run
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct JsonOutput {
+    /// Paths of all processed files, in output order
+    pub paths: Vec<String>,
+    pub files: Vec<JsonFile>,
+}
+
+/// Size and SHA-256 of a file's bytes, kept from reading the file for the JSON output
+#[derive(Debug, Clone)]
+pub struct FileDigest {
+    pub size: u64,
+    pub hash: String,
+}
+
+impl FileDigest {
+    pub fn new(bytes: &[u8]) -> FileDigest {
+        FileDigest {
+            size: bytes.len() as u64,
+            hash: Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+}
+
+impl JsonFile {
+    /// Describes a file for the JSON output; size and hash are taken from the bytes read from disk
+    pub fn new(file_path: &Path, relative_path: &Path, digest: Option<FileDigest>, content: Option<String>, summary: Option<String>) -> JsonFile {
+        JsonFile {
+            path: relative_path.display().to_string(),
+            content,
+            summary,
+            language: language_hint(file_path).to_string(),
+            size: digest.as_ref().map(|digest| digest.size),
+            hash: digest.map(|digest| digest.hash),
+            executable: is_executable(file_path),
+        }
+    }
+}
+
+/// JSON Schema of the structured output, generated from the types above so it stays in sync
+pub fn json_output_schema() -> Result<String> {
+    Ok(serde_json::to_string_pretty(&schema_for!(JsonOutput))?)
+}
+
+/// Wraps content in a markdown code fence tagged with the language.
+/// The fence is made longer than any backtick run inside the content.
+pub fn fence(content: &str, language: &str) -> String {
+    let longest_run = content
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let ticks = "`".repeat((longest_run + 1).max(3));
+    let content = content.strip_suffix('\n').unwrap_or(content);
+    format!("{}{}\n{}\n{}", ticks, language, content, ticks)
+}
+
+/// Formats a block of file content for the chosen output format
+pub fn format_block(content: &str, language: &str, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text | OutputFormat::Json => content.to_string(),
+        OutputFormat::Markdown => fence(content, language),
+    }
+}
+
+/// Prefixes every line with its number, right-aligned to the width of the largest one
+pub fn number_lines(content: &str) -> String {
+    let width = content.lines().count().to_string().len();
+    let mut numbered = content.lines()
+        .enumerate()
+        .map(|(i, line)| match line {
+            // Blank lines get no separator space, so they don't end in trailing whitespace
+            "" => format!("{:>width$} |", i + 1, width = width),
+            _ => format!("{:>width$} | {}", i + 1, line, width = width),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    if content.ends_with('\n') {
+        numbered.push('\n');
+    }
+    numbered
+}
+
+/// Formats a heading for the chosen output format; plain text headings end with a colon,
+/// markdown headings get `level` hashes
+pub fn heading(title: &str, level: usize, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text | OutputFormat::Json => format!("{}:", title),
+        OutputFormat::Markdown => format!("{} {}", "#".repeat(level), title),
+    }
+}
+
+// A directory in the tree view, children are kept in the order they were added
+#[derive(Default)]
+struct TreeNode {
+    children: Vec<(String, TreeNode)>,
+}
+
+impl TreeNode {
+    fn insert(&mut self, components: &[String]) {
+        let Some((first, rest)) = components.split_first() else {
+            return;
+        };
+        let index = match self.children.iter().position(|(name, _)| name == first) {
+            Some(index) => index,
+            None => {
+                self.children.push((first.clone(), TreeNode::default()));
+                self.children.len() - 1
+            }
+        };
+        self.children[index].1.insert(rest);
+    }
+
+    fn render(&self, prefix: &str, lines: &mut Vec<String>) {
+        for (i, (name, child)) in self.children.iter().enumerate() {
+            let last = i == self.children.len() - 1;
+            lines.push(format!("{}{}{}", prefix, if last { "└── " } else { "├── " }, name));
+            child.render(&format!("{}{}", prefix, if last { "    " } else { "│   " }), lines);
+        }
+    }
+}
+
+/// Renders paths below `root`, given as their components, as an indented directory tree like
+/// the `tree` command. Entries appear in the order of `paths`, grouped under their directories.
+pub fn render_tree(root: &str, paths: &[Vec<String>]) -> Vec<String> {
+    let mut tree = TreeNode::default();
+    for components in paths {
+        tree.insert(components);
+    }
+    let mut lines = vec![root.to_string()];
+    tree.render("", &mut lines);
+    lines
+}
+
+pub const BYTES_PER_TOKEN: usize = 4;
+
+/// Parses a size given in bytes ("100000") or in estimated tokens ("25000t")
+pub fn parse_size(size: &str) -> Result<usize> {
+    let (number, multiplier) = match size.strip_suffix('t') {
+        Some(tokens) => (tokens, BYTES_PER_TOKEN),
+        None => (size, 1),
+    };
+    let number: usize = number.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size '{}': expected a number of bytes or tokens like '25000t'", size))?;
+    if number == 0 {
+        bail!("Size must be greater than 0");
+    }
+    number.checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("Invalid size '{}': too large", size))
+}
+
+/// Keeps the leading file blocks that fit into `budget` bytes together with a note
+/// about the files that were left out, so the output is only cut at file boundaries.
+/// Only the first `files` blocks are files, the blocks after them aren't counted in the note.
+pub fn truncate_blocks(blocks: &[String], files: usize, budget: usize, max_bytes: usize) -> Vec<String> {
+    if blocks.iter().map(|block| block.len()).sum::<usize>() <= budget {
+        return blocks.to_vec();
+    }
+
+    let mut kept = 0;
+    let mut used = 0;
+    while kept < blocks.len() && used + blocks[kept].len() <= budget {
+        used += blocks[kept].len();
+        kept += 1;
+    }
+    // Make room for the note itself
+    loop {
+        let note = format!("\n... output truncated at {} bytes, {} files omitted ...\n", max_bytes, files.saturating_sub(kept));
+        if used + note.len() <= budget || kept == 0 {
+            let mut truncated = blocks[..kept].to_vec();
+            truncated.push(note);
+            return truncated;
+        }
+        kept -= 1;
+        used -= blocks[kept].len();
+    }
+}
+
+/// Keeps as many blocks as fit into `budget` bytes by leaving out the largest ones first,
+/// then appends a note with the number of blocks omitted. The kept blocks stay in their order.
+pub fn drop_largest_blocks(blocks: &[String], budget: usize) -> Vec<String> {
+    let sizes = blocks.iter().map(|block| block.len()).collect::<Vec<usize>>();
+    if sizes.iter().sum::<usize>() <= budget {
+        return blocks.to_vec();
+    }
+
+    // Room for the note is set aside up front, assuming the widest possible count
+    let reserved = format!("\n[truncated: {} files omitted]\n", blocks.len()).len();
+    let mut by_size = (0..blocks.len()).collect::<Vec<usize>>();
+    by_size.sort_by_key(|&i| (sizes[i], i));
+
+    let mut keep = vec![false; blocks.len()];
+    let mut used = 0;
+    for i in by_size {
+        if used + sizes[i] + reserved > budget {
+            break;
+        }
+        used += sizes[i];
+        keep[i] = true;
+    }
+
+    let omitted = keep.iter().filter(|&&kept| !kept).count();
+    let mut truncated = blocks.iter().zip(&keep)
+        .filter(|(_, &kept)| kept)
+        .map(|(block, _)| block.clone())
+        .collect::<Vec<String>>();
+    truncated.push(format!("\n[truncated: {} files omitted]\n", omitted));
+    truncated
+}
+
+/// Packs output blocks into chunks of at most `max_bytes`, never splitting a block.
+/// A block larger than `max_bytes` gets a chunk of its own.
+pub fn split_into_chunks(blocks: &[String], max_bytes: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for block in blocks {
+        if !current.is_empty() && current.len() + block.len() > max_bytes {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push_str(block);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Bytes of output blocks that fit into a chunk of at most `max_bytes` once it is embedded
+/// in the template, which may hold the content more than once
+pub fn chunk_budget(max_bytes: usize, template_path: Option<&str>, values: &TemplateValues) -> Result<usize> {
+    let Some(template_path) = template_path else {
+        return Ok(max_bytes);
+    };
+    let template = load_template(template_path, values)?;
+    let copies = template.matches("${${CONTENT}$}$").count();
+    let overhead = template.len() - copies * "${${CONTENT}$}$".len();
+    Ok(max_bytes.saturating_sub(overhead) / copies)
+}
+
+/// Numbers an output path for one chunk, e.g. `out.txt` becomes `out.2.txt`
+pub fn chunk_path(output_path: &str, index: usize) -> String {
+    let path = Path::new(output_path);
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, index),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+/// Path of the output file for one input file below `output_dir`, e.g. `src/main.rs` becomes
+/// `<output_dir>/src/main.rs.txt`, or `.md` for markdown output
+pub fn mirrored_path(output_dir: &Path, relative_path: &Path, format: OutputFormat) -> PathBuf {
+    let extension = match format {
+        OutputFormat::Markdown => "md",
+        OutputFormat::Text | OutputFormat::Json => "txt",
+    };
+    let mut file_name = relative_path.as_os_str().to_owned();
+    file_name.push(format!(".{}", extension));
+    output_dir.join(file_name)
+}
 
 pub fn write_to_clipboard(content: &str) -> Result<()> {
     let mut ctx: ClipboardContext = ClipboardProvider::new()
@@ -12,7 +317,19 @@ pub fn write_to_clipboard(content: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn process_with_template(content: &str, template_path: &str) -> Result<String> {
+/// Values for the optional placeholders of a prompt template
+#[derive(Debug, Clone, Default)]
+pub struct TemplateValues {
+    /// Replaces `${${FILE_LIST}$}$`, the processed files one per line
+    pub file_list: String,
+    /// Replaces `${${DATE}$}$`
+    pub date: String,
+    /// Replaces `${${CWD}$}$`, the directory dirscribe was run in
+    pub cwd: String,
+}
+
+// Reads the template and fills in everything but the content
+fn load_template(template_path: &str, values: &TemplateValues) -> Result<String> {
     // Read the template file
     let template = fs::read_to_string(template_path)
         .map_err(|e| anyhow::anyhow!("Failed to read template file: {}", e))?;
@@ -22,6 +339,173 @@ pub fn process_with_template(content: &str, template_path: &str) -> Result<Strin
         bail!("Template file must contain the placeholder '${{${{CONTENT}}$}}$'");
     }
 
-    // Replace the placeholder with the content
-    Ok(template.replace("${${CONTENT}$}$", content))
-}
\ No newline at end of file
+    Ok(template
+        .replace("${${FILE_LIST}$}$", &values.file_list)
+        .replace("${${DATE}$}$", &values.date)
+        .replace("${${CWD}$}$", &values.cwd))
+}
+
+pub fn process_with_template(content: &str, template_path: &str, values: &TemplateValues) -> Result<String> {
+    // The content goes in last, so placeholders that happen to appear in the files are left alone
+    Ok(load_template(template_path, values)?.replace("${${CONTENT}$}$", content))
+}
+
+/// Lists the files for `${${FILE_LIST}$}$`, relative to the directory dirscribe was run in
+pub fn template_file_list(files: &[PathBuf]) -> String {
+    files.iter()
+        .map(|file| file.strip_prefix(".").unwrap_or(file).display().to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Receives the output of a run block by block, see [`crate::file_processing::process_directory_into`]
+pub trait BlockWriter {
+    /// Called once the files are selected, before the first block
+    fn begin(&mut self, _files: &[PathBuf]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: String) -> Result<()>;
+}
+
+impl BlockWriter for Vec<String> {
+    fn write_block(&mut self, block: String) -> Result<()> {
+        self.push(block);
+        Ok(())
+    }
+}
+
+/// Writes each block as it is produced, embedded in the template if one is given, so the
+/// output is never held in memory as a whole
+pub struct TemplateWriter<W: Write> {
+    writer: W,
+    template_path: Option<String>,
+    values: TemplateValues,
+    // The template parts after the content, written by `finish`
+    rest: Vec<String>,
+    // A template with the content in several places needs all of it, it is only written at the end
+    buffered: Option<Vec<String>>,
+}
+
+impl<W: Write> TemplateWriter<W> {
+    /// The file list of `values` is filled in with the selected files
+    pub fn new(writer: W, template_path: Option<&str>, values: TemplateValues) -> Self {
+        Self {
+            writer,
+            template_path: template_path.map(String::from),
+            values,
+            rest: Vec::new(),
+            buffered: None,
+        }
+    }
+
+    /// Writes the end of the template and flushes the writer
+    pub fn finish(mut self) -> Result<()> {
+        let blocks = self.buffered.take().unwrap_or_default();
+        for part in &self.rest {
+            for block in &blocks {
+                self.writer.write_all(block.as_bytes())?;
+            }
+            self.writer.write_all(part.as_bytes())?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> BlockWriter for TemplateWriter<W> {
+    fn begin(&mut self, files: &[PathBuf]) -> Result<()> {
+        let Some(template_path) = &self.template_path else {
+            return Ok(());
+        };
+        self.values.file_list = template_file_list(files);
+        let template = load_template(template_path, &self.values)?;
+        let mut parts = template.split("${${CONTENT}$}$").map(String::from);
+        self.writer.write_all(parts.next().unwrap_or_default().as_bytes())?;
+        self.rest = parts.collect();
+        if self.rest.len() > 1 {
+            self.buffered = Some(Vec::new());
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: String) -> Result<()> {
+        match &mut self.buffered {
+            Some(blocks) => blocks.push(block),
+            None => self.writer.write_all(block.as_bytes())?,
+        }
+        Ok(())
+    }
+}
+
+
+/// Extracts the first line of prose from a summary comment block, skipping comment
+/// delimiters, the [DIRSCRIBE] markers and the timestamp added by --apply.
+/// Skipped and failed files have no summary to take it from.
+pub fn first_summary_line(summary: &SummaryOutcome) -> Option<String> {
+    let SummaryOutcome::Ok(summary) = summary else {
+        return None;
+    };
+    summary.lines()
+        .map(|line| line
+            .trim_start_matches(|c: char| !c.is_alphanumeric() && c != '`' && c != '[')
+            .trim_end_matches(|c: char| c.is_whitespace() || c == '/' || c == '*' || c == '>' || c == '-')
+            .trim_end_matches("<!")
+            .trim())
+        .find(|line| !line.is_empty()
+            && *line != "[DIRSCRIBE]"
+            && *line != "[/DIRSCRIBE]"
+            && DateTime::parse_from_rfc3339(line).is_err())
+        .map(String::from)
+}
+
+// The path of `path` seen from the directory `base`, going up with ".." where needed
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let resolve = |p: &Path| fs::canonicalize(p).or_else(|_| std::path::absolute(p)).unwrap_or_else(|_| p.to_path_buf());
+    let (path, base) = (resolve(path), resolve(base));
+    let common = path.components().zip(base.components()).take_while(|(a, b)| a == b).count();
+    base.components().skip(common).map(|_| Component::ParentDir)
+        .chain(path.components().skip(common))
+        .collect()
+}
+
+// Markdown link targets end at a space or a parenthesis, so those are percent-encoded
+fn link_target(path: &Path) -> String {
+    let link = path.components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<String>>()
+        .join("/");
+    link.chars()
+        .map(|c| match c {
+            '%' | ' ' | '(' | ')' | '<' | '>' => format!("%{:02X}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Builds a markdown index linking every file to the first line of its summary, grouped by directory.
+/// The links are relative to `index_dir`, the directory the index is written to.
+pub fn build_summary_index(summaries: &[(PathBuf, SummaryOutcome)], index_dir: &Path) -> String {
+    let mut directories: BTreeMap<String, Vec<(String, String, String)>> = BTreeMap::new();
+    for (file, summary) in summaries {
+        let link = link_target(&relative_path(file, index_dir));
+        let file = file.strip_prefix(".").unwrap_or(file);
+        let directory = match file.parent().map(|p| p.to_string_lossy().into_owned()) {
+            Some(parent) if !parent.is_empty() => parent,
+            _ => ".".to_string(),
+        };
+        let name = file.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let line = first_summary_line(summary).unwrap_or_else(|| "_No summary available_".to_string());
+        directories.entry(directory).or_default().push((name, link, line));
+    }
+
+    let mut index = String::from("# Summary\n");
+    for (directory, mut files) in directories {
+        files.sort();
+        index.push_str(&format!("\n## {}\n\n", directory));
+        for (name, link, line) in files {
+            index.push_str(&format!("- [{}]({}): {}\n", name, link, line));
+        }
+    }
+    index
+}
@@ -18,6 +18,28 @@ pub fn load_prompts(_dir: &str) -> std::io::Result<HashMap<String, String>> {
         "summary-diff-0.1".to_string(),
         include_str!("../prompts/summary-diff-0.1.txt").to_string()
     );
+
+
+    prompts.insert(
+        "summary-image-0.1".to_string(),
+        include_str!("../prompts/summary-image-0.1.txt").to_string()
+    );
+
+    prompts.insert(
+        "summary-directory-0.1".to_string(),
+        include_str!("../prompts/summary-directory-0.1.txt").to_string()
+    );
+
+    prompts.insert(
+        "overview-0.1".to_string(),
+        include_str!("../prompts/overview-0.1.txt").to_string()
+    );
     
     Ok(prompts)
-}
\ No newline at end of file
+}
+/// Looks up a prompt template by name; the error names the template when it isn't loaded
+pub fn get_prompt(prompts: &HashMap<String, String>, name: &str) -> anyhow::Result<String> {
+    prompts.get(name)
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("Prompt template '{}' not found", name))
+}
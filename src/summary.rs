@@ -1,20 +1,48 @@
 use reqwest::{Client, header};
 use serde::{Deserialize, Serialize};
-use tokio::time::{sleep, Duration};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use tokio::time::{sleep, timeout, Duration, Instant};
+use tracing::{debug, info, warn};
 use anyhow::{Result, Context};
 use std::env;
-use std::path::Path;
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use tokio::sync::Semaphore;
-use std::sync::Arc;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
 use std::str::FromStr;
-use crate::file_processing::filter_dirscribe_sections;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::file_processing::{filter_dirscribe_sections, image_media_type, CommentMap};
+use crate::color::yellow;
+use crate::cache::{default_cache_dir, SummaryCache};
+use crate::output::{drop_largest_blocks, BYTES_PER_TOKEN};
 
 const DEFAULT_CONCURRENT_REQUESTS: usize = 10;
 const ANTHROPIC_MAX_TOKENS: i32 = 512;
 const ANTHROPIC_TEMPERATURE: f32 = 0.1;
-const MAX_RETRIES: u32 = 6;
-const INITIAL_BACKOFF_MS: u64 = 1000;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 120;
+const DEFAULT_MAX_RETRIES: u32 = 6;
+const DEFAULT_INITIAL_BACKOFF_MS: u64 = 1000;
+
+const COMMENT_START_PLACEHOLDER: &str = "${${COMMENT_START}$}$";
+const COMMENT_END_PLACEHOLDER: &str = "${${COMMENT_END}$}$";
+
+// Phrases that mark a response as a refusal rather than a summary, matched case-insensitively
+pub const DEFAULT_REFUSAL_PATTERNS: &[&str] = &[
+    "I can't help with that",
+    "I cannot help with that",
+    "I can't assist with",
+    "I cannot assist with",
+    "I'm sorry, but I can't",
+    "I'm sorry, but I cannot",
+    "I am unable to",
+    "I'm unable to",
+    "As an AI language model",
+];
 
 const DEFAULT_DEEPSEEK_MODEL: &str = "deepseek-chat";
 const DEFAULT_ANTHROPIC_MODEL: &str = "claude-3-sonnet-20240229";
@@ -34,48 +62,328 @@ impl FromStr for Provider {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "deepseek" => Ok(Provider::Deepseek),
-            "anthropic" => Ok(Provider::Anthropic),
-            "ollama" => Ok(Provider::Ollama),
-            "gemini" => Ok(Provider::Gemini),
-            _ => Err(anyhow::anyhow!("Invalid provider: {}. Valid options are: deepseek, anthropic, ollama", s))
+        let name = s.to_lowercase();
+        Provider::ALL.into_iter()
+            .find(|provider| provider.name() == name)
+            .ok_or_else(|| anyhow::anyhow!(
+                "Invalid provider: {}. Valid options are: {}",
+                s, Provider::ALL.map(|provider| provider.name()).join(", ")
+            ))
+    }
+}
+
+impl Provider {
+    /// Every provider, in the order they are listed to the user
+    pub const ALL: [Provider; 4] = [Provider::Deepseek, Provider::Anthropic, Provider::Ollama, Provider::Gemini];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::Deepseek => "deepseek",
+            Provider::Anthropic => "anthropic",
+            Provider::Ollama => "ollama",
+            Provider::Gemini => "gemini",
+        }
+    }
+
+    pub fn supports_images(&self) -> bool {
+        !matches!(self, Provider::Deepseek)
+    }
+}
+
+// A provider with an optional model, written as "provider" or "provider:model"
+#[derive(Debug, Clone)]
+pub struct ProviderSpec {
+    pub provider: Provider,
+    pub model: Option<String>,
+    /// Endpoint instead of the provider's default one, like `DIRSCRIBE_BASE_URL`
+    pub base_url: Option<String>,
+}
+
+impl ProviderSpec {
+    /// The primary provider, with the model and endpoint from `DIRSCRIBE_MODEL` and `DIRSCRIBE_BASE_URL`
+    pub fn primary(provider: Provider) -> Self {
+        ProviderSpec {
+            provider,
+            model: env::var("DIRSCRIBE_MODEL").ok(),
+            base_url: env::var("DIRSCRIBE_BASE_URL").ok(),
         }
     }
 }
 
+impl FromStr for ProviderSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (provider, model) = match s.split_once(':') {
+            Some((provider, model)) => (provider, Some(model.to_string())),
+            None => (s, None),
+        };
+        Ok(ProviderSpec {
+            provider: Provider::from_str(provider)?,
+            model,
+            base_url: None,
+        })
+    }
+}
+
+impl fmt::Display for ProviderSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.model {
+            Some(model) => write!(f, "{}:{}", self.provider.name(), model),
+            None => write!(f, "{}", self.provider.name()),
+        }
+    }
+}
+
+// Base64 encoded image attached to a message
+#[derive(Debug, Clone)]
+pub struct ImageContent {
+    pub media_type: String,
+    pub data: String,
+}
+
 // Common message structure used across providers
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
     pub role: String,
     pub content: String,
+    #[serde(skip)]
+    pub image: Option<ImageContent>,
 }
 
 // Unified response structure
 #[derive(Debug)]
 pub struct UnifiedResponse {
     pub content: String,
+    pub usage: Option<TokenUsage>,
 }
 
+/// Tokens used by a client's requests, as reported by the provider
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Files summarized with these tokens, counted once however often they were retried
+    pub files: u64,
+}
+
+impl TokenUsage {
+    fn add(&mut self, other: TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.files += other.files;
+    }
+}
+
+// Dollars per million input and output tokens, matched by model name prefix
+const MODEL_PRICES: &[(&str, f64, f64)] = &[
+    ("deepseek-chat", 0.27, 1.10),
+    ("deepseek-reasoner", 0.55, 2.19),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-7-sonnet", 3.00, 15.00),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-sonnet", 3.00, 15.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("gemini-1.5-flash", 0.075, 0.30),
+    ("gemini-1.5-pro", 1.25, 5.00),
+    ("gemini-2.0-flash", 0.10, 0.40),
+];
+
+/// Estimated cost in dollars, `None` if the model's price is unknown. Local Ollama models are free.
+pub fn estimate_cost(provider: Provider, model: &str, usage: &TokenUsage) -> Option<f64> {
+    if matches!(provider, Provider::Ollama) {
+        return Some(0.0);
+    }
+    MODEL_PRICES.iter()
+        .find(|(prefix, _, _)| model.starts_with(prefix))
+        .map(|(_, input, output)| (usage.input_tokens as f64 * input + usage.output_tokens as f64 * output) / 1_000_000.0)
+}
+
+/// Prints the tokens used by a run's clients to stderr, with the estimated cost if asked for
+fn report_usage(clients: &[&UnifiedClient], show_cost: bool) {
+    let mut total = TokenUsage::default();
+    let mut cost = 0.0;
+    let mut unpriced_models = Vec::new();
+    for client in clients {
+        let usage = client.usage();
+        total.add(usage);
+        match estimate_cost(client.provider, &client.model, &usage) {
+            Some(client_cost) => cost += client_cost,
+            None if usage.files > 0 => unpriced_models.push(client.model.as_str()),
+            None => {},
+        }
+    }
+    // Everything came from the resume file or the cache, or the provider doesn't report usage
+    if total.files == 0 || total.input_tokens + total.output_tokens == 0 {
+        return;
+    }
+
+    let mut line = format!(
+        "Used {} input / {} output tokens across {} files",
+        format_count(total.input_tokens), format_count(total.output_tokens), format_count(total.files)
+    );
+    if show_cost {
+        if unpriced_models.is_empty() {
+            line += &format!(", estimated cost ${:.4}", cost);
+        } else {
+            line += &format!(", no price known for {}", unpriced_models.join(", "));
+        }
+    }
+    eprintln!("{}", line);
+}
+
+// Groups digits by thousands, e.g. 12340 becomes "12,340"
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+const RESPONSE_SNIPPET_CHARS: usize = 200;
+
+// Shows the start and end of a response body, which is enough to spot truncation
+fn response_snippet(body: &str) -> String {
+    let char_count = body.chars().count();
+    if char_count <= 2 * RESPONSE_SNIPPET_CHARS {
+        return body.to_string();
+    }
+    let head: String = body.chars().take(RESPONSE_SNIPPET_CHARS).collect();
+    let tail: String = body.chars().skip(char_count - RESPONSE_SNIPPET_CHARS).collect();
+    format!("{} ... {}", head, tail)
+}
+
+/// The summary of a single file, or why there is none
+#[derive(Debug, Clone, PartialEq)]
+pub enum SummaryOutcome {
+    Ok(String),
+    /// Left out after --per-file-timeout, with the reason
+    Skipped(String),
+    /// The request failed or the summary was rejected, with the error
+    Failed(String),
+}
+
+impl SummaryOutcome {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, SummaryOutcome::Ok(_))
+    }
+}
+
+// Failures and skips are marked, so they stand out in the output
+impl fmt::Display for SummaryOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SummaryOutcome::Ok(summary) => write!(f, "{}", summary),
+            SummaryOutcome::Skipped(reason) => write!(f, "Skipped: {}", reason),
+            SummaryOutcome::Failed(error) => write!(f, "Error: {}", error),
+        }
+    }
+}
+
+// Reasoning models put their thoughts before the answer, only the answer is kept
+fn strip_thinking(response: String) -> String {
+    match response.split_once("</think>") {
+        Some((_, answer)) => answer.trim().to_string(),
+        None => response,
+    }
+}
+
+fn parse_json<T: DeserializeOwned>(response_text: &str) -> Result<T> {
+    serde_json::from_str(response_text).map_err(|e| anyhow::anyhow!(
+        "Invalid response body ({}, {} bytes): {}",
+        e, response_text.len(), response_snippet(response_text)
+    ))
+}
+
+// Gemini endpoints name the model in their path, as in ".../models/<model>:generateContent";
+// the root and the method are kept, only the model is put in
+fn gemini_endpoint(base_url: &str, model: &str) -> Option<String> {
+    let (root, rest) = base_url.rsplit_once("/models/")?;
+    let (_, method) = rest.split_once(':')?;
+    Some(format!("{}/models/{}:{}", root, model, method))
+}
+
+// The provider could not be reached or kept failing on its side
+#[derive(Debug)]
+pub struct ProviderUnavailable(String);
+
+impl fmt::Display for ProviderUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Provider unavailable: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProviderUnavailable {}
+
+// The summary of a file took longer than --per-file-timeout
+#[derive(Debug)]
+pub struct SummaryTimedOut(u64);
+
+impl fmt::Display for SummaryTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "summarization timed out after {} seconds", self.0)
+    }
+}
+
+impl std::error::Error for SummaryTimedOut {}
+
 pub struct UnifiedClient {
     client: Client,
     provider: Provider,
     api_key: String,
     base_url: String,
     model: String,
+    stream: bool,
+    usage: Mutex<TokenUsage>,
 }
 
+// Only one response is echoed to stderr at a time, concurrent ones are accumulated silently
+static STREAMING_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
 impl UnifiedClient {
     pub fn new(provider: Provider) -> Result<Self> {
-        let client = Client::new();
-        
+        let mut client = Self::from_env(provider, "PROVIDER_API_KEY", env::var("DIRSCRIBE_MODEL").ok())?;
+        // A gateway or proxy replaces the provider's endpoint, authentication stays as is
+        if let Ok(base_url) = env::var("DIRSCRIBE_BASE_URL") {
+            client.base_url = base_url;
+        }
+        Ok(client)
+    }
+
+    // The fallback provider is configured separately, since key and model rarely carry over
+    pub fn new_fallback(provider: Provider) -> Result<Self> {
+        Self::from_env(provider, "DIRSCRIBE_FALLBACK_API_KEY", env::var("DIRSCRIBE_FALLBACK_MODEL").ok())
+    }
+
+    // Each compared provider reads its key from DIRSCRIBE_<PROVIDER>_API_KEY, falling back to PROVIDER_API_KEY
+    pub fn from_spec(spec: &ProviderSpec) -> Result<Self> {
+        let key_var = format!("DIRSCRIBE_{}_API_KEY", spec.provider.name().to_uppercase());
+        let key_var = if env::var(&key_var).is_ok() { key_var.as_str() } else { "PROVIDER_API_KEY" };
+        let mut client = Self::from_env(spec.provider, key_var, spec.model.clone())?;
+        if let Some(base_url) = &spec.base_url {
+            client.base_url = base_url.clone();
+        }
+        Ok(client)
+    }
+
+    fn from_env(provider: Provider, key_var: &str, model: Option<String>) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(request_timeout_secs()))
+            .build()
+            .context("Failed to build HTTP client")?;
 
         let (api_key, base_url, model) = match provider {
             Provider::Deepseek => {
-                let key = env::var("PROVIDER_API_KEY")
-                    .context("PROVIDER_API_KEY not set")?;
-                let model = env::var("DIRSCRIBE_MODEL")
-                    .unwrap_or_else(|_| DEFAULT_DEEPSEEK_MODEL.to_string());
+                let key = env::var(key_var)
+                    .with_context(|| format!("{} not set", key_var))?;
+                let model = model
+                    .unwrap_or_else(|| DEFAULT_DEEPSEEK_MODEL.to_string());
                 (
                     key,
                     "https://api.deepseek.com/v1/chat/completions".to_string(),
@@ -83,10 +391,10 @@ impl UnifiedClient {
                 )
             }
             Provider::Anthropic => {
-                let key = env::var("PROVIDER_API_KEY")
-                    .context("PROVIDER_API_KEY not set")?;
-                let model = env::var("DIRSCRIBE_MODEL")
-                    .unwrap_or_else(|_| DEFAULT_ANTHROPIC_MODEL.to_string());
+                let key = env::var(key_var)
+                    .with_context(|| format!("{} not set", key_var))?;
+                let model = model
+                    .unwrap_or_else(|| DEFAULT_ANTHROPIC_MODEL.to_string());
                 (
                     key,
                     "https://api.anthropic.com/v1/messages".to_string(),
@@ -94,8 +402,8 @@ impl UnifiedClient {
                 )
             }
             Provider::Ollama => {
-                let model = env::var("DIRSCRIBE_MODEL")
-                    .unwrap_or_else(|_| DEFAULT_OLLAMA_MODEL.to_string());
+                let model = model
+                    .unwrap_or_else(|| DEFAULT_OLLAMA_MODEL.to_string());
                 (
                     String::new(), // No API key needed for local Ollama
                     "http://localhost:11434/api/generate".to_string(),
@@ -103,10 +411,10 @@ impl UnifiedClient {
                 )
             }
             Provider::Gemini => {
-                let key = env::var("PROVIDER_API_KEY")
-                    .context("PROVIDER_API_KEY not set")?;
-                let model = env::var("DIRSCRIBE_MODEL")
-                    .unwrap_or_else(|_| DEFAULT_GEMINI_MODEL.to_string());
+                let key = env::var(key_var)
+                    .with_context(|| format!("{} not set", key_var))?;
+                let model = model
+                    .unwrap_or_else(|| DEFAULT_GEMINI_MODEL.to_string());
                 (
                     key,
                     format!("https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent", model),
@@ -121,9 +429,72 @@ impl UnifiedClient {
             api_key,
             base_url,
             model,
+            stream: false,
+            usage: Mutex::new(TokenUsage::default()),
+        })
+    }
+
+    /// Settings of the client as shown by --dump-config, with the API key redacted
+    pub fn describe(&self) -> serde_json::Value {
+        json!({
+            "provider": self.provider.name(),
+            "model": self.model,
+            "base_url": self.base_url,
+            "api_key": if self.api_key.is_empty() { None } else { Some("<redacted>") },
         })
     }
 
+    /// Tokens used by all requests of this client so far
+    pub fn usage(&self) -> TokenUsage {
+        self.usage.lock().map(|usage| *usage).unwrap_or_default()
+    }
+
+    fn record_usage(&self, usage: TokenUsage) {
+        if let Ok(mut total) = self.usage.lock() {
+            total.add(usage);
+        }
+    }
+
+    /// Uses this model instead of the configured one, e.g. a stronger model for a single request
+    pub fn with_model(mut self, model: Option<&str>) -> Self {
+        if let Some(model) = model {
+            // Gemini names the model in the endpoint instead of the request body
+            if matches!(self.provider, Provider::Gemini) {
+                match gemini_endpoint(&self.base_url, model) {
+                    Some(endpoint) => self.base_url = endpoint,
+                    None => warn!(base_url = %self.base_url, model, "Gemini endpoint has no model in its path, the model can't be changed"),
+                }
+            }
+            self.model = model.to_string();
+        }
+        self
+    }
+
+    /// Streams responses from providers that support it, printing them to stderr as they arrive
+    pub fn with_streaming(mut self, stream: bool) -> Self {
+        // Anthropic and Gemini use their own event formats, they are always read in one piece
+        self.stream = stream && matches!(self.provider, Provider::Deepseek | Provider::Ollama);
+        self
+    }
+
+    /// Identifies a request to this provider and model, see `SummaryCache`
+    fn cache_key(&self, messages: &[Message], temperature: Option<f32>, max_tokens: Option<i32>) -> String {
+        let mut parts = vec![
+            self.provider.name().to_string(),
+            self.model.clone(),
+            format!("{:?}", temperature),
+            format!("{:?}", max_tokens),
+        ];
+        for message in messages {
+            parts.push(message.role.clone());
+            parts.push(message.content.clone());
+            if let Some(image) = &message.image {
+                parts.push(image.data.clone());
+            }
+        }
+        SummaryCache::key(&parts.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
     fn build_headers(&self) -> Result<header::HeaderMap> {
         let mut headers = header::HeaderMap::new();
         
@@ -146,10 +517,6 @@ impl UnifiedClient {
             }
             Provider::Ollama => {}
             Provider::Gemini => {
-                headers.insert(
-                    "Content-Type",
-                    "application/json; charset=utf-8".parse().unwrap(),
-                );
                 headers.insert(
                     "X-goog-api-key",
                     self.api_key.parse().unwrap(),
@@ -177,11 +544,29 @@ impl UnifiedClient {
                 })
             }
             Provider::Anthropic => {
+                let messages = messages.iter().map(|m| match &m.image {
+                    Some(image) => serde_json::json!({
+                        "role": m.role,
+                        "content": [
+                            {
+                                "type": "image",
+                                "source": {
+                                    "type": "base64",
+                                    "media_type": image.media_type,
+                                    "data": image.data
+                                }
+                            },
+                            { "type": "text", "text": m.content }
+                        ]
+                    }),
+                    None => serde_json::json!({ "role": m.role, "content": m.content }),
+                }).collect::<Vec<_>>();
+
                 serde_json::json!({
                     "model": self.model,
                     "messages": messages,
-                    "max_tokens": ANTHROPIC_MAX_TOKENS,
-                    "temperature": ANTHROPIC_TEMPERATURE
+                    "max_tokens": max_tokens.unwrap_or(ANTHROPIC_MAX_TOKENS),
+                    "temperature": temperature.unwrap_or(ANTHROPIC_TEMPERATURE)
                 })
             }
             Provider::Ollama => {
@@ -190,21 +575,57 @@ impl UnifiedClient {
                     .map(|m| format!("{}: {}", m.role, m.content))
                     .collect::<Vec<_>>()
                     .join("\n");
+                let images = messages.iter()
+                    .filter_map(|m| m.image.as_ref().map(|image| image.data.clone()))
+                    .collect::<Vec<_>>();
                 
-                serde_json::json!({
-                    "model": self.model,
-                    "prompt": prompt,
-                    "stream": false
-                })
+                let mut request = if images.is_empty() {
+                    serde_json::json!({
+                        "model": self.model,
+                        "prompt": prompt,
+                        "stream": false
+                    })
+                } else {
+                    serde_json::json!({
+                        "model": self.model,
+                        "prompt": prompt,
+                        "images": images,
+                        "stream": false
+                    })
+                };
+
+                // Ollama takes sampling settings as model options, the model's defaults apply otherwise
+                let mut options = serde_json::Map::new();
+                if let Some(temperature) = temperature {
+                    options.insert("temperature".to_string(), json!(temperature));
+                }
+                if let Some(max_tokens) = max_tokens {
+                    options.insert("num_predict".to_string(), json!(max_tokens));
+                }
+                if !options.is_empty() {
+                    request["options"] = serde_json::Value::Object(options);
+                }
+                request
             }
             Provider::Gemini => {
                 // Convert messages to Gemini format
-                let contents = messages.iter().map(|m| {
-                    serde_json::json!({
+                let contents = messages.iter().map(|m| match &m.image {
+                    Some(image) => serde_json::json!({
+                        "parts": [
+                            {
+                                "inline_data": {
+                                    "mime_type": image.media_type,
+                                    "data": image.data
+                                }
+                            },
+                            { "text": m.content }
+                        ]
+                    }),
+                    None => serde_json::json!({
                         "parts": [{
                             "text": m.content
                         }]
-                    })
+                    }),
                 }).collect::<Vec<_>>();
 
                 serde_json::json!({
@@ -218,13 +639,86 @@ impl UnifiedClient {
         }
     }
 
+    // Endpoint that lists the available models, derived from the generation endpoint
+    fn models_url(&self) -> Option<String> {
+        match self.provider {
+            Provider::Ollama => self.base_url.strip_suffix("/api/generate")
+                .or_else(|| self.base_url.strip_suffix("/api/chat"))
+                .map(|base| format!("{}/api/tags", base)),
+            Provider::Deepseek => self.base_url.strip_suffix("/chat/completions")
+                .map(|base| format!("{}/models", base)),
+            Provider::Anthropic | Provider::Gemini => None,
+        }
+    }
+
+    /// Checks that the model is offered by the provider before anything is summarized, so a
+    /// misspelled model fails right away instead of after all retries. Providers without a
+    /// model list are not checked, and neither are lists that can't be fetched.
+    pub async fn check_model(&self) -> Result<()> {
+        let Some(url) = self.models_url() else {
+            return Ok(());
+        };
+        let response = match self.client.get(&url).headers(self.build_headers()?).send().await {
+            Ok(response) if response.status().is_success() => response.text().await?,
+            Ok(response) => {
+                debug!(url, status = %response.status(), "Could not list models, skipping the model check");
+                return Ok(());
+            },
+            Err(e) => {
+                debug!(url, error = %e, "Could not list models, skipping the model check");
+                return Ok(());
+            },
+        };
+
+        let available: Vec<String> = match self.provider {
+            Provider::Ollama => {
+                #[derive(Debug, Deserialize)]
+                struct OllamaTags {
+                    models: Vec<OllamaModel>,
+                }
+
+                #[derive(Debug, Deserialize)]
+                struct OllamaModel {
+                    name: String,
+                }
+
+                let tags: OllamaTags = parse_json(&response)?;
+                tags.models.into_iter().map(|model| model.name).collect()
+            }
+            _ => {
+                #[derive(Debug, Deserialize)]
+                struct ModelList {
+                    data: Vec<ModelEntry>,
+                }
+
+                #[derive(Debug, Deserialize)]
+                struct ModelEntry {
+                    id: String,
+                }
+
+                let models: ModelList = parse_json(&response)?;
+                models.data.into_iter().map(|model| model.id).collect()
+            }
+        };
+
+        // Ollama lists every model with its tag, a model given without one is the "latest" tag
+        let is_model = |name: &str| name == self.model
+            || (matches!(self.provider, Provider::Ollama) && !self.model.contains(':') && name == format!("{}:latest", self.model));
+        if !available.iter().any(|name| is_model(name)) {
+            anyhow::bail!(
+                "Model '{}' is not available from {}. Available models: {}",
+                self.model, self.provider.name(), available.join(", ")
+            );
+        }
+        Ok(())
+    }
+
     async fn parse_response(&self, response_text: String) -> Result<UnifiedResponse> {
         match self.provider {
             Provider::Deepseek => {
                 #[derive(Debug, Deserialize)]
                 struct DeepseekResponse {
                     choices: Vec<DeepseekChoice>,
-                    #[allow(dead_code)]
                     usage: DeepseekUsage,
                 }
                 
@@ -234,21 +728,25 @@ impl UnifiedClient {
                 }
                 
                 #[derive(Debug, Deserialize)]
-                #[allow(dead_code)]
                 struct DeepseekUsage {
-                    total_tokens: i32,
+                    prompt_tokens: u64,
+                    completion_tokens: u64,
                 }
 
-                let response: DeepseekResponse = serde_json::from_str(&response_text)?;
+                let response: DeepseekResponse = parse_json(&response_text)?;
                 Ok(UnifiedResponse {
-                    content: response.choices[0].message.content.clone()
+                    content: response.choices[0].message.content.clone(),
+                    usage: Some(TokenUsage {
+                        input_tokens: response.usage.prompt_tokens,
+                        output_tokens: response.usage.completion_tokens,
+                        files: 0,
+                    }),
                 })
             }
             Provider::Anthropic => {
                 #[derive(Debug, Deserialize)]
                 struct AnthropicResponse {
                     content: Vec<AnthropicContent>,
-                    #[allow(dead_code)]
                     usage: AnthropicUsage,
                 }
                 
@@ -262,15 +760,19 @@ impl UnifiedClient {
                 }
                 
                 #[derive(Debug, Deserialize)]
-                #[allow(dead_code)]
                 struct AnthropicUsage {
-                    input_tokens: i32,
-                    output_tokens: i32,
+                    input_tokens: u64,
+                    output_tokens: u64,
                 }
 
-                let response: AnthropicResponse = serde_json::from_str(&response_text)?;
+                let response: AnthropicResponse = parse_json(&response_text)?;
                 Ok(UnifiedResponse {
-                    content: response.content[0].message.clone()
+                    content: response.content[0].message.clone(),
+                    usage: Some(TokenUsage {
+                        input_tokens: response.usage.input_tokens,
+                        output_tokens: response.usage.output_tokens,
+                        files: 0,
+                    }),
                 })
             }
             Provider::Ollama => {
@@ -279,27 +781,46 @@ impl UnifiedClient {
                     response: String,
                     #[allow(dead_code)]
                     done: bool,
+                    // Left out when the prompt was cached by Ollama
+                    #[serde(default)]
+                    prompt_eval_count: u64,
+                    #[serde(default)]
+                    eval_count: u64,
                 }
-                let response: OllamaResponse = serde_json::from_str(&response_text)?;
-                let content = if response.response.contains("</think>") {
-                    response.response
-                        .split("</think>")
-                        .nth(1)
-                        .unwrap_or(&response.response)
-                        .trim()
-                        .to_string()
-                } else {
-                    response.response.clone()
-                };
-                
+                let response: OllamaResponse = parse_json(&response_text)?;
                 Ok(UnifiedResponse {
-                    content
+                    content: strip_thinking(response.response),
+                    usage: Some(TokenUsage {
+                        input_tokens: response.prompt_eval_count,
+                        output_tokens: response.eval_count,
+                        files: 0,
+                    }),
                 })
             }
             Provider::Gemini => {
+                // Blocked prompts come back without candidates, only with feedback on why
                 #[derive(Debug, Deserialize)]
+                #[serde(rename_all = "camelCase")]
                 struct GeminiResponse {
+                    #[serde(default)]
                     candidates: Vec<GeminiCandidate>,
+                    prompt_feedback: Option<GeminiPromptFeedback>,
+                    usage_metadata: Option<GeminiUsage>,
+                }
+
+                #[derive(Debug, Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct GeminiUsage {
+                    #[serde(default)]
+                    prompt_token_count: u64,
+                    #[serde(default)]
+                    candidates_token_count: u64,
+                }
+
+                #[derive(Debug, Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct GeminiPromptFeedback {
+                    block_reason: Option<String>,
                 }
 
                 #[derive(Debug, Deserialize)]
@@ -317,12 +838,16 @@ impl UnifiedClient {
                     text: String,
                 }
 
-                let response: GeminiResponse = serde_json::from_str(&response_text)?;
+                let response: GeminiResponse = parse_json(&response_text)?;
                 
                 // Get the first candidate's text
+                // Returned as an error so the request is retried with backoff
+                let block_reason = response.prompt_feedback
+                    .and_then(|feedback| feedback.block_reason)
+                    .unwrap_or_else(|| "unknown".to_string());
                 let content = response.candidates
                     .first()
-                    .ok_or_else(|| anyhow::anyhow!("No response candidates"))?
+                    .ok_or_else(|| anyhow::anyhow!("No response candidates (block reason: {})", block_reason))?
                     .content
                     .parts
                     .first()
@@ -330,50 +855,192 @@ impl UnifiedClient {
                     .text
                     .clone();
 
-                Ok(UnifiedResponse { content })
+                Ok(UnifiedResponse {
+                    content,
+                    usage: response.usage_metadata.map(|usage| TokenUsage {
+                        input_tokens: usage.prompt_token_count,
+                        output_tokens: usage.candidates_token_count,
+                        files: 0,
+                    }),
+                })
+            }
+        }
+    }
+
+    /// Extracts the text of one line of a streamed response: NDJSON for Ollama, server-sent events otherwise
+    fn stream_token(&self, line: &str) -> Option<String> {
+        match self.provider {
+            Provider::Ollama => {
+                let chunk: serde_json::Value = serde_json::from_str(line).ok()?;
+                chunk["response"].as_str().map(String::from)
+            }
+            _ => {
+                let data = line.strip_prefix("data:")?.trim();
+                let chunk: serde_json::Value = serde_json::from_str(data).ok()?;
+                chunk["choices"][0]["delta"]["content"].as_str().map(String::from)
+            }
+        }
+    }
+
+    /// Extracts the token usage from the last lines of a streamed response: Ollama's final chunk,
+    /// or the chunk Deepseek sends after the text when it was asked to include the usage
+    fn stream_usage(&self, line: &str) -> Option<TokenUsage> {
+        match self.provider {
+            Provider::Ollama => {
+                let chunk: serde_json::Value = serde_json::from_str(line).ok()?;
+                if !chunk["done"].as_bool().unwrap_or(false) {
+                    return None;
+                }
+                Some(TokenUsage {
+                    input_tokens: chunk["prompt_eval_count"].as_u64().unwrap_or(0),
+                    output_tokens: chunk["eval_count"].as_u64().unwrap_or(0),
+                    files: 0,
+                })
+            }
+            _ => {
+                let data = line.strip_prefix("data:")?.trim();
+                let chunk: serde_json::Value = serde_json::from_str(data).ok()?;
+                let usage = chunk.get("usage").filter(|usage| usage.is_object())?;
+                Some(TokenUsage {
+                    input_tokens: usage["prompt_tokens"].as_u64().unwrap_or(0),
+                    output_tokens: usage["completion_tokens"].as_u64().unwrap_or(0),
+                    files: 0,
+                })
             }
         }
     }
 
-    pub async fn chat(&self, suffix_map: &HashMap<&'static str, Vec<(&'static str, &'static str)>>, diff_only: bool,  file_path: &str, messages: &Vec<Message>, temperature: Option<f32>, max_tokens: Option<i32>) -> Result<UnifiedResponse> {
-        let request = self.build_request(messages.clone(), temperature, max_tokens);
+    /// Reads a streamed response, returning the raw body, the assembled text and the usage if it was reported.
+    /// The text is `None` if no line could be parsed, e.g. because the endpoint ignored the stream flag.
+    async fn read_stream(&self, mut response: reqwest::Response, file_path: &str) -> Result<(String, Option<String>, Option<TokenUsage>), reqwest::Error> {
+        let echo = !STREAMING_TO_STDERR.swap(true, Ordering::SeqCst);
+        if echo {
+            eprintln!("{}:", file_path);
+        }
+
+        let mut body = Vec::new();
+        let mut line = Vec::new();
+        let mut content: Option<String> = None;
+        let mut usage = None;
+        let mut handle_line = |line: &[u8]| {
+            let line = String::from_utf8_lossy(line);
+            if let Some(line_usage) = self.stream_usage(line.trim()) {
+                usage = Some(line_usage);
+            }
+            if let Some(token) = self.stream_token(line.trim()) {
+                if echo {
+                    eprint!("{}", token);
+                    let _ = std::io::stderr().flush();
+                }
+                content.get_or_insert_with(String::new).push_str(&token);
+            }
+        };
+        let result = async {
+            while let Some(chunk) = response.chunk().await? {
+                body.extend_from_slice(&chunk);
+                for &byte in chunk.iter() {
+                    if byte == b'\n' {
+                        handle_line(&line);
+                        line.clear();
+                    } else {
+                        line.push(byte);
+                    }
+                }
+            }
+            handle_line(&line);
+            Ok(())
+        }.await;
+
+        if echo {
+            eprintln!();
+            STREAMING_TO_STDERR.store(false, Ordering::SeqCst);
+        }
+        result.map(|_| (String::from_utf8_lossy(&body).into_owned(), content, usage))
+    }
+
+    pub async fn chat(&self, suffix_map: &CommentMap, diff_only: bool,  file_path: &str, messages: &Vec<Message>, temperature: Option<f32>, max_tokens: Option<i32>) -> Result<UnifiedResponse> {
+        let mut request = self.build_request(messages.clone(), temperature, max_tokens);
+        if self.stream {
+            request["stream"] = json!(true);
+            // Deepseek only reports the usage of a streamed response when asked to
+            if matches!(self.provider, Provider::Deepseek) {
+                request["stream_options"] = json!({"include_usage": true});
+            }
+        }
         let headers = self.build_headers()?;
         
         let mut retries = 0;
-        let mut backoff_ms = INITIAL_BACKOFF_MS;
+        let max_retries = max_retries();
+        let mut backoff_ms = initial_backoff_ms();
+        let started = Instant::now();
     
         loop {
-            let response = self.client
-                .post(&self.base_url)
-                .headers(headers.clone())
-                .json(&request)
-                .send()
-                .await?;
-    
-            let status = response.status();
-            let response_text = response.text().await?;
+            debug!(file = file_path, attempt = retries + 1, provider = ?self.provider, model = %self.model, "Sending request");
+            let result = async {
+                let response = self.client
+                    .post(&self.base_url)
+                    .headers(headers.clone())
+                    .json(&request)
+                    .send()
+                    .await?;
+                let status = response.status();
+                if self.stream && status.is_success() {
+                    let (body, content, usage) = self.read_stream(response, file_path).await?;
+                    return Ok::<_, reqwest::Error>((status, body, content.map(|content| (content, usage))));
+                }
+                Ok((status, response.text().await?, None))
+            }.await;
+
+            let (status, response_text, streamed_content) = match result {
+                Ok(result) => result,
+                Err(e) if e.is_timeout() => {
+                    // A stalled provider is retried like a server error
+                    if retries >= max_retries {
+                        return Err(ProviderUnavailable(format!("Request timed out after {} retries: {}", max_retries, e)).into());
+                    }
+                    debug!(file = file_path, backoff_ms, "Request timed out, retrying");
+                    sleep(Duration::from_millis(with_jitter(backoff_ms))).await;
+                    retries += 1;
+                    backoff_ms = backoff_ms.saturating_mul(2);
+                    continue;
+                }
+                Err(e) if e.is_connect() => return Err(ProviderUnavailable(e.to_string()).into()),
+                Err(e) => return Err(e.into()),
+            };
+            debug!(file = file_path, status = %status, elapsed_ms = started.elapsed().as_millis() as u64, "Received response");
             
             // First check if the request was successful
             if status.is_success() {
                 // Try to parse the response
-                match self.parse_response(response_text.clone()).await {
+                let parsed_response = match streamed_content {
+                    Some((content, usage)) => Ok(UnifiedResponse { content: strip_thinking(content), usage }),
+                    None => self.parse_response(response_text.clone()).await,
+                };
+                match parsed_response {
                     Ok(parsed_response) => {
+                        // Responses that are retried below are billed as well
+                        self.record_usage(parsed_response.usage.unwrap_or_default());
                         // Check if the summary is valid
                         let summary_format_correct = check_summary(Path::new(file_path), &parsed_response.content, suffix_map);
                         if diff_only | summary_format_correct {
+                            info!(file = file_path, retries, elapsed_ms = started.elapsed().as_millis() as u64, "Summarized file");
+                            self.record_usage(TokenUsage { files: 1, ..Default::default() });
                             return Ok(parsed_response);
                         } else {
+                            debug!(file = file_path, "Summary is not a correctly formatted comment");
                             // If summary validation fails, treat it like a retriable error
-                            if retries >= MAX_RETRIES {
+                            if retries >= max_retries {
+                                self.record_usage(TokenUsage { files: 1, ..Default::default() });
                                 return Ok(parsed_response); //return badly formatted sumnmary rather than nothing
                             }
                             // Continue to retry logic
                         }
                     }
                     Err(e) => {
+                        debug!(file = file_path, error = %e, "Failed to parse response");
                         // If parsing fails and we're out of retries, bail
-                        if retries >= MAX_RETRIES {
-                            anyhow::bail!("Failed to parse response after {} retries: {}", MAX_RETRIES, e);
+                        if retries >= max_retries {
+                            anyhow::bail!("Failed to parse response after {} retries: {}", max_retries, e);
                         }
                         // Continue to retry logic
                     }
@@ -384,39 +1051,234 @@ impl UnifiedClient {
             }
     
             // Retry logic
-            if retries >= MAX_RETRIES {
+            if retries >= max_retries {
+                // Retries are only exhausted here on server errors and rate limits
+                if status.is_server_error() || status == 429 {
+                    return Err(ProviderUnavailable(format!("Max retries exceeded. Last error: {} {}", status, response_text)).into());
+                }
                 anyhow::bail!("Max retries exceeded. Last error: {} {}", status, response_text);
             }
     
-            sleep(Duration::from_millis(backoff_ms)).await;
+            debug!(file = file_path, status = %status, backoff_ms, "Retrying request");
+            sleep(Duration::from_millis(with_jitter(backoff_ms))).await;
             retries += 1;
-            backoff_ms *= 2;
+            backoff_ms = backoff_ms.saturating_mul(2);
         }
     }
 }
 
+// Settings for a summarization run that go beyond the prompt itself
+#[derive(Debug, Clone, Default)]
+pub struct SummaryOptions {
+    pub per_file_timeout: Option<u64>,
+    pub image_prompt_template: Option<String>,
+    pub resume_file: Option<String>,
+    pub strict: bool,
+    pub refusal_patterns: Vec<String>,
+    pub fallback_provider: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<i32>,
+    // Number of requests in flight at once, resolved from --concurrency or DIRSCRIBE_CONCURRENT_REQUESTS
+    pub concurrency: Option<usize>,
+    // Show a progress bar on stderr while summarizing
+    pub progress: bool,
+    // Stream responses and print them to stderr as they arrive
+    pub stream: bool,
+    // Print the tokens used once all files are summarized
+    pub show_usage: bool,
+    // Add the estimated cost to the token usage
+    pub show_cost: bool,
+    // Don't read or write the summary cache
+    pub no_cache: bool,
+    // Summarize every file again and overwrite its cached summary
+    pub refresh_cache: bool,
+    // Summarize with this provider instead of DIRSCRIBE_PROVIDER
+    pub provider_spec: Option<ProviderSpec>,
+    // Concurrency limit shared with other runs, instead of one per run
+    pub semaphore: Option<Arc<Semaphore>>,
+    // Ask for plain summaries instead of comment blocks, for summaries kept outside the source files
+    pub plain_summaries: bool,
+    // Don't print warnings and non-fatal errors to stderr
+    pub quiet: bool,
+}
+
+/// Returns the refusal pattern the response starts with, if any. Only the start counts, so a
+/// summary that mentions such a phrase, e.g. a log message of the file, is not a refusal.
+pub fn detect_refusal<'a>(content: &str, refusal_patterns: &'a [String]) -> Option<&'a str> {
+    // The comment delimiters and markers around the summary text are skipped
+    let opening = content.lines()
+        .map(|line| line.trim_start_matches(|c: char| !c.is_alphanumeric()))
+        .find(|line| !line.is_empty() && !line.starts_with("DIRSCRIBE]"))?
+        .to_lowercase();
+    refusal_patterns.iter()
+        .find(|pattern| opening.starts_with(&pattern.to_lowercase()))
+        .map(|pattern| pattern.as_str())
+}
+
+fn request_timeout_secs() -> u64 {
+    env::var("DIRSCRIBE_REQUEST_TIMEOUT_SECS").ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS)
+}
+
+fn max_retries() -> u32 {
+    env::var("DIRSCRIBE_MAX_RETRIES").ok()
+        .and_then(|retries| retries.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RETRIES)
+}
+
+fn initial_backoff_ms() -> u64 {
+    env::var("DIRSCRIBE_INITIAL_BACKOFF_MS").ok()
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(DEFAULT_INITIAL_BACKOFF_MS)
+}
+
+// Waits between half and all of the backoff, so requests that were rate limited together don't retry together
+fn with_jitter(backoff_ms: u64) -> u64 {
+    backoff_ms / 2 + fastrand::u64(0..=backoff_ms / 2)
+}
+
+/// Resolves the concurrency limit, the CLI value takes precedence over DIRSCRIBE_CONCURRENT_REQUESTS
+pub fn concurrent_requests(concurrency: Option<usize>) -> usize {
+    concurrency.unwrap_or_else(|| {
+        env::var("DIRSCRIBE_CONCURRENT_REQUESTS").unwrap_or_else(|_| DEFAULT_CONCURRENT_REQUESTS.to_string()).parse().unwrap_or(DEFAULT_CONCURRENT_REQUESTS)
+    })
+}
+
+fn client_for(options: &SummaryOptions) -> Result<(Provider, UnifiedClient)> {
+    match &options.provider_spec {
+        Some(spec) => Ok((spec.provider, UnifiedClient::from_spec(spec)?.with_streaming(options.stream))),
+        None => {
+            // Get provider from environment variable, default to Ollama if not set
+            let provider = env::var("DIRSCRIBE_PROVIDER")
+                .map(|p| Provider::from_str(&p))
+                .unwrap_or(Ok(Provider::Ollama))?;
+            Ok((provider, UnifiedClient::new(provider)?.with_streaming(options.stream)))
+        }
+    }
+}
+
+/// Checks the models of the primary provider, or of every compared provider, see [`UnifiedClient::check_model`]
+pub async fn check_models(options: &SummaryOptions, compare_providers: &[ProviderSpec]) -> Result<()> {
+    if compare_providers.is_empty() {
+        return client_for(options)?.1.check_model().await;
+    }
+    for spec in compare_providers {
+        UnifiedClient::from_spec(spec)?.check_model().await?;
+    }
+    Ok(())
+}
+
+/// Resolves the provider settings a summarization run would use. Configuration errors,
+/// like a missing API key, are reported in place of the settings.
+pub fn describe_provider_config(options: &SummaryOptions, compare_providers: &[ProviderSpec]) -> serde_json::Value {
+    let describe = |client: Result<UnifiedClient>| match client {
+        Ok(client) => client.describe(),
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+
+    let fallback = options.fallback_provider.clone()
+        .or_else(|| env::var("DIRSCRIBE_PROVIDER_FALLBACK").ok())
+        .map(|p| describe(Provider::from_str(&p).and_then(UnifiedClient::new_fallback)));
+
+    json!({
+        "primary": describe(client_for(options).map(|(_, client)| client)),
+        "fallback": fallback,
+        "compare": compare_providers.iter()
+            .map(|spec| describe(UnifiedClient::from_spec(spec)))
+            .collect::<Vec<_>>(),
+        "concurrent_requests": options.concurrency.unwrap_or(DEFAULT_CONCURRENT_REQUESTS),
+        "request_timeout_secs": request_timeout_secs(),
+        "max_retries": max_retries(),
+        "initial_backoff_ms": initial_backoff_ms(),
+        "per_file_timeout": options.per_file_timeout,
+        "temperature": options.temperature,
+        "max_tokens": options.max_tokens,
+        "strict": options.strict,
+        "cache_dir": (!options.no_cache).then(default_cache_dir),
+    })
+}
+
 pub async fn get_summaries(
     valid_files: Vec<String>, 
     file_contents: HashMap<String, String>, 
     prompt_template: String,
-    suffix_map: HashMap<&'static str, Vec<(&'static str, &'static str)>>,
-    diff_only:bool
-) -> Result<Vec<String>> {
-    // Get provider from environment variable, default to Ollama if not set
-    let provider = env::var("DIRSCRIBE_PROVIDER")
-        .map(|p| Provider::from_str(&p))
-        .unwrap_or(Ok(Provider::Ollama))?;
+    suffix_map: CommentMap,
+    diff_only:bool,
+    options: &SummaryOptions
+) -> Result<Vec<SummaryOutcome>> {
+    let per_file_timeout = options.per_file_timeout;
+    let image_prompt_template = options.image_prompt_template.clone();
+    let resume_file = options.resume_file.as_deref();
+    let strict = options.strict;
+    let quiet = options.quiet;
+    let (temperature, max_tokens) = (options.temperature, options.max_tokens);
+    let refusal_patterns = Arc::new(if options.refusal_patterns.is_empty() {
+        DEFAULT_REFUSAL_PATTERNS.iter().map(|p| p.to_string()).collect()
+    } else {
+        options.refusal_patterns.clone()
+    });
 
-    let client = Arc::new(UnifiedClient::new(provider)?);
-    let max_concurrent_requests: usize =  env::var("DIRSCRIBE_CONCURRENT_REQUESTS").unwrap_or_else(|_| DEFAULT_CONCURRENT_REQUESTS.to_string()).parse().unwrap_or(DEFAULT_CONCURRENT_REQUESTS);
+    let (provider, client) = client_for(options)?;
+    let client = Arc::new(client);
 
-    let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
+    // Summaries switch to the fallback provider for the rest of the run once the primary is unavailable
+    let fallback_provider = match options.fallback_provider.clone().or_else(|| env::var("DIRSCRIBE_PROVIDER_FALLBACK").ok()) {
+        Some(p) => Some(Provider::from_str(&p)?),
+        None => None,
+    };
+    // The fallback client is built once it is needed, so a missing fallback key doesn't fail runs
+    // in which the primary provider works; until then, the fallback isn't available either
+    let fallback: Arc<OnceLock<Option<Arc<UnifiedClient>>>> = Arc::new(OnceLock::new());
+    let stream = options.stream;
+    let fell_back = Arc::new(AtomicBool::new(false));
+    let max_concurrent_requests = options.concurrency.unwrap_or(DEFAULT_CONCURRENT_REQUESTS);
+
+    let semaphore = options.semaphore.clone()
+        .unwrap_or_else(|| Arc::new(Semaphore::new(max_concurrent_requests)));
     let suffix_map = Arc::new(suffix_map);
     
-    // Rest of the function remains the same
+    // Summaries completed by a previous, interrupted run are reused from the journal
+    let completed = match resume_file {
+        Some(path) => load_journal(path)?,
+        None => HashMap::new(),
+    };
+    let journal = match resume_file {
+        Some(path) => Some(Arc::new(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(path)
+                .with_context(|| format!("Failed to open resume file {}", path))?
+        ))),
+        None => None,
+    };
+
+    let cache = if options.no_cache {
+        None
+    } else {
+        Some(Arc::new(SummaryCache::open(default_cache_dir())?))
+    };
+
+    // Warnings from the tasks are printed through the bar, so they don't tear it
+    let progress = if options.progress {
+        let bar = ProgressBar::new(valid_files.len() as u64);
+        bar.set_style(ProgressStyle::with_template("{elapsed_precise} [{bar:40}] {pos}/{len} files summarized")?
+            .progress_chars("=> "));
+        bar
+    } else {
+        ProgressBar::hidden()
+    };
+
     let mut handles = Vec::new();
     
     for file_path in valid_files {
+        if let Some(summary) = completed.get(&file_path) {
+            debug!(file = %file_path, "Reusing summary from resume file");
+            let summary = summary.clone();
+            progress.inc(1);
+            handles.push(tokio::spawn(async move { Ok(summary) }));
+            continue;
+        }
+
         let permit = semaphore.clone().acquire_owned().await?;
         let content = file_contents.get(&file_path).unwrap_or(&String::new()).clone();
         let processed_content = filter_dirscribe_sections(&content, true);
@@ -424,23 +1286,60 @@ pub async fn get_summaries(
         let client = client.clone();
         let suffix_map = Arc::clone(&suffix_map);
         let prompt_template = prompt_template.clone();
+        let journal = journal.clone();
+        let refusal_patterns = Arc::clone(&refusal_patterns);
+        let fallback = Arc::clone(&fallback);
+        let fell_back = Arc::clone(&fell_back);
 
         let extension = Path::new(&file_path)
             .extension()
             .and_then(|ext| ext.to_str())
             .unwrap_or(""); 
 
-        let prompt_base = prompt_template.replace("${${CONTENT}$}$", &processed_content);
-        let prompt = if !diff_only {
+        // Images are sent as attachments, described with their own prompt
+        let image = match (&image_prompt_template, image_media_type(Path::new(&file_path))) {
+            (Some(_), Some(media_type)) => Some(ImageContent {
+                media_type: media_type.to_string(),
+                data: content.clone(),
+            }),
+            _ => None,
+        };
+
+        if image.is_some() && !provider.supports_images() {
+            drop(permit);
+            progress.inc(1);
+            handles.push(tokio::spawn(async move {
+                Err(anyhow::anyhow!("Error processing file {}: provider {:?} does not support images", file_path_clone, provider))
+            }));
+            continue;
+        }
+
+        // Templates that place the comment delimiters themselves don't get the formatting instruction appended
+        let has_comment_placeholders = prompt_template.contains(COMMENT_START_PLACEHOLDER)
+            || prompt_template.contains(COMMENT_END_PLACEHOLDER);
+        let (comment_start, comment_end) = match suffix_map.get(extension) {
+            _ if options.plain_summaries => ("", ""),
+            // Line comments have no closing delimiter
+            Some(comment_chars) if comment_chars[0].1 == "\n" => (comment_chars[0].0.as_ref(), ""),
+            Some(comment_chars) => (comment_chars[0].0.as_ref(), comment_chars[0].1.as_ref()),
+            None => ("", ""),
+        };
+        let prompt_base = prompt_template
+            .replace(COMMENT_START_PLACEHOLDER, comment_start)
+            .replace(COMMENT_END_PLACEHOLDER, comment_end)
+            .replace("${${CONTENT}$}$", &processed_content);
+        let prompt = if let (Some(image_prompt), Some(_)) = (&image_prompt_template, &image) {
+            image_prompt.clone()
+        } else if !diff_only && !has_comment_placeholders && !options.plain_summaries {
             if let Some(comment_chars) = suffix_map.get(extension)  {
-                let (multi_line_comment_start, multi_line_comment_end) = comment_chars[0];
+                let (multi_line_comment_start, multi_line_comment_end) = &comment_chars[0];
                  
-                if multi_line_comment_end != "single line" {
+                if multi_line_comment_end != "\n" {
                     prompt_base.to_owned() + &format!("\n\nPlease use the following structure: line 1: '{}', line 2: '[DIRSCRIBE]', lines 3 to N -2: *the summary*, line N-1: '[/DIRSCRIBE]', line N: '{}'. The appropriate start and end of a multiline comment are '{}' and '{}', respectively.", 
                         multi_line_comment_start, multi_line_comment_end, multi_line_comment_start, multi_line_comment_end)
                 } else {
-                    prompt_base.to_owned() + &format!("\n\nPlease make sure to start every line of the summary with '{}'. Please use the following structure: line 1: '{}', line 2: '{} [DIRSCRIBE]', lines 3 to N -2: *the summary*, line N-1: '{} [/DIRSCRIBE]', line N: '{}'. The appropriate start and end of a multiline comment are '{}' and '{}', respectively.", 
-                        multi_line_comment_start, multi_line_comment_start, multi_line_comment_start, multi_line_comment_start, multi_line_comment_start, multi_line_comment_start, multi_line_comment_end)
+                    prompt_base.to_owned() + &format!("\n\nPlease make sure to start every line of the summary with '{0}'. Please use the following structure: line 1: '{0}', line 2: '{0} [DIRSCRIBE]', lines 3 to N -2: *the summary*, line N-1: '{0} [/DIRSCRIBE]', line N: '{0}'.",
+                        multi_line_comment_start)
                 }
             } else {
                 prompt_base.to_owned() + &"\n\nPlease make sure to return the summary as a comment block appropriately formatted for the language, with this structure: line 1: , line 2: [DIRSCRIBE], line N-1: [/DIRSCRIBE], line N: . Lines 1 and N should be empty."
@@ -449,58 +1348,403 @@ pub async fn get_summaries(
             prompt_base.to_string()
         };
 
+        // Image descriptions and plain summaries aren't comments, so the format check is skipped for them
+        let skip_format_check = diff_only || image.is_some() || options.plain_summaries;
+
         let messages: Vec<Message> = vec![Message {
             role: "user".to_string(),
             content: prompt,
+            image,
         }];
 
-        let handle = tokio::spawn(async move {
-            let result = client.chat(&suffix_map, diff_only, &file_path_clone, &messages, None, None).await;
+        let cache_key = cache.as_ref().map(|_| client.cache_key(&messages, temperature, max_tokens));
+        if let (false, Some(cache), Some(key)) = (options.refresh_cache, &cache, &cache_key) {
+            if let Some(summary) = cache.get(key) {
+                debug!(file = %file_path, "Reusing summary from cache");
+                drop(permit);
+                progress.inc(1);
+                handles.push(tokio::spawn(async move { Ok(summary) }));
+                continue;
+            }
+        }
+        let cache = cache.clone();
+
+        let bar = progress.clone();
+        let task = async move {
+            let chat = async {
+                if let (Some(Some(fallback)), true) = (fallback.get(), fell_back.load(Ordering::SeqCst)) {
+                    return fallback.chat(&suffix_map, skip_format_check, &file_path_clone, &messages, temperature, max_tokens).await;
+                }
+                match (client.chat(&suffix_map, skip_format_check, &file_path_clone, &messages, temperature, max_tokens).await, fallback_provider) {
+                    (Err(e), Some(fallback_provider)) if e.is::<ProviderUnavailable>() => {
+                        let fallback = fallback.get_or_init(|| match UnifiedClient::new_fallback(fallback_provider) {
+                            Ok(fallback) => Some(Arc::new(fallback.with_streaming(stream))),
+                            Err(fallback_error) => {
+                                if !quiet {
+                                    bar.suspend(|| eprintln!("{} provider {:?} is unavailable ({}), and fallback provider {:?} can't be used: {}", yellow("Warning:"), provider, e, fallback_provider, fallback_error));
+                                }
+                                None
+                            }
+                        });
+                        let Some(fallback) = fallback else {
+                            return Err(e);
+                        };
+                        if !fell_back.swap(true, Ordering::SeqCst) && !quiet {
+                            bar.suspend(|| eprintln!("{} provider {:?} is unavailable ({}), falling back to {:?}", yellow("Warning:"), provider, e, fallback.provider));
+                        }
+                        fallback.chat(&suffix_map, skip_format_check, &file_path_clone, &messages, temperature, max_tokens).await
+                    },
+                    (result, _) => result,
+                }
+            };
+            let result = match per_file_timeout {
+                Some(seconds) => match timeout(Duration::from_secs(seconds), chat).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        drop(permit);
+                        if !quiet {
+                            bar.suspend(|| eprintln!("Skipping file {}: summarization timed out after {} seconds", file_path_clone, seconds));
+                        }
+                        return Err(SummaryTimedOut(seconds).into());
+                    }
+                },
+                None => chat.await
+            };
             drop(permit);
             match result {
-                Ok(response) => Ok(response.content),
+                Ok(response) => {
+                    let refusal = detect_refusal(&response.content, &refusal_patterns);
+                    if let Some(pattern) = refusal {
+                        if strict {
+                            return Err(anyhow::anyhow!("Error processing file {}: provider refused to summarize (matched \"{}\")", file_path_clone, pattern));
+                        }
+                        if !quiet {
+                            bar.suspend(|| eprintln!("{} summary of {} looks like a refusal (matched \"{}\")", yellow("Warning:"), file_path_clone, pattern));
+                        }
+                    }
+                    // Refusals are worth retrying, and summaries from the fallback don't belong to the primary's key
+                    if let (None, Some(cache), Some(key)) = (refusal, &cache, &cache_key) {
+                        if !fell_back.load(Ordering::SeqCst) {
+                            if let (Err(e), false) = (cache.put(key, &response.content), quiet) {
+                                bar.suspend(|| eprintln!("Error writing {} to cache: {}", file_path_clone, e));
+                            }
+                        }
+                    }
+                    if let Some(journal) = journal {
+                        if let (Err(e), false) = (append_to_journal(&journal, &file_path_clone, &response.content), quiet) {
+                            bar.suspend(|| eprintln!("Error writing {} to resume file: {}", file_path_clone, e));
+                        }
+                    }
+                    Ok(response.content)
+                },
                 Err(e) => Err(anyhow::anyhow!("Error processing file {}: {}", file_path_clone, e))
             }
-        });
-        
-        handles.push(handle);
+        };
+
+        // Tasks finish in any order, so each one advances the bar itself
+        let progress = progress.clone();
+        handles.push(tokio::spawn(async move {
+            let result = task.await;
+            progress.inc(1);
+            result
+        }));
     }
     
     let mut results = Vec::new();
+    let mut all_succeeded = true;
     for handle in handles {
         match handle.await? {
-            Ok(content) => results.push(content),
-            Err(e) => results.push(format!("Error: {}", e)),
+            Ok(content) => results.push(SummaryOutcome::Ok(content)),
+            Err(e) => {
+                all_succeeded = false;
+                // Timed out files are skipped rather than failed, both count as not summarized
+                results.push(match e.downcast_ref::<SummaryTimedOut>() {
+                    Some(timed_out) => SummaryOutcome::Skipped(timed_out.to_string()),
+                    None => SummaryOutcome::Failed(e.to_string()),
+                })
+            },
+        }
+    }
+    progress.finish_and_clear();
+
+    if options.show_usage {
+        let clients: Vec<&UnifiedClient> = std::iter::once(&*client).chain(fallback.get().and_then(Option::as_deref)).collect();
+        report_usage(&clients, options.show_cost);
+    }
+
+    // The journal is only needed until every file has been summarized
+    if let Some(path) = resume_file {
+        if all_succeeded {
+            fs::remove_file(path).with_context(|| format!("Failed to clear resume file {}", path))?;
         }
     }
     Ok(results)
 }
 
-pub fn check_summary(file_path: &Path, s: &str, suffix_map: &HashMap<&'static str, Vec<(&'static str, &'static str)>>) -> bool {
+const DIRECTORY_PLACEHOLDER: &str = "${${DIRECTORY}$}$";
+
+/// Synthesizes a summary for every directory from the summaries of its files and subdirectories,
+/// working bottom-up so the summary of `dir_path` covers the whole tree; directories above it are
+/// left out, and so are files and subdirectories without a summary. Returns (directory, outcome)
+/// pairs with every directory listed before its subdirectories.
+pub async fn get_directory_summaries(
+    dir_path: &Path,
+    valid_files: &[String],
+    summaries: &[SummaryOutcome],
+    prompt_template: String,
+    options: &SummaryOptions
+) -> Result<Vec<(String, SummaryOutcome)>> {
+    let (_, client) = client_for(options)?;
+    let client = Arc::new(client);
+    let (temperature, max_tokens) = (options.temperature, options.max_tokens);
+    let max_concurrent_requests = options.concurrency.unwrap_or(DEFAULT_CONCURRENT_REQUESTS);
+    let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
+    let suffix_map = Arc::new(HashMap::new());
+
+    // Every directory on the way from a file up to the processed directory takes part
+    let mut file_summaries: BTreeMap<PathBuf, Vec<(String, String)>> = BTreeMap::new();
+    let mut subdirectories: BTreeMap<PathBuf, BTreeSet<PathBuf>> = BTreeMap::new();
+    for (file, outcome) in valid_files.iter().zip(summaries) {
+        let path = Path::new(file);
+        let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        if let SummaryOutcome::Ok(summary) = outcome {
+            file_summaries.entry(parent.clone()).or_default().push((name, summary.clone()));
+        }
+
+        let mut directory = parent;
+        subdirectories.entry(directory.clone()).or_default();
+        while let Some(parent) = directory.parent().filter(|p| p.starts_with(dir_path)) {
+            subdirectories.entry(parent.to_path_buf()).or_default().insert(directory.clone());
+            directory = parent.to_path_buf();
+        }
+    }
+
+    // Deepest directories first, each level waits for the one below it
+    let mut levels: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+    for directory in subdirectories.keys() {
+        levels.entry(directory.components().count()).or_default().push(directory.clone());
+    }
+
+    let mut directory_summaries: HashMap<PathBuf, SummaryOutcome> = HashMap::new();
+    for (_, directories) in levels.into_iter().rev() {
+        let mut handles = Vec::new();
+        for directory in directories {
+            let mut content = String::new();
+            for (name, summary) in file_summaries.get(&directory).into_iter().flatten() {
+                content.push_str(&format!("File {}:\n{}\n\n", name, summary));
+            }
+            for subdirectory in &subdirectories[&directory] {
+                if let SummaryOutcome::Ok(summary) = &directory_summaries[subdirectory] {
+                    content.push_str(&format!("Directory {}:\n{}\n\n", subdirectory.display(), summary));
+                }
+            }
+            // Nothing below the directory was summarized, so there is nothing to synthesize
+            if content.is_empty() {
+                directory_summaries.insert(directory, SummaryOutcome::Skipped("no file or subdirectory below it was summarized".to_string()));
+                continue;
+            }
+
+            let directory_name = directory.display().to_string();
+            let messages = vec![Message {
+                role: "user".to_string(),
+                content: prompt_template
+                    .replace(DIRECTORY_PLACEHOLDER, &directory_name)
+                    .replace("${${CONTENT}$}$", &content),
+                image: None,
+            }];
+            let permit = semaphore.clone().acquire_owned().await?;
+            let client = client.clone();
+            let suffix_map = Arc::clone(&suffix_map);
+            handles.push((directory, tokio::spawn(async move {
+                // Directory summaries are plain text, so the comment format check is skipped
+                let result = client.chat(&suffix_map, true, &directory_name, &messages, temperature, max_tokens).await;
+                drop(permit);
+                match result {
+                    Ok(response) => SummaryOutcome::Ok(response.content),
+                    Err(e) => SummaryOutcome::Failed(format!("Error processing directory {}: {}", directory_name, e)),
+                }
+            })));
+        }
+        for (directory, handle) in handles {
+            directory_summaries.insert(directory, handle.await?);
+        }
+    }
+
+    // Paths sort component-wise, so every directory comes right before its subdirectories
+    let mut ordered: Vec<(PathBuf, SummaryOutcome)> = directory_summaries.into_iter().collect();
+    ordered.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(ordered.into_iter()
+        .map(|(directory, outcome)| (directory.display().to_string(), outcome))
+        .collect())
+}
+
+// Approximate number of tokens of file summaries or contents sent along for the overview
+const OVERVIEW_INPUT_TOKENS: usize = 50_000;
+
+/// Asks for an architectural overview of the whole code base in a single request, from the file list
+/// and the summaries or contents of the files. Files that don't fit into the input budget are left
+/// out, the largest first; the file list is always sent in full.
+pub async fn get_overview(
+    valid_files: &[String],
+    contents: &[String],
+    prompt_template: String,
+    model: Option<&str>,
+    options: &SummaryOptions
+) -> Result<String> {
+    // A second client, so only the overview uses the overview model
+    let client = client_for(options)?.1.with_model(model);
+
+    let file_list = format!("Files:\n{}\n\n", valid_files.join("\n"));
+    let blocks: Vec<String> = valid_files.iter().zip(contents)
+        .map(|(file, content)| format!("File {}:\n{}\n\n", file, content))
+        .collect();
+    let budget = (OVERVIEW_INPUT_TOKENS * BYTES_PER_TOKEN).saturating_sub(file_list.len());
+    let content = file_list + &drop_largest_blocks(&blocks, budget).concat();
+
+    let messages = vec![Message {
+        role: "user".to_string(),
+        content: prompt_template.replace("${${CONTENT}$}$", &content),
+        image: None,
+    }];
+    // The overview is plain text, so the comment format check is skipped
+    let response = client.chat(&HashMap::new(), true, "overview", &messages, options.temperature, options.max_tokens).await
+        .context("Failed to generate the overview")?;
+    Ok(response.content)
+}
+
+/// Summarizes the files with every provider at once, sharing one concurrency limit.
+/// Returns the outcomes per file, in the order of the providers; see [`render_comparison`].
+pub async fn compare_summaries(
+    valid_files: Vec<String>,
+    file_contents: HashMap<String, String>,
+    prompt_template: String,
+    suffix_map: CommentMap,
+    diff_only: bool,
+    options: &SummaryOptions,
+    providers: &[ProviderSpec]
+) -> Result<Vec<Vec<SummaryOutcome>>> {
+    let max_concurrent_requests = options.concurrency.unwrap_or(DEFAULT_CONCURRENT_REQUESTS);
+    let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
+
+    let mut handles = Vec::new();
+    for spec in providers {
+        // Several bars at once would overwrite each other
+        let options = SummaryOptions {
+            provider_spec: Some(spec.clone()),
+            semaphore: Some(semaphore.clone()),
+            progress: false,
+            ..options.clone()
+        };
+        let valid_files = valid_files.clone();
+        let file_contents = file_contents.clone();
+        let prompt_template = prompt_template.clone();
+        let suffix_map = suffix_map.clone();
+        handles.push(tokio::spawn(async move {
+            get_summaries(valid_files, file_contents, prompt_template, suffix_map, diff_only, &options).await
+        }));
+    }
+
+    let mut provider_summaries = Vec::new();
+    for handle in handles {
+        let summaries = handle.await?
+            .unwrap_or_else(|e| vec![SummaryOutcome::Failed(e.to_string()); valid_files.len()]);
+        provider_summaries.push(summaries);
+    }
+
+    Ok((0..valid_files.len())
+        .map(|i| provider_summaries.iter()
+            .map(|summaries| summaries[i].clone())
+            .collect())
+        .collect())
+}
+
+/// Labels each provider's summary of a file, as returned by [`compare_summaries`]
+pub fn render_comparison(providers: &[ProviderSpec], outcomes: &[SummaryOutcome]) -> String {
+    providers.iter().zip(outcomes)
+        .map(|(spec, outcome)| format!("[{}]\n{}", spec, outcome))
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    file: String,
+    summary: String,
+}
+
+fn load_journal(path: &str) -> Result<HashMap<String, String>> {
+    if !Path::new(path).exists() {
+        return Ok(HashMap::new());
+    }
+
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read resume file {}", path))?;
+    // A line cut off by the interruption is ignored, that file is summarized again
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<JournalEntry>(line).ok())
+        .map(|entry| (entry.file, entry.summary))
+        .collect())
+}
+
+fn append_to_journal(journal: &Mutex<fs::File>, file: &str, summary: &str) -> Result<()> {
+    let line = serde_json::to_string(&JournalEntry {
+        file: file.to_string(),
+        summary: summary.to_string(),
+    })?;
+    let mut journal = journal.lock().map_err(|_| anyhow::anyhow!("Resume file lock poisoned"))?;
+    writeln!(journal, "{}", line)?;
+    journal.flush()?;
+    Ok(())
+}
+
+// Both Python docstring quotes are accepted, whichever one the comment map lists
+fn delimiter_variants(delimiter: &str) -> Vec<&str> {
+    match delimiter {
+        "\"\"\"" | "'''" => vec!["\"\"\"", "'''"],
+        _ => vec![delimiter],
+    }
+}
+
+// Doc comment openers like `/**` or `/*!` start with the plain opener
+fn opens_comment(line: &str, start: &str) -> bool {
+    delimiter_variants(start).iter().any(|start| line.trim().starts_with(start))
+}
+
+// Closers may carry extra stars, like `**/`, and surrounding whitespace
+fn closes_comment(line: &str, end: &str) -> bool {
+    let line = line.trim();
+    delimiter_variants(end).iter()
+        .any(|end| line.strip_suffix(end).is_some_and(|rest| rest.chars().all(|c| c == '*')))
+}
+
+pub fn check_summary(file_path: &Path, s: &str, suffix_map: &CommentMap) -> bool {
     let extension = file_path.extension()
         .and_then(|ext| ext.to_str())
         .unwrap_or(""); 
     if let Some(comment_chars) = suffix_map.get(extension) {
-        for (multi_line_comment_start, multi_line_comment_end) in comment_chars {
+        for (multi_line_comment_start, multi_line_comment_end) in comment_chars.iter().map(|(start, end)| (start.as_ref(), end.as_ref())) {
             let lines: Vec<&str> = s.trim().split('\n').collect();
             if lines.len() < 4 {
                 continue;
             }
             
-            if *multi_line_comment_end != "\n" {
-                let comment_start = lines[0].trim().starts_with(multi_line_comment_start);
+            if multi_line_comment_end != "\n" {
+                let comment_start = opens_comment(lines[0], multi_line_comment_start);
                 let dirscribe_start = lines[1].trim() == "[DIRSCRIBE]";
                 let dirscribe_end = lines[lines.len() - 2].trim() == "[/DIRSCRIBE]";
-                let comment_end = lines[lines.len() - 1].trim() == *multi_line_comment_end;
+                let comment_end = closes_comment(lines[lines.len() - 1], multi_line_comment_end);
                 
                 if comment_start && dirscribe_start && dirscribe_end && comment_end {
                     return true;
                 }
             } else {
+                // Line comments have no closing delimiter, every line starts with the prefix instead
                 let comment_start = lines[0].trim().starts_with(multi_line_comment_start);
                 let dirscribe_start = lines[1].trim() == format!("{} [DIRSCRIBE]", multi_line_comment_start);
                 let dirscribe_end = lines[lines.len() - 2].trim() == format!("{} [/DIRSCRIBE]", multi_line_comment_start);
-                let comment_end = lines[lines.len() - 1].trim() == *multi_line_comment_end;
+                let comment_end = lines.iter().all(|line| line.trim().starts_with(multi_line_comment_start));
                 
                 if comment_start && dirscribe_start && dirscribe_end && comment_end {
                     return true;
@@ -1,6 +1,13 @@
 use std::path::{Path, PathBuf};
 use crate::cli::Cli;
+use crate::file_processing::{split_keywords, CommentStyle, SummaryPosition, OUTPUT_SECTIONS};
+use crate::output::{parse_size, OutputFormat};
 use git2::Repository;
+use regex::Regex;
+use crate::summary::{Provider, ProviderSpec};
+use crate::git::get_latest_tag;
+use crate::prompt_handling::load_prompts;
+use std::str::FromStr;
 use anyhow::Result;
 use std::error::Error;
 use std::fmt;
@@ -29,8 +36,34 @@ impl From<&str> for ValidationError {
 }
 
 pub fn validate_cli_args(cli: &Cli) -> Result<()> {
+    if cli.suffixes.is_none() && cli.files_from.is_none() && !cli.summarize_stdin && !cli.dump_config {
+        return Err(ValidationError("No file extensions given, pass them as the first argument or set suffixes in the config file".into()).into());
+    }
+
     // Validate suffixes
-    validate_suffixes(&cli.suffixes)?;
+    if let Some(suffixes) = &cli.suffixes {
+        validate_suffixes(suffixes)?;
+    }
+
+    if let Some(stdin_ext) = &cli.stdin_ext {
+        if !cli.summarize_stdin {
+            return Err(ValidationError("--stdin-ext can only be used with --summarize-stdin".into()).into());
+        }
+        validate_suffixes(stdin_ext)?;
+    }
+
+    if cli.summarize_stdin && (cli.apply || cli.retrieve || cli.diff_only) {
+        return Err(ValidationError("--summarize-stdin cannot be used with --apply, --retrieve or --diff-only".into()).into());
+    }
+
+    if let Some(files_from) = &cli.files_from {
+        if cli.summarize_stdin {
+            return Err(ValidationError("--files-from cannot be used with --summarize-stdin".into()).into());
+        }
+        if files_from != "-" && !Path::new(files_from).is_file() {
+            return Err(ValidationError(format!("File list not found: {}", files_from)).into());
+        }
+    }
 
     // Validate paths
     if let Some(template_path) = &cli.prompt_template_path {
@@ -41,17 +74,131 @@ pub fn validate_cli_args(cli: &Cli) -> Result<()> {
         validate_output_path(output_path)?;
     }
 
-    // Validate git-related arguments
-    validate_git_args(
-        cli.diff_only,
-        &cli.start_commit_id,
-        &cli.end_commit_id,
-    )?;
+    if cli.stdout && cli.output_path.is_some() {
+        return Err(ValidationError("--stdout and --output-path cannot be used together".into()).into());
+    }
+
+    if cli.dry_run && (cli.summarize_stdin || cli.apply || cli.format == OutputFormat::Json) {
+        return Err(ValidationError("--dry-run cannot be used with --summarize-stdin, --apply or --format json".into()).into());
+    }
+
+    validate_section_order(&cli.section_order)?;
+
+    if cli.content_only && (cli.classify || cli.show_metadata || cli.diffstat || cli.list_commits || cli.diff_line_ranges || cli.diff_with_content || cli.include_commit_messages) {
+        return Err(ValidationError("--content-only cannot be used with options that add headers or annotations".into()).into());
+    }
+
+    if cli.format == OutputFormat::Json && (cli.content_only || cli.line_numbers || cli.diffstat || cli.list_commits || cli.classify || cli.tree || cli.show_metadata || cli.diff_line_ranges || cli.diff_with_content
+        || cli.include_commit_messages || cli.hierarchical_summary || cli.overview || cli.max_output_bytes.is_some() || cli.max_output_tokens.is_some() || cli.split_size.is_some()) {
+        return Err(ValidationError("--format json cannot be used with options that change the layout of the text output".into()).into());
+    }
+
+    for pattern in &cli.strip_lines {
+        Regex::new(pattern).map_err(|e| ValidationError(format!("Invalid --strip-lines pattern '{}': {}", pattern, e)))?;
+    }
+
+    if cli.max_output_bytes == Some(0) {
+        return Err(ValidationError("--max-output-bytes must be greater than 0".into()).into());
+    }
+
+    if cli.max_output_tokens == Some(0) {
+        return Err(ValidationError("--max-output-tokens must be greater than 0".into()).into());
+    }
+
+    if let Some(output_dir) = &cli.output_dir {
+        if cli.output_path.is_some() || cli.stdout {
+            return Err(ValidationError("--output-dir cannot be used with --output-path or --stdout".into()).into());
+        }
+        if cli.summarize_stdin {
+            return Err(ValidationError("--output-dir cannot be used with --summarize-stdin".into()).into());
+        }
+        if cli.dry_run || cli.format == OutputFormat::Json || cli.content_only || cli.prompt_template_path.is_some() || cli.split_size.is_some()
+            || cli.max_output_bytes.is_some() || cli.max_output_tokens.is_some() || cli.hierarchical_summary || cli.overview {
+            return Err(ValidationError("--output-dir only writes the file contents or summaries and cannot be used with options that shape the combined output".into()).into());
+        }
+        if Path::new(output_dir).is_file() {
+            return Err(ValidationError(format!("Output directory is a file: {}", output_dir)).into());
+        }
+    }
+
+    if cli.quiet && cli.verbose > 0 {
+        return Err(ValidationError("--quiet and --verbose cannot be used together".into()).into());
+    }
+
+    if cli.max_output_bytes.is_some() && cli.max_output_tokens.is_some() {
+        return Err(ValidationError("--max-output-bytes and --max-output-tokens cannot be used together".into()).into());
+    }
+
+    if let Some(split_size) = &cli.split_size {
+        if cli.output_path.is_none() {
+            return Err(ValidationError("--split-size can only be used with --output-path".into()).into());
+        }
+        parse_size(split_size).map_err(|e| ValidationError(e.to_string()))?;
+    }
+
+    if cli.staged && !cli.diff_only {
+        return Err(ValidationError("--staged can only be used with --diff-only".into()).into());
+    }
+    if cli.staged && (cli.start_commit_id.is_some() || cli.end_commit_id.is_some() || cli.pr_base.is_some() || cli.since_tag) {
+        return Err(ValidationError("--staged cannot be used with --start-commit-id, --end-commit-id, --pr-base or --since-tag".into()).into());
+    }
+
+    if cli.include_untracked && (!cli.diff_only || cli.staged || cli.end_commit_id.is_some()) {
+        return Err(ValidationError("--include-untracked can only be used with --diff-only when diffing against the working directory, not with --staged or --end-commit-id".into()).into());
+    }
+
+    // Validate git-related arguments; --pr-base and --since-tag determine the start commit themselves
+    if cli.since_tag && cli.pr_base.is_some() {
+        return Err(ValidationError("--since-tag and --pr-base cannot be used together".into()).into());
+    }
+    if let Some(pr_base) = &cli.pr_base {
+        validate_pr_base(pr_base, &cli.start_commit_id, &cli.end_commit_id)?;
+    } else if cli.since_tag {
+        validate_since_tag(&cli.start_commit_id, &cli.end_commit_id)?;
+    } else {
+        validate_git_args(
+            cli.diff_only,
+            &cli.start_commit_id,
+            &cli.end_commit_id,
+        )?;
+    }
+
+    if let Some(exclude_paths_file) = &cli.exclude_paths_file {
+        validate_paths_file(exclude_paths_file, "Exclude")?;
+    }
+
+    if let Some(include_paths_file) = &cli.include_paths_file {
+        validate_paths_file(include_paths_file, "Include")?;
+    }
+
+    if let Some(only_matching_file) = &cli.only_matching_file {
+        validate_paths_file(only_matching_file, "Whitelist")?;
+    }
+
+    if let Some(exclude_dirs) = &cli.exclude_dirs {
+        if exclude_dirs.split(',').any(|d| d.is_empty() || d.contains('/') || d.contains('\\')) {
+            return Err(ValidationError("--exclude-dirs takes directory names, not paths".into()).into());
+        }
+    }
+
+    if let Some(exclude_extensions) = &cli.exclude_extensions {
+        if cli.suffixes.as_deref() != Some("*") {
+            return Err(ValidationError("--exclude-extensions can only be used when processing all files with \"*\"".into()).into());
+        }
+        if exclude_extensions == "*" {
+            return Err(ValidationError("--exclude-extensions takes file extensions, not \"*\"".into()).into());
+        }
+        validate_suffixes(exclude_extensions)?;
+    }
 
     // Validate keywords
-    validate_keywords(&cli.or_keywords, "or_keywords")?;
-    validate_keywords(&cli.and_keywords, "and_keywords")?;
-    validate_keywords(&cli.exclude_keywords, "exclude_keywords")?;
+    validate_keywords(&split_keywords(&cli.or_keywords, cli.regex_keywords), "or_keywords", cli.regex_keywords)?;
+    validate_keywords(&split_keywords(&cli.and_keywords, cli.regex_keywords), "and_keywords", cli.regex_keywords)?;
+    validate_keywords(&split_keywords(&cli.exclude_keywords, cli.regex_keywords), "exclude_keywords", cli.regex_keywords)?;
+
+    if cli.keyword_stats && cli.or_keywords.is_empty() {
+        return Err(ValidationError("--keyword-stats can only be used with --or-keywords".into()).into());
+    }
 
     // Validate exclude/include paths
     validate_path_filters(
@@ -67,14 +214,183 @@ pub fn validate_cli_args(cli: &Cli) -> Result<()> {
         return Err(ValidationError("--retrieve can only be used with --summarize or --summarize_keywords".into()).into());
     }
 
+    if cli.summarize_binary && (!cli.summarize && !cli.summarize_keywords) {
+        return Err(ValidationError("--summarize-binary can only be used with --summarize or --summarize_keywords".into()).into());
+    }
+
+    if cli.summarize_binary && (cli.diff_only || cli.retrieve) {
+        return Err(ValidationError("--summarize-binary cannot be used with --diff-only or --retrieve".into()).into());
+    }
+
+    if cli.resume_file.is_some() && (!cli.summarize && !cli.summarize_keywords) {
+        return Err(ValidationError("--resume-file can only be used with --summarize or --summarize_keywords".into()).into());
+    }
+
+    if (cli.no_cache || cli.refresh_cache) && !(cli.summarize || cli.summarize_keywords || cli.summarize_stdin) {
+        return Err(ValidationError("--no-cache and --refresh-cache can only be used when summarizing".into()).into());
+    }
+
+    if cli.show_cost && !(cli.summarize || cli.summarize_keywords || cli.summarize_stdin) {
+        return Err(ValidationError("--show-cost can only be used when summarizing".into()).into());
+    }
+
+    if cli.no_cache && cli.refresh_cache {
+        return Err(ValidationError("--no-cache and --refresh-cache cannot be used together".into()).into());
+    }
+
+    if cli.resume_file.is_some() && cli.retrieve {
+        return Err(ValidationError("--resume-file cannot be used with --retrieve".into()).into());
+    }
+
+    if cli.sidecar && ((!cli.summarize && !cli.summarize_keywords) || cli.apply || cli.retrieve || cli.diff_only) {
+        return Err(ValidationError("--sidecar can only be used with --summarize or --summarize-keywords, and not with --apply, --retrieve or --diff-only".into()).into());
+    }
+
+    match cli.comment_style {
+        Some(CommentStyle::Line) if cli.comment_prefix.is_none() || cli.comment_open.is_some() || cli.comment_close.is_some() => {
+            return Err(ValidationError("--comment-style line requires --comment-prefix, and no --comment-open or --comment-close".into()).into());
+        },
+        Some(CommentStyle::Block) if cli.comment_open.is_none() || cli.comment_close.is_none() || cli.comment_prefix.is_some() => {
+            return Err(ValidationError("--comment-style block requires --comment-open and --comment-close, and no --comment-prefix".into()).into());
+        },
+        None if cli.comment_prefix.is_some() || cli.comment_open.is_some() || cli.comment_close.is_some() => {
+            return Err(ValidationError("--comment-prefix, --comment-open and --comment-close require --comment-style".into()).into());
+        },
+        _ => {},
+    }
+    if cli.comment_style.is_some() && ((!cli.summarize && !cli.summarize_keywords) || cli.sidecar || cli.diff_only) {
+        return Err(ValidationError("--comment-style can only be used with --summarize or --summarize-keywords, and not with --sidecar or --diff-only".into()).into());
+    }
+
+    if let Some(prompt_version) = &cli.prompt_version {
+        if !cli.summarize && !cli.summarize_keywords && !cli.summarize_stdin {
+            return Err(ValidationError("--prompt-version can only be used with --summarize, --summarize_keywords or --summarize-stdin".into()).into());
+        }
+        // The prompts are compiled in, so they are available here already
+        let prompts = load_prompts("prompts")?;
+        if !prompts.contains_key(prompt_version) {
+            let mut available: Vec<&str> = prompts.keys().map(String::as_str).collect();
+            available.sort();
+            return Err(ValidationError(format!("Unknown prompt version '{}', available versions: {}", prompt_version, available.join(", "))).into());
+        }
+    }
+
+    if cli.check_model && !cli.summarize && !cli.summarize_keywords && !cli.summarize_stdin {
+        return Err(ValidationError("--check-model can only be used with --summarize, --summarize_keywords or --summarize-stdin".into()).into());
+    }
+
+    if cli.summary_position != SummaryPosition::Top && !cli.apply {
+        return Err(ValidationError("--summary-position can only be used with --apply".into()).into());
+    }
+
     if cli.apply && cli.diff_only {
         return Err(ValidationError("--apply cannot be used with --diff-only".into()).into());
     }
 
+    if cli.preprocess_cmd.is_some() && cli.diff_only {
+        return Err(ValidationError("--preprocess-cmd cannot be used with --diff-only".into()).into());
+    }
+
+    if cli.diff_line_ranges && !cli.diff_only {
+        return Err(ValidationError("--diff-line-ranges can only be used with --diff-only".into()).into());
+    }
+
+    if cli.ignore_whitespace && !cli.diff_only {
+        return Err(ValidationError("--ignore-whitespace can only be used with --diff-only".into()).into());
+    }
+
+    if cli.diffstat && !cli.diff_only {
+        return Err(ValidationError("--diffstat can only be used with --diff-only".into()).into());
+    }
+
+    if cli.diff_with_content && !cli.diff_only {
+        return Err(ValidationError("--diff-with-content can only be used with --diff-only".into()).into());
+    }
+
+    // --pr-base and --since-tag set the start commit after validation
+    let has_start_commit = cli.start_commit_id.is_some() || cli.pr_base.is_some() || cli.since_tag;
+    if cli.list_commits && !(cli.diff_only && has_start_commit) {
+        return Err(ValidationError("--list-commits requires --diff-only with a start commit, --pr-base or --since-tag".into()).into());
+    }
+
+    if cli.include_commit_messages && !cli.diff_only {
+        return Err(ValidationError("--include-commit-messages can only be used with --diff-only".into()).into());
+    }
+
+    if cli.diff_with_content && (cli.summarize || cli.summarize_keywords) {
+        return Err(ValidationError("--diff-with-content cannot be used with --summarize or --summarize_keywords".into()).into());
+    }
+
     if cli.diff_only && cli.retrieve {
         return Err(ValidationError("--retrieve is not available with --diff-only".into()).into());
     }
 
+    if cli.per_file_timeout.is_some() && !cli.summarize && !cli.summarize_keywords && !cli.summarize_stdin {
+        return Err(ValidationError("--per-file-timeout can only be used with --summarize, --summarize_keywords or --summarize-stdin".into()).into());
+    }
+
+    if (cli.strict || cli.refusal_patterns.is_some()) && !cli.summarize && !cli.summarize_keywords && !cli.summarize_stdin {
+        return Err(ValidationError("--strict and --refusal-patterns can only be used with --summarize, --summarize_keywords or --summarize-stdin".into()).into());
+    }
+
+    if cli.hierarchical_summary && ((!cli.summarize && !cli.summarize_keywords) || cli.diff_only || cli.compare_providers.is_some()) {
+        return Err(ValidationError("--hierarchical-summary can only be used with --summarize or --summarize_keywords and not with --diff-only or --compare-providers".into()).into());
+    }
+
+    if cli.overview_model.is_some() && !cli.overview {
+        return Err(ValidationError("--overview-model can only be used with --overview".into()).into());
+    }
+
+    if cli.overview && (cli.summarize_stdin || cli.content_only || cli.dry_run) {
+        return Err(ValidationError("--overview cannot be used with --summarize-stdin, --content-only or --dry-run".into()).into());
+    }
+
+    if let Some(compare_providers) = &cli.compare_providers {
+        if !cli.summarize && !cli.summarize_keywords {
+            return Err(ValidationError("--compare-providers can only be used with --summarize or --summarize_keywords".into()).into());
+        }
+        if cli.apply || cli.sidecar || cli.retrieve || cli.resume_file.is_some() || cli.index.is_some() {
+            return Err(ValidationError("--compare-providers cannot be used with --apply, --sidecar, --retrieve, --resume-file or --index".into()).into());
+        }
+        for spec in compare_providers.split(',') {
+            ProviderSpec::from_str(spec).map_err(|e| ValidationError(e.to_string()))?;
+        }
+    }
+
+    if let Some(fallback_provider) = &cli.fallback_provider {
+        if !cli.summarize && !cli.summarize_keywords && !cli.summarize_stdin {
+            return Err(ValidationError("--fallback-provider can only be used with --summarize, --summarize_keywords or --summarize-stdin".into()).into());
+        }
+        Provider::from_str(fallback_provider).map_err(|e| ValidationError(e.to_string()))?;
+    }
+
+    if let Some(index) = &cli.index {
+        if (!cli.summarize && !cli.summarize_keywords) || cli.diff_only {
+            return Err(ValidationError("--index can only be used with --summarize or --summarize_keywords and not with --diff-only".into()).into());
+        }
+        validate_output_path(index)?;
+    }
+
+    if cli.concurrency == Some(0) {
+        return Err(ValidationError("--concurrency must be at least 1".into()).into());
+    }
+
+    if (cli.temperature.is_some() || cli.max_tokens.is_some()) && !cli.summarize && !cli.summarize_keywords && !cli.summarize_stdin {
+        return Err(ValidationError("--temperature and --max-tokens can only be used with --summarize, --summarize_keywords or --summarize-stdin".into()).into());
+    }
+
+    if cli.temperature.is_some_and(|t| !(0.0..=2.0).contains(&t)) {
+        return Err(ValidationError("--temperature must be between 0 and 2".into()).into());
+    }
+
+    if cli.max_tokens.is_some_and(|n| n < 1) {
+        return Err(ValidationError("--max-tokens must be at least 1".into()).into());
+    }
+
+    if cli.per_file_timeout == Some(0) {
+        return Err(ValidationError("--per-file-timeout must be greater than 0".into()).into());
+    }
+
     if cli.apply && cli.retrieve {
         return Err(ValidationError("--apply and --retrieve cannot be used together".into()).into());
     }
@@ -111,6 +427,23 @@ fn validate_suffixes(suffixes: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+fn validate_section_order(section_order: &str) -> Result<(), ValidationError> {
+    let mut seen = Vec::new();
+
+    for section in section_order.split(',') {
+        if !OUTPUT_SECTIONS.contains(&section) {
+            return Err(format!("Unknown output section '{}': must be one of {}", section, OUTPUT_SECTIONS.join(", ")).into());
+        }
+
+        if seen.contains(&section) {
+            return Err(format!("Output section '{}' is listed more than once", section).into());
+        }
+        seen.push(section);
+    }
+
+    Ok(())
+}
+
 fn validate_template_path(path: &str) -> Result<(), ValidationError> {
     let path = Path::new(path);
     
@@ -132,6 +465,16 @@ fn validate_template_path(path: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+fn validate_paths_file(path: &str, kind: &str) -> Result<(), ValidationError> {
+    let path = Path::new(path);
+
+    if !path.is_file() {
+        return Err(format!("{} paths file does not exist: {}", kind, path.display()).into());
+    }
+
+    Ok(())
+}
+
 fn validate_output_path(path: &str) -> Result<(), ValidationError> {
     let path = Path::new(path);
     
@@ -149,9 +492,7 @@ fn validate_git_args(
     end_commit: &Option<String>,
 ) -> Result<(), ValidationError> {
 
-    if diff_only && start_commit.is_none() {
-        return Err("--start-commit-id must be provided when using --diff-only".into());
-    }
+    // Without a start commit, diff-only mode compares HEAD with the working directory
     if start_commit.is_some() && !diff_only {
         return Err("--diff-only must be set when using --start-commit-id".into());
     }
@@ -191,9 +532,15 @@ fn validate_git_args(
                 .peel_to_commit()
                 .map_err(|_| "Failed to parse end commit".to_string())?;
 
-            if !repo.graph_descendant_of(end_commit.id(), start_commit.id())
-                .map_err(|_| "Failed to check commit relationship".to_string())? {
-                return Err("start_commit_id must be an ancestor of end_commit_id".into());
+            let is_ancestor = start_commit.id() == end_commit.id()
+                || repo.graph_descendant_of(end_commit.id(), start_commit.id()).unwrap_or(false);
+            if !is_ancestor {
+                return Err(match repo.merge_base(start_commit.id(), end_commit.id()) {
+                    Ok(merge_base) if merge_base == end_commit.id() =>
+                        format!("start_commit_id must be an ancestor of end_commit_id, but {} comes after {}; swap them", start, end),
+                    Ok(_) => "start_commit_id must be an ancestor of end_commit_id; they appear to be on divergent branches".to_string(),
+                    Err(_) => "start_commit_id must be an ancestor of end_commit_id; they share no history".to_string(),
+                }.into());
             }
         }
 
@@ -201,37 +548,83 @@ fn validate_git_args(
     Ok(())
 }
 
-fn validate_commit(repo: &Repository, commit_id: &str, arg_name: &str) -> Result<(), ValidationError> {
-    match repo.revparse_single(commit_id) {
-        Ok(obj) => {
-            if obj.as_commit().is_none() {
-                return Err(format!("{} is not a valid commit", arg_name).into());
-            }
-        }
-        Err(_) => {
-            return Err(format!("Invalid {}: {}", arg_name, commit_id).into());
-        }
+fn validate_pr_base(
+    pr_base: &str,
+    start_commit: &Option<String>,
+    end_commit: &Option<String>,
+) -> Result<(), ValidationError> {
+    if start_commit.is_some() || end_commit.is_some() {
+        return Err("--pr-base cannot be used with --start-commit-id or --end-commit-id".into());
+    }
+
+    let repo = match Repository::open(".") {
+        Ok(repo) => repo,
+        Err(_) => return Err("Not a git repository".into()),
+    };
+
+    let base_commit = repo
+        .revparse_single(pr_base)
+        .and_then(|obj| obj.peel_to_commit())
+        .map_err(|_| format!("Branch not found for --pr-base: {}", pr_base))?;
+    let head_commit = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .map_err(|_| "Failed to resolve HEAD".to_string())?;
+
+    if repo.merge_base(base_commit.id(), head_commit.id()).is_err() {
+        return Err(format!("HEAD has no common ancestor with {}", pr_base).into());
     }
     Ok(())
 }
 
-fn validate_keywords(keywords: &Option<String>, field_name: &str) -> Result<(), ValidationError> {
-    if let Some(keywords) = keywords {
-        let parts: Vec<&str> = keywords.split(',').collect();
-        
-        for keyword in parts {
-            if keyword.is_empty() {
-                return Err(format!("Empty keyword found in {}", field_name).into());
-            }
+fn validate_since_tag(
+    start_commit: &Option<String>,
+    end_commit: &Option<String>,
+) -> Result<(), ValidationError> {
+    if start_commit.is_some() || end_commit.is_some() {
+        return Err("--since-tag cannot be used with --start-commit-id or --end-commit-id".into());
+    }
 
-            if keyword.len() > 100 {
-                return Err(format!("Keyword in {} exceeds maximum length of 100", field_name).into());
-            }
+    let repo = match Repository::open(".") {
+        Ok(repo) => repo,
+        Err(_) => return Err("Not a git repository".into()),
+    };
 
-            // Check for invalid characters (optional - adjust as needed)
-            if keyword.chars().any(|c| !c.is_ascii()) {
-                return Err(format!("Non-ASCII characters found in {} keyword: {}", field_name, keyword).into());
-            }
+    match get_latest_tag(&repo) {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err("--since-tag requires a tag reachable from HEAD, but none was found".into()),
+        Err(e) => Err(format!("Failed to look up tags: {}", e).into()),
+    }
+}
+
+// Anything revparse understands is accepted: commit ids, branches like main or origin/main,
+// tags (annotated ones are peeled to their commit) and expressions like HEAD~3
+fn validate_commit(repo: &Repository, commit_id: &str, arg_name: &str) -> Result<(), ValidationError> {
+    let obj = repo.revparse_single(commit_id).map_err(|_| format!(
+        "Invalid {}: '{}' is not a commit id, branch, tag or revision like HEAD~3", arg_name, commit_id
+    ))?;
+    obj.peel_to_commit()
+        .map_err(|_| format!("{} '{}' does not point to a commit", arg_name, commit_id))?;
+    Ok(())
+}
+
+fn validate_keywords(keywords: &[String], field_name: &str, regex: bool) -> Result<(), ValidationError> {
+    for keyword in keywords {
+        if keyword.is_empty() {
+            return Err(format!("Empty keyword found in {}", field_name).into());
+        }
+
+        if keyword.len() > 100 {
+            return Err(format!("Keyword in {} exceeds maximum length of 100", field_name).into());
+        }
+
+        // Check for invalid characters (optional - adjust as needed)
+        if keyword.chars().any(|c| !c.is_ascii()) {
+            return Err(format!("Non-ASCII characters found in {} keyword: {}", field_name, keyword).into());
+        }
+
+        if regex {
+            Regex::new(keyword).map_err(|e| format!("Invalid regex in {} keyword '{}': {}", field_name, keyword, e))?;
         }
     }
     Ok(())
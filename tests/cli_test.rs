@@ -2,6 +2,7 @@ use std::process::Command;
 use std::fs;
 use similar::{ChangeTag, TextDiff};
 use std::sync::Once;
+use assert_fs::prelude::*;
 
 static INIT: Once = Once::new();
 
@@ -16,6 +17,55 @@ fn setup() {
     });
 }
 
+// A minimal Ollama stand-in that answers a single request with a summary and returns the request it received
+fn serve_ollama_once() -> (u16, std::thread::JoinHandle<String>) {
+    let (port, server) = serve_responses(vec![r#"{"response":"/*\n[DIRSCRIBE]\nsummary\n[/DIRSCRIBE]\n*/","done":true}"#.to_string()]);
+    (port, std::thread::spawn(move || server.join().unwrap().remove(0)))
+}
+
+fn serve_responses(bodies: Vec<String>) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+    serve_with_status(bodies.into_iter().map(|body| ("200 OK", body)).collect())
+}
+
+// Answers one request per response, in order, and returns the raw requests
+fn serve_with_status(responses: Vec<(&'static str, String)>) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        for (status, body) in responses {
+            let (mut stream, _) = listener.accept().expect("No request received");
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buffer).expect("Failed to read request");
+                request.extend_from_slice(&buffer[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(header_end) = text.find("\r\n\r\n") {
+                    let content_length = text[..header_end]
+                        .lines()
+                        .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= header_end + 4 + content_length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+            stream.write_all(response.as_bytes()).unwrap();
+            requests.push(String::from_utf8_lossy(&request).to_string());
+        }
+        requests
+    });
+    (port, server)
+}
+
 #[test]
 fn test_dirscribe_output_matches_ground_truth() {
     setup();
@@ -113,4 +163,2329 @@ fn test_dirscribe_diff_only_output_matches_ground_truth() {
 
         panic!("{}", error_msg);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_dirscribe_strips_utf8_bom() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("bom.rs")
+        .write_str("\u{feff}/*\n[DIRSCRIBE]\nSummary of bom.rs\n[/DIRSCRIBE]\n*/\nfn main() {}\n")
+        .expect("Failed to write test file");
+
+    // Content output starts directly with the file's first line
+    let output = Command::new("dirscribe")
+        .args(["rs", "--output-path=output.txt"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let output_content = fs::read_to_string(temp.path().join("output.txt"))
+        .expect("Failed to read output file");
+    assert!(!output_content.contains('\u{feff}'), "Output contains a byte order mark");
+    assert!(output_content.contains("File Content of ./bom.rs:\n\n/*\n[DIRSCRIBE]"));
+
+    // Applied summaries can be retrieved from files with a byte order mark
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--retrieve", "--output-path=output-retrieve.txt"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let output_content = fs::read_to_string(temp.path().join("output-retrieve.txt"))
+        .expect("Failed to read output file");
+    assert!(!output_content.contains('\u{feff}'), "Output contains a byte order mark");
+    assert!(output_content.contains("Summary of ./bom.rs:\n\n/*\n[DIRSCRIBE]\nSummary of bom.rs\n[/DIRSCRIBE]\n*/"));
+}
+
+
+#[test]
+fn test_dirscribe_preprocess_cmd() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("small.py").write_str("x = 1\n").unwrap();
+    // Large enough that the command exits before dirscribe has written it all to stdin
+    temp.child("big.py").write_str(&"x = 1\n".repeat(40_000)).unwrap();
+
+    let run = |cmd: &str| {
+        let output = Command::new("dirscribe")
+            .args(["py", "--stdout", "--preprocess-cmd", cmd])
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed");
+        (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    let (stdout, stderr) = run("tr x y");
+    assert!(stdout.contains("File Content of ./small.py:\n\ny = 1\n"), "Unexpected output: {}", stdout);
+    assert!(!stderr.contains("Warning"), "{}", stderr);
+
+    // A command that reads the file itself and ignores stdin is not a failure
+    let (stdout, stderr) = run("tr x y < \"$DIRSCRIBE_FILE\"");
+    assert_eq!(stdout.matches("y = 1\n").count(), 40_001, "Unexpected output: {}", &stdout[..200]);
+    assert!(!stderr.contains("Warning"), "{}", stderr);
+
+    // A failing command falls back to the raw content with a warning
+    let (stdout, stderr) = run("exit 3");
+    assert!(stdout.contains("File Content of ./small.py:\n\nx = 1\n"), "Unexpected output: {}", stdout);
+    assert!(stderr.contains("preprocess command failed for ./small.py"), "{}", stderr);
+}
+
+#[test]
+fn test_dirscribe_split_size() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    for name in ["a", "b", "c"] {
+        temp.child(format!("{}.rs", name)).write_str(&format!("fn {}() {{}}\n", name).repeat(10)).unwrap();
+    }
+    temp.child("template.txt").write_str(&format!("{}\n${{${{CONTENT}}$}}$\n", "Context. ".repeat(20))).unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new("dirscribe")
+            .args(["rs", "--output-path", "out.txt"])
+            .args(args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+        (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    // Every part stays under the limit including the template text
+    let (stdout, stderr) = run(&["--split-size", "400", "--prompt-template-path", "template.txt"]);
+    let paths: Vec<&str> = stdout.lines().collect();
+    assert!(paths.len() > 1, "Unexpected output: {}", stdout);
+    let mut combined = String::new();
+    for path in &paths {
+        let part = fs::read_to_string(temp.path().join(path)).unwrap();
+        assert!(part.len() <= 400, "{} is {} bytes", path, part.len());
+        assert!(part.starts_with("Context. "));
+        combined.push_str(&part);
+    }
+    for name in ["a", "b", "c"] {
+        assert_eq!(combined.matches(&format!("fn {}() {{}}", name)).count(), 10);
+    }
+    assert!(!stderr.contains("Warning"), "{}", stderr);
+
+    // A file that doesn't fit on its own gets an oversized part and a warning
+    let (stdout, stderr) = run(&["--split-size", "100"]);
+    assert_eq!(stdout.lines().count(), 4, "Unexpected output: {}", stdout);
+    assert!(stderr.contains("out.2.txt is 127 bytes, more than --split-size"), "{}", stderr);
+}
+
+#[test]
+fn test_dirscribe_case_insensitive_suffixes() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    for file_name in ["lower.py", "upper.PY", "mixed.Py"] {
+        temp.child(file_name).write_str("print('hello')\n").expect("Failed to write test file");
+    }
+
+    let run = |extra_args: &[&str], output_name: &str| -> String {
+        let output = Command::new("dirscribe")
+            .args(["py", &format!("--output-path={}", output_name)])
+            .args(extra_args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed");
+        fs::read_to_string(temp.path().join(output_name)).expect("Failed to read output file")
+    };
+
+    // Matching is exact by default
+    let output_content = run(&[], "output.txt");
+    assert!(output_content.contains("./lower.py"));
+    assert!(!output_content.contains("./upper.PY"));
+    assert!(!output_content.contains("./mixed.Py"));
+
+    let output_content = run(&["--case-insensitive-suffixes"], "output-case-insensitive.txt");
+    assert!(output_content.contains("./lower.py"));
+    assert!(output_content.contains("./upper.PY"));
+    assert!(output_content.contains("./mixed.Py"));
+}
+
+
+#[test]
+fn test_dirscribe_markdown_language_hints() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").expect("Failed to write test file");
+    temp.child("script.py").write_str("print('hello')\n").expect("Failed to write test file");
+    temp.child("server.go").write_str("package main\n").expect("Failed to write test file");
+    temp.child("config.yml").write_str("key: value\n").expect("Failed to write test file");
+    temp.child("notes.xyz").write_str("unknown\n").expect("Failed to write test file");
+
+    let output = Command::new("dirscribe")
+        .args(["rs,py,go,yml,xyz", "--format=markdown", "--output-path=output.md"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let output_content = fs::read_to_string(temp.path().join("output.md"))
+        .expect("Failed to read output file");
+    assert!(output_content.contains("```rust\nfn main() {}\n```"));
+    assert!(output_content.contains("```python\nprint('hello')\n```"));
+    assert!(output_content.contains("```go\npackage main\n```"));
+    assert!(output_content.contains("```yaml\nkey: value\n```"));
+    // Unknown file types get a fence without a language
+    assert!(output_content.contains("```\nunknown\n```"));
+}
+
+
+#[test]
+fn test_dirscribe_base_url_override() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+
+    // The resolved endpoint is reported by --dump-config
+    let output = Command::new("dirscribe")
+        .args(["rs", "--dump-config"])
+        .env("DIRSCRIBE_PROVIDER", "deepseek")
+        .env("PROVIDER_API_KEY", "test-key")
+        .env("DIRSCRIBE_BASE_URL", "http://localhost:4000/v1/chat/completions")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .expect("Failed to parse configuration");
+    assert_eq!(config["provider"]["primary"]["base_url"], "http://localhost:4000/v1/chat/completions");
+    assert_eq!(config["provider"]["primary"]["api_key"], "<redacted>");
+}
+
+#[test]
+fn test_dirscribe_dump_config_redacts_api_keys() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--fallback-provider", "anthropic", "--compare-providers", "deepseek,gemini", "--dump-config"])
+        .env("DIRSCRIBE_PROVIDER", "deepseek")
+        .env("PROVIDER_API_KEY", "primary-secret")
+        .env("DIRSCRIBE_FALLBACK_API_KEY", "fallback-secret")
+        .env("DIRSCRIBE_GEMINI_API_KEY", "gemini-secret")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    // Every configured key is replaced, none of them shows up anywhere in the output
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("secret"), "Unexpected output: {}", stdout);
+    let config: serde_json::Value = serde_json::from_str(&stdout).expect("Failed to parse configuration");
+    assert_eq!(config["provider"]["primary"]["api_key"], "<redacted>");
+    assert_eq!(config["provider"]["fallback"]["provider"], "anthropic");
+    assert_eq!(config["provider"]["fallback"]["api_key"], "<redacted>");
+    assert_eq!(config["provider"]["compare"][0]["api_key"], "<redacted>");
+    assert_eq!(config["provider"]["compare"][1]["provider"], "gemini");
+    assert_eq!(config["provider"]["compare"][1]["api_key"], "<redacted>");
+}
+
+#[test]
+fn test_dirscribe_temperature_is_sent_to_provider() {
+    use std::io::Write;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+
+    let (port, server) = serve_ollama_once();
+
+    let mut child = Command::new("dirscribe")
+        .args(["--summarize-stdin", "--stdin-ext", "rs", "--temperature", "0.25", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to run dirscribe");
+    child.stdin.take().unwrap().write_all(b"fn main() {}").unwrap();
+    assert!(child.wait().unwrap().success(), "dirscribe command failed");
+
+    let request = server.join().unwrap();
+    let body: serde_json::Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..])
+        .expect("Failed to parse request body");
+    assert_eq!(body["options"]["temperature"], 0.25);
+}
+
+#[test]
+fn test_dirscribe_summarize_stdin() {
+    use std::io::Write;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let summary = "/*\n[DIRSCRIBE]\nsummary\n[/DIRSCRIBE]\n*/";
+    let (port, server) = serve_responses(vec![serde_json::json!({ "response": summary, "done": true }).to_string()]);
+
+    let mut child = Command::new("dirscribe")
+        .args(["--summarize-stdin", "--stdin-ext", "rs", "--no-cache", "--stdout"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_MAX_RETRIES", "0")
+        .current_dir(temp.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to run dirscribe");
+    child.stdin.take().unwrap().write_all(b"fn main() { piped() }").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    // The piped content is summarized as a file with the given extension, so it gets a Rust comment
+    let request = server.join().unwrap().remove(0);
+    let body: serde_json::Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..])
+        .expect("Failed to parse request body");
+    let prompt = body["prompt"].as_str().unwrap();
+    assert!(prompt.contains("fn main() { piped() }"), "Unexpected prompt: {}", prompt);
+    assert!(prompt.contains("line 1: '/*'"), "Unexpected prompt: {}", prompt);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), summary);
+}
+
+#[test]
+fn test_dirscribe_concurrency_flag_overrides_env() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--concurrency", "3", "--dump-config"])
+        .env("DIRSCRIBE_CONCURRENT_REQUESTS", "7")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .expect("Failed to parse configuration");
+    assert_eq!(config["provider"]["concurrent_requests"], 3);
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--concurrency", "0"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "--concurrency 0 should be rejected");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--concurrency must be at least 1"));
+}
+
+#[test]
+fn test_dirscribe_request_timeout() {
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+
+    // Accepts connections but never answers them
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let mut connections = Vec::new();
+        for stream in listener.incoming() {
+            connections.push(stream);
+        }
+    });
+
+    let mut child = Command::new("dirscribe")
+        .args(["--summarize-stdin", "--stdin-ext", "rs", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_REQUEST_TIMEOUT_SECS", "1")
+        .current_dir(temp.path())
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to run dirscribe");
+    child.stdin.take().unwrap().write_all(b"fn main() {}").unwrap();
+    // The output is still written, the failed request only decides the exit status
+    assert!(!child.wait().unwrap().success(), "a timed out request should fail the run");
+
+    let output = fs::read_to_string(temp.path().join("out.txt")).expect("Failed to read output");
+    assert!(output.contains("Request timed out"), "Unexpected output: {}", output);
+}
+
+#[test]
+fn test_dirscribe_per_file_timeout_fails_the_file() {
+    use std::net::TcpListener;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    // Accepts connections but never answers them
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let mut connections = Vec::new();
+        for stream in listener.incoming() {
+            connections.push(stream);
+        }
+    });
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--per-file-timeout", "1", "--resume-file", "journal.jsonl", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "a timed out file should fail the run");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 file failed to process"), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let out = fs::read_to_string(temp.path().join("out.txt")).expect("Failed to read output");
+    assert!(out.contains("Skipped: summarization timed out after 1 seconds"), "Unexpected output: {}", out);
+    // The journal is kept, so resuming summarizes the file again
+    assert!(temp.path().join("journal.jsonl").exists());
+}
+
+#[test]
+fn test_dirscribe_failed_request_fails_the_file() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let (port, server) = serve_with_status(vec![
+        ("500 Internal Server Error", r#"{"error":"internal error"}"#.to_string()),
+    ]);
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--apply", "--no-cache", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_MAX_RETRIES", "0")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    server.join().unwrap();
+    assert!(!output.status.success(), "a failed request should fail the run");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 file failed to process"), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let out = fs::read_to_string(temp.path().join("out.txt")).expect("Failed to read output");
+    assert!(out.contains("Error: "), "Unexpected output: {}", out);
+    // The error is not written into the file in place of a summary
+    assert_eq!(fs::read_to_string(temp.path().join("main.rs")).unwrap(), "fn main() {}\n");
+}
+
+#[test]
+fn test_dirscribe_failed_directory_summary_fails_the_run() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("a.rs").write_str("fn a() {}\n").unwrap();
+    temp.child("b.rs").write_str("fn b() {}\n").unwrap();
+
+    // One file is summarized, the other one and then the directory fail
+    let (port, server) = serve_with_status(vec![
+        ("200 OK", r#"{"response":"/*\n[DIRSCRIBE]\nsummary\n[/DIRSCRIBE]\n*/","done":true}"#.to_string()),
+        ("500 Internal Server Error", r#"{"error":"internal error"}"#.to_string()),
+        ("500 Internal Server Error", r#"{"error":"internal error"}"#.to_string()),
+    ]);
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--hierarchical-summary", "--no-cache", "--concurrency", "1", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_MAX_RETRIES", "0")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    let requests = server.join().unwrap();
+    assert!(!output.status.success(), "a failed directory summary should fail the run");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 files failed to process"), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Only the summarized file is sent along for the directory, not the error of the other one
+    assert!(requests[2].contains("File a.rs") != requests[2].contains("File b.rs"), "{}", requests[2]);
+    assert!(!requests[2].contains("internal error"), "{}", requests[2]);
+
+    let out = fs::read_to_string(temp.path().join("out.txt")).expect("Failed to read output");
+    assert!(out.contains("Summary of directory .:\n\nError: Error processing directory ."), "Unexpected output: {}", out);
+}
+
+#[test]
+fn test_dirscribe_strict_refusal_fails_the_file() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let (port, server) = serve_responses(vec![
+        r#"{"response":"/*\n[DIRSCRIBE]\nI'm sorry, but I can't summarize this file.\n[/DIRSCRIBE]\n*/","done":true}"#.to_string(),
+    ]);
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--apply", "--strict", "--no-cache", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    server.join().unwrap();
+    assert!(!output.status.success(), "a refusal under --strict should fail the run");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 file failed to process"), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let out = fs::read_to_string(temp.path().join("out.txt")).expect("Failed to read output");
+    assert!(out.contains("provider refused to summarize"), "Unexpected output: {}", out);
+    assert_eq!(fs::read_to_string(temp.path().join("main.rs")).unwrap(), "fn main() {}\n");
+}
+
+#[test]
+fn test_dirscribe_fallback_without_key_is_unused() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    // The fallback needs a key, but the primary provider answers, so it is never set up
+    let (port, server) = serve_ollama_once();
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--stdout"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_PROVIDER_FALLBACK", "anthropic")
+        .env_remove("DIRSCRIBE_FALLBACK_API_KEY")
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    server.join().unwrap();
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("summary"));
+}
+
+#[test]
+fn test_dirscribe_path_filters_match_whole_components() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("src/main.rs").write_str("fn in_src() {}").unwrap();
+    temp.child("src2/main.rs").write_str("fn in_src2() {}").unwrap();
+
+    for (filter, kept, dropped) in [("--exclude-paths", "in_src2", "in_src()"), ("--include-paths", "in_src()", "in_src2")] {
+        let status = Command::new("dirscribe")
+            .args(["rs", filter, "src", "--output-path", "out.txt"])
+            .current_dir(temp.path())
+            .status()
+            .expect("Failed to run dirscribe");
+        assert!(status.success(), "dirscribe command failed");
+
+        let output = fs::read_to_string(temp.path().join("out.txt")).expect("Failed to read output");
+        assert!(output.contains(kept), "{} src should keep {}", filter, kept);
+        assert!(!output.contains(dropped), "{} src should drop {}", filter, dropped);
+    }
+}
+
+#[test]
+fn test_dirscribe_paths_files_with_globs() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("src/main.rs").write_str("fn in_src() {}").unwrap();
+    temp.child("src/net/client.rs").write_str("fn in_net() {}").unwrap();
+    temp.child("src/net/client_test.rs").write_str("fn in_net_test() {}").unwrap();
+    temp.child("vendor/lib/dep.rs").write_str("fn in_vendor() {}").unwrap();
+    temp.child("include.txt").write_str("# Sources only\nsrc/**/*.rs\n").unwrap();
+    temp.child("exclude.txt").write_str("**/*_test.rs\n./src/n*\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let status = Command::new("dirscribe")
+            .args(["rs", "--output-path", "out.txt"])
+            .args(args)
+            .current_dir(temp.path())
+            .status()
+            .expect("Failed to run dirscribe");
+        assert!(status.success(), "dirscribe command failed");
+        fs::read_to_string(temp.path().join("out.txt")).expect("Failed to read output")
+    };
+
+    let output = run(&["--include-paths-file", "include.txt"]);
+    assert!(output.contains("in_src()") && output.contains("in_net()") && output.contains("in_net_test()"), "{}", output);
+    assert!(!output.contains("in_vendor"), "{}", output);
+
+    // A glob covers the directories it matches with everything below them, "*" stays within a directory
+    let output = run(&["--exclude-paths-file", "exclude.txt"]);
+    assert!(output.contains("in_src()") && output.contains("in_vendor()"), "{}", output);
+    assert!(!output.contains("in_net"), "{}", output);
+}
+
+#[test]
+fn test_dirscribe_markdown_output_matches_snapshot() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {\n    println!(\"hi\");\n}\n").unwrap();
+
+    let status = Command::new("dirscribe")
+        .args(["rs", "--format", "markdown", "--output-path", "output.md"])
+        .current_dir(temp.path())
+        .status()
+        .expect("Failed to run dirscribe");
+    assert!(status.success(), "dirscribe command failed");
+
+    let output = fs::read_to_string(temp.path().join("output.md")).expect("Failed to read output file");
+    let snapshot = fs::read_to_string("tests/data/ground-truth-markdown.md").expect("Failed to read snapshot");
+    assert_eq!(output, snapshot);
+}
+
+#[test]
+fn test_dirscribe_json_output() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let run = |args: &[&str], port: Option<u16>| -> serde_json::Value {
+        let mut command = Command::new("dirscribe");
+        command.args(args).args(["--format", "json", "--output-path", "out.json"]).current_dir(temp.path());
+        if let Some(port) = port {
+            command.env("DIRSCRIBE_PROVIDER", "ollama")
+                .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+                .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"));
+        }
+        assert!(command.status().expect("Failed to run dirscribe").success(), "dirscribe command failed");
+        serde_json::from_str(&fs::read_to_string(temp.path().join("out.json")).unwrap()).expect("Output is not valid JSON")
+    };
+
+    let output = run(&["rs"], None);
+    assert_eq!(output["paths"], serde_json::json!(["main.rs"]));
+    assert_eq!(output["files"][0]["content"], "fn main() {}\n");
+    assert_eq!(output["files"][0]["language"], "rust");
+    assert_eq!(output["files"][0]["size"], 13);
+    assert_eq!(output["files"][0]["hash"], "536e506bb90914c243a12b397b9a998f85ae2cbd9ba02dfd03a9e155ca5ca0f4");
+    assert_eq!(output["files"][0]["executable"], false);
+    assert!(output["files"][0].get("summary").is_none());
+
+    // Size and hash are those of the file, not of the content after the content options
+    let output = run(&["rs", "--strip-lines", "main"], None);
+    assert_eq!(output["files"][0]["content"], "");
+    assert_eq!(output["files"][0]["size"], 13);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        temp.child("run.rs").write_str("fn main() {}\n").unwrap();
+        fs::set_permissions(temp.path().join("run.rs"), fs::Permissions::from_mode(0o755)).unwrap();
+        let output = run(&["rs"], None);
+        assert_eq!(output["paths"], serde_json::json!(["main.rs", "run.rs"]));
+        assert_eq!(output["files"][0]["executable"], false);
+        assert_eq!(output["files"][1]["executable"], true);
+        fs::remove_file(temp.path().join("run.rs")).unwrap();
+    }
+
+    let (port, server) = serve_ollama_once();
+    let output = run(&["rs", "--summarize"], Some(port));
+    server.join().unwrap();
+    assert!(output["files"][0]["summary"].as_str().unwrap().contains("summary"));
+
+    // In diff mode the content is the file's diff
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("main.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+    temp.child("main.rs").write_str("fn main() { changed() }\n").unwrap();
+
+    let output = run(&["rs", "--diff-only", "--start-commit-id", "HEAD"], None);
+    let content = output["files"][0]["content"].as_str().unwrap();
+    assert!(content.starts_with("diff --git a/main.rs b/main.rs"));
+    assert!(content.contains("+fn main() { changed() }"));
+}
+
+#[test]
+fn test_dirscribe_color_only_on_stderr() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--output-path", "out.txt", "--color", "always"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+    assert!(!output.stdout.contains(&0x1b), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\x1b[32mSuccessfully processed"));
+    assert!(!fs::read_to_string(temp.path().join("out.txt")).unwrap().contains('\x1b'));
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--stdout", "--color", "always", "--no-color"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "--color and --no-color should conflict");
+}
+
+#[test]
+fn test_dirscribe_stdout() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Paths:\n./main.rs\n"));
+    assert!(stdout.contains("fn main() {}"));
+    assert!(!stdout.contains("Successfully processed"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Successfully processed"));
+}
+
+#[test]
+fn test_dirscribe_dirscribeignore() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn kept() {}").unwrap();
+    temp.child("fixtures/big.rs").write_str("fn fixture() {}").unwrap();
+    temp.child("src/vendored.rs").write_str("fn vendored() {}").unwrap();
+    temp.child(".dirscribeignore").write_str("fixtures/\n").unwrap();
+    temp.child("src/.dirscribeignore").write_str("vendored.rs\n").unwrap();
+
+    // .dirscribeignore applies whether or not .gitignore rules are used
+    for extra_args in [&[][..], &["--dont-use-gitignore"][..]] {
+        let status = Command::new("dirscribe")
+            .args(["rs", "--output-path", "out.txt"])
+            .args(extra_args)
+            .current_dir(temp.path())
+            .status()
+            .expect("Failed to run dirscribe");
+        assert!(status.success(), "dirscribe command failed");
+
+        let output = fs::read_to_string(temp.path().join("out.txt")).expect("Failed to read output");
+        assert!(output.contains("fn kept() {}"));
+        assert!(!output.contains("fixture"));
+        assert!(!output.contains("vendored"));
+    }
+}
+
+#[test]
+fn test_dirscribe_config_file() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("skip/a.rs").write_str("fn a() {}").unwrap();
+    temp.child(".dirscribe.toml").write_str(
+        "suffixes = [\"rs\", \"md\"]\nexclude_paths = [\"skip\"]\nconcurrency = 4\nformat = \"markdown\"\n"
+    ).unwrap();
+    temp.child("other.toml").write_str("suffixes = [\"py\"]\n").unwrap();
+
+    let dump_config = |args: &[&str]| -> serde_json::Value {
+        let output = Command::new("dirscribe")
+            .args(args)
+            .arg("--dump-config")
+            .env_remove("DIRSCRIBE_CONCURRENT_REQUESTS")
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed");
+        serde_json::from_slice(&output.stdout).expect("Failed to parse configuration")
+    };
+
+    // Values from .dirscribe.toml replace the built-in defaults
+    let config = dump_config(&[]);
+    assert_eq!(config["suffixes"], serde_json::json!(["rs", "md"]));
+    assert_eq!(config["filters"]["exclude_paths"], serde_json::json!(["skip"]));
+    assert_eq!(config["provider"]["concurrent_requests"], 4);
+    assert_eq!(config["output"]["format"], "markdown");
+
+    // Command line flags win over the config
+    let config = dump_config(&["txt", "--concurrency", "2", "--format", "text"]);
+    assert_eq!(config["suffixes"], serde_json::json!(["txt"]));
+    assert_eq!(config["filters"]["exclude_paths"], serde_json::json!(["skip"]));
+    assert_eq!(config["provider"]["concurrent_requests"], 2);
+    assert_eq!(config["output"]["format"], "text");
+
+    // An explicit config file is used instead of .dirscribe.toml
+    let config = dump_config(&["--config", "other.toml"]);
+    assert_eq!(config["suffixes"], serde_json::json!(["py"]));
+    assert_eq!(config["output"]["format"], "text");
+
+    // The provider from the config only applies without DIRSCRIBE_PROVIDER, and keeps DIRSCRIBE_MODEL
+    temp.child("provider.toml").write_str("provider = \"deepseek\"\n").unwrap();
+    let output = Command::new("dirscribe")
+        .args(["--config", "provider.toml", "--dump-config"])
+        .env_remove("DIRSCRIBE_PROVIDER")
+        .env("PROVIDER_API_KEY", "test-key")
+        .env("DIRSCRIBE_MODEL", "model-from-env")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).expect("Failed to parse configuration");
+    assert_eq!(config["provider"]["primary"]["provider"], "deepseek");
+    assert_eq!(config["provider"]["primary"]["model"], "model-from-env");
+
+    let output = Command::new("dirscribe")
+        .args(["--config", "provider.toml", "--dump-config"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).expect("Failed to parse configuration");
+    assert_eq!(config["provider"]["primary"]["provider"], "ollama");
+}
+
+#[test]
+fn test_dirscribe_loads_dotenv() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child(".env").write_str(
+        "DIRSCRIBE_PROVIDER=deepseek\nPROVIDER_API_KEY=key-from-dotenv\nDIRSCRIBE_MODEL=model-from-dotenv\n"
+    ).unwrap();
+
+    // Variables set in the environment are not overridden by .env
+    let output = Command::new("dirscribe")
+        .args(["rs", "--dump-config"])
+        .env_remove("DIRSCRIBE_PROVIDER")
+        .env_remove("PROVIDER_API_KEY")
+        .env_remove("DIRSCRIBE_BASE_URL")
+        .env("DIRSCRIBE_MODEL", "model-from-env")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .expect("Failed to parse configuration");
+    assert_eq!(config["provider"]["primary"]["provider"], "deepseek");
+    assert_eq!(config["provider"]["primary"]["api_key"], "<redacted>");
+    assert_eq!(config["provider"]["primary"]["model"], "model-from-env");
+}
+
+#[test]
+fn test_dirscribe_dry_run() {
+    use std::net::TcpListener;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("lib.rs").write_str("pub fn lib() {}\n").unwrap();
+
+    // Any request to the provider would be queued on this listener
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--dry-run", "--or-keywords", "main"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "File Paths:\n./main.rs\n\n");
+    assert!(!stdout.contains("File Contents:"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 file selected"));
+    assert!(listener.accept().is_err(), "dry run made a request to the provider");
+}
+
+#[test]
+fn test_dirscribe_summary_cache() {
+    use std::net::TcpListener;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let run = |port: u16| {
+        let status = Command::new("dirscribe")
+            .args(["rs", "--summarize", "--output-path", "out.txt"])
+            .env("DIRSCRIBE_PROVIDER", "ollama")
+            .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+            .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+            .current_dir(temp.path())
+            .status()
+            .expect("Failed to run dirscribe");
+        assert!(status.success(), "dirscribe command failed");
+        fs::read_to_string(temp.path().join("out.txt")).unwrap()
+    };
+
+    let (port, server) = serve_ollama_once();
+    let first = run(port);
+    server.join().unwrap();
+    assert!(first.contains("summary"));
+
+    // The unchanged file is answered from the cache, so the second server never sees a request
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    listener.set_nonblocking(true).unwrap();
+    let second = run(listener.local_addr().unwrap().port());
+    assert_eq!(first, second);
+    assert!(listener.accept().is_err(), "cached summary was requested again");
+}
+
+#[test]
+fn test_dirscribe_reports_token_usage() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("lib.rs").write_str("pub fn lib() {}\n").unwrap();
+
+    let response = |input_tokens: u64, output_tokens: u64| serde_json::json!({
+        "choices": [{ "message": { "role": "assistant", "content": "/*\n[DIRSCRIBE]\nsummary\n[/DIRSCRIBE]\n*/" } }],
+        "usage": { "prompt_tokens": input_tokens, "completion_tokens": output_tokens, "total_tokens": input_tokens + output_tokens },
+    }).to_string();
+    let (port, server) = serve_responses(vec![response(1200, 300), response(800, 100)]);
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--show-cost", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "deepseek")
+        .env("PROVIDER_API_KEY", "test-key")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/v1/chat/completions", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .env_remove("DIRSCRIBE_MODEL")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+    server.join().unwrap();
+
+    // deepseek-chat: 2,000 input tokens at $0.27 and 400 output tokens at $1.10 per million
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Used 2,000 input / 400 output tokens across 2 files, estimated cost $0.0010"), "{}", stderr);
+}
+
+#[test]
+fn test_dirscribe_retries_rate_limited_request() {
+    use std::io::Write;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+
+    let (port, server) = serve_with_status(vec![
+        ("429 Too Many Requests", r#"{"error":"rate limited"}"#.to_string()),
+        ("200 OK", r#"{"response":"/*\n[DIRSCRIBE]\nsummary\n[/DIRSCRIBE]\n*/","done":true}"#.to_string()),
+    ]);
+
+    let mut child = Command::new("dirscribe")
+        .args(["--summarize-stdin", "--stdin-ext", "rs", "--no-cache", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_MAX_RETRIES", "1")
+        .env("DIRSCRIBE_INITIAL_BACKOFF_MS", "10")
+        .current_dir(temp.path())
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to run dirscribe");
+    child.stdin.take().unwrap().write_all(b"fn main() {}").unwrap();
+    assert!(child.wait().unwrap().success(), "dirscribe command failed");
+
+    assert_eq!(server.join().unwrap().len(), 2);
+    let output = fs::read_to_string(temp.path().join("out.txt")).unwrap();
+    assert!(output.contains("summary"), "{}", output);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dirscribe_follow_symlinks() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let target = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    target.child("linked.rs").write_str("fn linked() {}\n").unwrap();
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    std::os::unix::fs::symlink(target.path(), temp.path().join("vendor")).unwrap();
+    // A link back to its parent must not send the walk into a loop
+    std::os::unix::fs::symlink(temp.path(), temp.path().join("loop")).unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new("dirscribe")
+            .args(["rs", "--stdout"])
+            .args(args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.contains("fn main() {}"));
+    assert!(!output.contains("fn linked() {}"));
+
+    let output = run(&["--follow-symlinks"]);
+    assert!(output.contains("./vendor/linked.rs"));
+    assert!(output.contains("fn linked() {}"));
+    assert_eq!(output.matches("fn main() {}").count(), 1);
+}
+
+#[test]
+fn test_dirscribe_ignore_case_keywords() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("// TODO: parse arguments\nfn main() {}\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new("dirscribe")
+            .args(["rs", "--dry-run", "--or-keywords", "todo"])
+            .args(args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert!(!run(&[]).contains("main.rs"));
+    assert!(run(&["--ignore-case"]).contains("main.rs"));
+}
+
+#[test]
+fn test_dirscribe_regex_keywords_with_commas() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("short.rs").write_str("fn ab(x: u8) {}\n").unwrap();
+    temp.child("long.rs").write_str("fn abcdef() {}\n").unwrap();
+    temp.child("todo.rs").write_str("// TODO(alice)\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new("dirscribe")
+            .args(["rs", "--dry-run", "--regex-keywords"])
+            .args(args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // A pattern is never split at the comma of its quantifier range
+    let stdout = run(&["--or-keywords", r"fn \w{1,3}\(.{0,40}\)"]);
+    assert!(stdout.contains("short.rs") && !stdout.contains("long.rs") && !stdout.contains("todo.rs"), "Unexpected output: {}", stdout);
+
+    // Several patterns are given by repeating the flag
+    let stdout = run(&["--or-keywords", r"fn \w{1,3}\(", "--or-keywords", r"TODO\(\w+\)"]);
+    assert!(stdout.contains("short.rs") && !stdout.contains("long.rs") && stdout.contains("todo.rs"), "Unexpected output: {}", stdout);
+}
+
+#[test]
+fn test_dirscribe_keyword_stats() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("a.rs").write_str("// TODO: one\n// FIXME: two\n").unwrap();
+    temp.child("b.rs").write_str("// TODO: three\n").unwrap();
+    temp.child("c.rs").write_str("// TODO: four\n// deprecated\n").unwrap();
+    temp.child("d.rs").write_str("fn d() {}\n").unwrap();
+
+    // c.rs is excluded, so its TODO is not counted
+    let output = Command::new("dirscribe")
+        .args(["rs", "--dry-run", "--or-keywords", "TODO,FIXME,XXX", "--exclude-keywords", "deprecated", "--keyword-stats"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stats: Vec<&str> = stderr.lines()
+        .skip_while(|line| !line.contains("Keyword stats:"))
+        .skip(1)
+        .take(3)
+        .collect();
+    assert_eq!(stats, vec!["  TODO   2 files", "  FIXME  1 file", "  XXX    0 files"]);
+}
+
+#[test]
+fn test_dirscribe_wildcard_detects_binary_files() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    // The two-byte "é" straddles byte 1024, and the end of the 8000 byte sample
+    let text = format!("{}é{}é{}", "a".repeat(1023), "b".repeat(8000 - 1025 - 1), "c".repeat(100));
+    assert!(!text.is_char_boundary(1024) && !text.is_char_boundary(8000));
+    temp.child("notes.data").write_str(&text).unwrap();
+    temp.child("image.data").write_binary(b"text until\0a null byte").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["*", "--dry-run"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("./notes.data"));
+    assert!(!stdout.contains("./image.data"));
+}
+
+#[test]
+fn test_dirscribe_sort() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("src/b.rs").write_str("fn b() {}\n").unwrap();
+    temp.child("src/a/z.rs").write_str("fn z() { /* the largest file */ }\n").unwrap();
+    temp.child("c.rs").write_str("\n").unwrap();
+    temp.child("a.rs").write_str("fn a() {}\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new("dirscribe")
+            .args(["rs", "--dry-run"])
+            .args(args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let sorted_by_path = "File Paths:\n./a.rs\n./c.rs\n./src/a/z.rs\n./src/b.rs\n\n";
+    assert_eq!(run(&[]), sorted_by_path);
+    assert_eq!(run(&["--sort", "path"]), sorted_by_path);
+    assert_eq!(run(&["--sort", "name"]), "File Paths:\n./a.rs\n./src/b.rs\n./c.rs\n./src/a/z.rs\n\n");
+    assert_eq!(run(&["--sort", "size"]), "File Paths:\n./c.rs\n./a.rs\n./src/b.rs\n./src/a/z.rs\n\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dirscribe_tree_matches_fixture() {
+    use std::os::unix::fs::PermissionsExt;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    for file in ["README.md", "build.sh", "src/main.rs", "src/util.rs", "src/parser/mod.rs", "src/parser/lexer/tokens.rs", "docs/notes.txt"] {
+        temp.child(file).write_str("content\n").unwrap();
+    }
+    fs::set_permissions(temp.path().join("build.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["md,rs,sh", "--tree", "--classify", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let expected = fs::read_to_string("tests/data/tree.txt").expect("Failed to read tree fixture");
+    assert_eq!(stdout.strip_prefix("File Paths:\n").unwrap().split("File Contents:").next().unwrap(), expected);
+    assert!(stdout.contains("./src/parser/lexer/tokens.rs"), "file contents keep their full paths");
+}
+
+#[test]
+fn test_dirscribe_section_order() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("src/util.rs").write_str("pub fn util() {}\n").unwrap();
+
+    let run = |section_order: &str| {
+        Command::new("dirscribe")
+            .args(["rs", "--stdout", "--section-order", section_order])
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe")
+    };
+
+    let output = run("tree,paths,contents");
+    assert!(output.status.success(), "dirscribe command failed");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let tree = stdout.find("File Tree:").expect("Missing tree section");
+    let paths = stdout.find("File Paths:").expect("Missing paths section");
+    let contents = stdout.find("File Contents:").expect("Missing contents section");
+    assert!(tree < paths && paths < contents, "Unexpected output: {}", stdout);
+    assert!(stdout[tree..paths].contains("util.rs") && !stdout[tree..paths].contains("./src/util.rs"), "Unexpected output: {}", stdout);
+
+    // Sections that are not listed are left out
+    let output = run("contents");
+    assert!(output.status.success(), "dirscribe command failed");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Contents:"), "Unexpected output: {}", stdout);
+    assert!(!stdout.contains("File Paths:") && !stdout.contains("File Tree:"));
+
+    let output = run("paths,summary");
+    assert!(!output.status.success(), "Unknown sections should be rejected");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown output section 'summary': must be one of paths, tree, contents"));
+
+    let output = run("paths,contents,paths");
+    assert!(!output.status.success(), "Duplicate sections should be rejected");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Output section 'paths' is listed more than once"));
+}
+
+#[test]
+fn test_dirscribe_show_metadata() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--show-metadata", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let entry = stdout.lines().nth(1).unwrap();
+    let annotation = entry.strip_prefix("./main.rs (").and_then(|rest| rest.strip_suffix(')'))
+        .unwrap_or_else(|| panic!("Unexpected entry: {}", entry));
+    let (size, modified) = annotation.split_once(" bytes, modified ").unwrap();
+    assert_eq!(size.parse::<u64>().unwrap(), 13);
+    let modified = chrono::DateTime::parse_from_rfc3339(modified).expect("Invalid modification time");
+    let expected: chrono::DateTime<chrono::Utc> = fs::metadata(temp.path().join("main.rs")).unwrap().modified().unwrap().into();
+    assert_eq!(modified.timestamp(), expected.timestamp());
+    // The contents section keeps the plain path
+    assert!(stdout.contains("File Content of ./main.rs:\n"));
+}
+
+#[test]
+fn test_dirscribe_max_output_bytes_truncates_at_file_boundary() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    for name in ["a", "b", "c"] {
+        temp.child(format!("{}.rs", name)).write_str(&format!("fn {}() {{}}\n", name)).unwrap();
+    }
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--max-output-bytes", "150", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "File Paths:\n./a.rs\n./b.rs\n./c.rs\n\nFile Contents:\n\n\nFile Content of ./a.rs:\n\nfn a() {}\n\n\
+        \n... output truncated at 150 bytes, 2 files omitted ...\n");
+    assert!(stdout.len() <= 150);
+
+    // Directory summaries aren't files, neither they nor their heading count as omitted files
+    let summarize = |max_output_bytes: Option<usize>| {
+        let (port, server) = serve_responses(vec![r#"{"response":"/*\n[DIRSCRIBE]\nsummary\n[/DIRSCRIBE]\n*/","done":true}"#.to_string(); 4]);
+        let mut command = Command::new("dirscribe");
+        command.args(["rs", "--summarize", "--hierarchical-summary", "--no-cache", "--stdout"])
+            .env("DIRSCRIBE_PROVIDER", "ollama")
+            .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+            .current_dir(temp.path());
+        if let Some(max_output_bytes) = max_output_bytes {
+            command.args(["--max-output-bytes", &max_output_bytes.to_string()]);
+        }
+        let output = command.output().expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+        server.join().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let full = summarize(None);
+    assert!(full.contains("Directory Summaries"), "Unexpected output: {}", full);
+    // Room for the summary of a.rs and the note, but not for the summary of b.rs
+    let max_output_bytes = full.find("\nSummary of ./b.rs").expect("Missing summary of b.rs") + 60;
+    let truncated = summarize(Some(max_output_bytes));
+    assert!(truncated.contains("Summary of ./a.rs") && !truncated.contains("Summary of ./b.rs"), "Unexpected output: {}", truncated);
+    assert!(!truncated.contains("Directory Summaries"), "Unexpected output: {}", truncated);
+    assert!(truncated.ends_with(&format!("\n... output truncated at {} bytes, 2 files omitted ...\n", max_output_bytes)), "Unexpected output: {}", truncated);
+}
+
+#[test]
+fn test_dirscribe_max_output_tokens_drops_largest_files() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("a.rs").write_str(&"a".repeat(100)).unwrap();
+    temp.child("b.rs").write_str(&"b".repeat(300)).unwrap();
+    temp.child("c.rs").write_str(&"c".repeat(1000)).unwrap();
+    temp.child("d.rs").write_str(&"d".repeat(50)).unwrap();
+
+    // 200 tokens leave room for every file but the largest one
+    let output = Command::new("dirscribe")
+        .args(["rs", "--max-output-tokens", "200", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.len() <= 800, "output exceeds the budget: {} bytes", stdout.len());
+    for kept in ["a.rs", "b.rs", "d.rs"] {
+        assert!(stdout.contains(&format!("File Content of ./{}:", kept)), "{} should survive", kept);
+    }
+    assert!(!stdout.contains("File Content of ./c.rs:"));
+    assert!(stdout.contains("./c.rs\n"), "the file paths section still lists every file");
+    assert!(stdout.ends_with("[truncated: 1 files omitted]\n"));
+}
+
+#[test]
+fn test_dirscribe_files_from_stdin() {
+    use std::io::Write;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("a.rs").write_str("fn a() {}\n").unwrap();
+    temp.child("src/b.py").write_str("def b(): pass\n").unwrap();
+    temp.child("c.rs").write_str("fn c() {}\n").unwrap();
+
+    let run = |list: &str| {
+        let mut child = Command::new("dirscribe")
+            .args(["--files-from", "-", "--stdout"])
+            .current_dir(temp.path())
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .expect("Failed to run dirscribe");
+        child.stdin.take().unwrap().write_all(list.as_bytes()).unwrap();
+        child.wait_with_output().expect("Failed to wait for dirscribe")
+    };
+
+    let output = run("src/b.py\n./a.rs\n\n");
+    assert!(output.status.success(), "dirscribe command failed");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Paths:\n./a.rs\n./src/b.py\n\n"), "Unexpected output: {}", stdout);
+    assert!(stdout.contains("def b(): pass"));
+    assert!(!stdout.contains("c.rs"));
+
+    let output = run("missing.rs\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("File not found: missing.rs"));
+}
+
+#[test]
+fn test_dirscribe_line_numbers_match_fixture() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("lines.rs").write_str(
+        "fn main() {\n    let a = 1;\n    let b = 2;\n\n    let c = a + b;\n    let d = c * 2;\n    let e = d - 1;\n    let f = e / 3;\n    println!(\"{}\", f);\n}\n"
+    ).unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--line-numbers", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let expected = fs::read_to_string("tests/data/line_numbers.txt").expect("Failed to read line numbers fixture");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn test_dirscribe_staged_only_shows_staged_changes() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("staged.rs").write_str("fn staged() {}\n").unwrap();
+    temp.child("unstaged.rs").write_str("fn unstaged() {}\n").unwrap();
+
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("staged.rs")).unwrap();
+    index.add_path(std::path::Path::new("unstaged.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+
+    temp.child("staged.rs").write_str("fn staged() { changed() }\n").unwrap();
+    index.add_path(std::path::Path::new("staged.rs")).unwrap();
+    index.write().unwrap();
+    temp.child("unstaged.rs").write_str("fn unstaged() { changed() }\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--diff-only", "--staged", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("+fn staged() { changed() }"));
+    assert!(!stdout.contains("unstaged.rs"), "unstaged changes should be left out: {}", stdout);
+}
+
+#[test]
+fn test_dirscribe_include_untracked() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("tracked.rs").write_str("fn tracked() {}\n").unwrap();
+
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("tracked.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+    temp.child("src/new.rs").write_str("fn brand_new() {}\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new("dirscribe")
+            .args(["rs", "--diff-only", "--start-commit-id", "HEAD", "--stdout"])
+            .args(args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert!(!run(&[]).contains("new.rs"));
+    let stdout = run(&["--include-untracked"]);
+    assert!(stdout.starts_with("File Paths:\n./src/new.rs\n"), "Unexpected output: {}", stdout);
+    assert!(stdout.contains("+fn brand_new() {}"));
+}
+
+#[test]
+fn test_dirscribe_diff_only_without_start_commit() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("changed.rs").write_str("fn changed() {}\n").unwrap();
+    temp.child("same.rs").write_str("fn same() {}\n").unwrap();
+
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("changed.rs")).unwrap();
+    index.add_path(std::path::Path::new("same.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+    temp.child("changed.rs").write_str("fn changed() { uncommitted() }\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--diff-only", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Paths:\n./changed.rs\n\n"), "Unexpected output: {}", stdout);
+    assert!(stdout.contains("+fn changed() { uncommitted() }"));
+}
+
+#[test]
+fn test_dirscribe_diff_with_content() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("changed.rs").write_str("fn changed() {}\n").unwrap();
+    temp.child("latin1.rs").write_str("// cafe\n").unwrap();
+
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("changed.rs")).unwrap();
+    index.add_path(std::path::Path::new("latin1.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let initial = repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+    temp.child("changed.rs").write_str("fn changed() { committed() }\n").unwrap();
+    temp.child("latin1.rs").write_str("// cafe!\n").unwrap();
+    index.add_path(std::path::Path::new("changed.rs")).unwrap();
+    index.add_path(std::path::Path::new("latin1.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let parent = repo.find_commit(initial).unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Second commit", &tree, &[&parent]).unwrap();
+    // The diff is between the commits, only the current content can't be read
+    temp.child("latin1.rs").write_binary(b"// caf\xe9\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--diff-only", "--diff-with-content", "--start-commit-id", "HEAD~1", "--end-commit-id", "HEAD", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("Current Content of ./changed.rs:\n\nfn changed() { committed() }"), "Unexpected output: {}", stdout);
+    // A file whose current content can't be read keeps its diff, but is reported and counts as failed
+    assert!(stdout.contains("Diff of ./latin1.rs"), "Unexpected output: {}", stdout);
+    assert!(!stdout.contains("Current Content of ./latin1.rs"), "Unexpected output: {}", stdout);
+    assert!(stderr.contains("Error processing file ./latin1.rs"), "{}", stderr);
+    assert!(!stdout.contains("File was deleted"), "Unexpected output: {}", stdout);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_dirscribe_diff_only_shows_deleted_files() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("kept.rs").write_str("fn kept() {}\n").unwrap();
+    temp.child("gone.rs").write_str("fn gone() {}\n").unwrap();
+
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("kept.rs")).unwrap();
+    index.add_path(std::path::Path::new("gone.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let initial = repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+    std::fs::remove_file(temp.child("gone.rs").path()).unwrap();
+    index.remove_path(std::path::Path::new("gone.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let parent = repo.find_commit(initial).unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Delete gone.rs", &tree, &[&parent]).unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--diff-only", "--diff-with-content", "--start-commit-id", "HEAD~1", "--end-commit-id", "HEAD", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    // The deleted file isn't on disk to be walked, it comes from the diff
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Paths:\n./gone.rs\n\n"), "Unexpected output: {}", stdout);
+    assert!(stdout.contains("-fn gone() {}"), "Unexpected output: {}", stdout);
+    assert!(stdout.contains("Current Content of ./gone.rs:\n\nFile was deleted"), "Unexpected output: {}", stdout);
+    assert!(!stdout.contains("kept.rs"), "Unexpected output: {}", stdout);
+}
+
+#[test]
+fn test_dirscribe_pr_base_diffs_from_the_merge_base() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("feature.rs").write_str("fn feature() {}\n").unwrap();
+    temp.child("shared.rs").write_str("fn shared() {}\n").unwrap();
+
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("feature.rs")).unwrap();
+    index.add_path(std::path::Path::new("shared.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let initial = repo.find_commit(repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap()).unwrap();
+
+    // The base branch moves on after the branch point, those changes aren't part of the pull request
+    temp.child("shared.rs").write_str("fn shared() { on_base() }\n").unwrap();
+    index.add_path(std::path::Path::new("shared.rs")).unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    repo.commit(Some("refs/heads/base"), &signature, &signature, "Base commit", &tree, &[&initial]).unwrap();
+
+    temp.child("shared.rs").write_str("fn shared() {}\n").unwrap();
+    temp.child("feature.rs").write_str("fn feature() { on_branch() }\n").unwrap();
+    index.add_path(std::path::Path::new("shared.rs")).unwrap();
+    index.add_path(std::path::Path::new("feature.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Feature commit", &tree, &[&initial]).unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--pr-base", "base", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Paths:\n./feature.rs\n\n"), "Unexpected output: {}", stdout);
+    assert!(stdout.contains("+fn feature() { on_branch() }"), "Unexpected output: {}", stdout);
+    assert!(!stdout.contains("on_base"), "Unexpected output: {}", stdout);
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--pr-base", "missing", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "an unknown --pr-base branch should be rejected");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Branch not found for --pr-base: missing"));
+}
+
+#[test]
+fn test_dirscribe_since_tag_skips_a_tag_on_head() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let mut index = repo.index().unwrap();
+    let mut parent: Option<git2::Commit> = None;
+    for (tag, file) in [("v1.0.0", "first.rs"), ("v1.1.0", "second.rs"), ("v1.2.0", "third.rs")] {
+        temp.child(file).write_str("fn release() {}\n").unwrap();
+        index.add_path(std::path::Path::new(file)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let commit = repo.find_commit(repo.commit(Some("HEAD"), &signature, &signature, tag, &tree, &parents).unwrap()).unwrap();
+        repo.tag_lightweight(tag, commit.as_object(), false).unwrap();
+        parent = Some(commit);
+    }
+
+    // HEAD is tagged v1.2.0, so a freshly tagged release is compared to the release before it
+    let output = Command::new("dirscribe")
+        .args(["rs", "--since-tag", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Processing changes since tag v1.1.0"));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Paths:\n./third.rs\n\n"), "Unexpected output: {}", stdout);
+}
+
+#[test]
+fn test_dirscribe_diff_only_files_from_absolute_paths() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("changed.rs").write_str("fn changed() {}\n").unwrap();
+    temp.child("same.rs").write_str("fn same() {}\n").unwrap();
+
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("changed.rs")).unwrap();
+    index.add_path(std::path::Path::new("same.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+    temp.child("changed.rs").write_str("fn changed() { uncommitted() }\n").unwrap();
+
+    // Absolute paths are checked against the diff like relative ones
+    let list = format!("{}\n{}\n", temp.path().join("changed.rs").display(), temp.path().join("same.rs").display());
+    temp.child("files.txt").write_str(&list).unwrap();
+    let output = Command::new("dirscribe")
+        .args(["--files-from", "files.txt", "--diff-only", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Paths:\n./changed.rs\n\n"), "Unexpected output: {}", stdout);
+    assert!(!stdout.contains("same.rs"), "Unexpected output: {}", stdout);
+}
+
+#[test]
+fn test_dirscribe_commit_refs() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let commit = |file: &str, content: &str, message: &str| {
+        temp.child(file).write_str(content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new(file)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parents = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+    };
+    let first = commit("lib.rs", "fn first() {}\n", "First commit");
+    repo.branch("base", &repo.find_commit(first).unwrap(), false).unwrap();
+    commit("lib.rs", "fn second() {}\n", "Second commit");
+
+    let run = |args: &[&str]| {
+        Command::new("dirscribe")
+            .args(["rs", "--diff-only", "--stdout"])
+            .args(args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe")
+    };
+
+    for args in [&["--start-commit-id", "base"][..], &["--start-commit-id", "HEAD~1", "--end-commit-id", "HEAD"][..]] {
+        let output = run(args);
+        assert!(output.status.success(), "dirscribe failed for {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        assert!(String::from_utf8(output.stdout).unwrap().contains("+fn second() {}"));
+    }
+
+    let output = run(&["--start-commit-id", "HEAD", "--end-commit-id", "HEAD~1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be an ancestor of end_commit_id"));
+
+    let output = run(&["--start-commit-id", "no-such-branch"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not a commit id, branch, tag or revision"));
+}
+
+#[test]
+fn test_dirscribe_diffstat() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let commit = |content: &str, message: &str| {
+        temp.child("lib.rs").write_str(content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new("lib.rs")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parents = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents.iter().collect::<Vec<_>>()).unwrap();
+    };
+    commit("fn a() {}\nfn b() {}\n", "First commit");
+    commit("fn a() {}\nfn c() {}\nfn d() {}\n", "Second commit");
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--diff-only", "--diffstat", "--start-commit-id", "HEAD~1", "--end-commit-id", "HEAD", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let diffstat = "\nDiffstat:\n./lib.rs | 2 insertions(+), 1 deletion(-)\n1 file changed, 2 insertions(+), 1 deletion(-)\n\nFile Contents:";
+    assert!(stdout.starts_with(&format!("File Paths:\n./lib.rs\n{}", diffstat)), "Unexpected output: {}", stdout);
+}
+
+#[test]
+fn test_dirscribe_ignore_whitespace() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let commit = |files: &[(&str, &str)], message: &str| {
+        let mut index = repo.index().unwrap();
+        for (file, content) in files {
+            temp.child(file).write_str(content).unwrap();
+            index.add_path(std::path::Path::new(file)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parents = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents.iter().collect::<Vec<_>>()).unwrap();
+    };
+    commit(&[("indented.rs", "fn a() {\nlet x = 1;\n}\n"), ("changed.rs", "fn b() {}\n")], "First commit");
+    commit(&[("indented.rs", "fn a() {\n    let x = 1;\n}\n"), ("changed.rs", "fn b() { changed() }\n")], "Reformat");
+
+    let run = |args: &[&str]| {
+        let output = Command::new("dirscribe")
+            .args(["rs", "--diff-only", "--start-commit-id", "HEAD~1", "--end-commit-id", "HEAD", "--stdout"])
+            .args(args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert!(run(&[]).contains("indented.rs"));
+    let stdout = run(&["--ignore-whitespace"]);
+    assert!(stdout.starts_with("File Paths:\n./changed.rs\n\n"), "Unexpected output: {}", stdout);
+    assert!(!stdout.contains("indented.rs"));
+}
+
+#[test]
+fn test_dirscribe_list_commits() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let commit = |content: &str, message: &str| {
+        temp.child("lib.rs").write_str(content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new("lib.rs")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parents = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+    };
+    commit("fn a() {}\n", "Initial commit");
+    let second = commit("fn b() {}\n", "Add b\n\nWith a body that is left out");
+    let third = commit("fn c() {}\n", "Add c");
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--diff-only", "--list-commits", "--start-commit-id", "HEAD~2", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let expected = format!("\nCommits in range:\n{} Add b\n{} Add c\n\nFile Contents:", &second.to_string()[..7], &third.to_string()[..7]);
+    assert!(stdout.contains(&expected), "Unexpected output: {}", stdout);
+    assert!(!stdout.contains("Initial commit"));
+}
+
+#[test]
+fn test_dirscribe_detects_renames() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let content = "fn moved() {\n    println!(\"same content\");\n}\n";
+    temp.child("old.rs").write_str(content).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(std::path::Path::new("old.rs")).unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let first = repo.commit(Some("HEAD"), &signature, &signature, "Add old.rs", &tree, &[]).unwrap();
+
+    fs::rename(temp.path().join("old.rs"), temp.path().join("new.rs")).unwrap();
+    index.remove_path(std::path::Path::new("old.rs")).unwrap();
+    index.add_path(std::path::Path::new("new.rs")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let parent = repo.find_commit(first).unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Rename to new.rs", &tree, &[&parent]).unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--diff-only", "--start-commit-id", "HEAD~1", "--end-commit-id", "HEAD", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("File Paths:\n./new.rs\n\n"), "Unexpected output: {}", stdout);
+    assert_eq!(stdout.matches("diff --git").count(), 1, "Expected a single rename entry: {}", stdout);
+    assert!(stdout.contains("renamed: old.rs -> new.rs"));
+    assert!(!stdout.contains("deleted file mode"));
+}
+
+#[test]
+fn test_dirscribe_index_in_subdirectory() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("src/main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("src/my file (1).rs").write_str("fn copy() {}\n").unwrap();
+    temp.child("docs/.keep").touch().unwrap();
+
+    let response = r#"{"response":"/*\n[DIRSCRIBE]\nsummary\n[/DIRSCRIBE]\n*/","done":true}"#.to_string();
+    let (port, server) = serve_responses(vec![response.clone(), response]);
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--index", "docs/SUMMARY.md", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    server.join().unwrap();
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    // The links lead from docs/ back up to the files, with spaces and parentheses encoded
+    let index = fs::read_to_string(temp.path().join("docs/SUMMARY.md")).expect("Failed to read index");
+    assert!(index.contains("- [main.rs](../src/main.rs): summary"), "{}", index);
+    assert!(index.contains("- [my file (1).rs](../src/my%20file%20%281%29.rs): summary"), "{}", index);
+}
+
+#[test]
+fn test_dirscribe_sidecar() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("src/lib.rs").write_str("pub fn lib() {}\n").unwrap();
+
+    let (port, server) = serve_responses(vec![r#"{"response":"Defines the lib function.","done":true}"#.to_string()]);
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--sidecar", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    // A plain summary is accepted right away, without asking for a comment block
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(!requests[0].contains("[DIRSCRIBE]"));
+
+    let sidecar = fs::read_to_string(temp.path().join("src/lib.rs.dirscribe.md")).expect("Sidecar file missing");
+    assert_eq!(sidecar, "Defines the lib function.\n");
+    assert_eq!(fs::read_to_string(temp.path().join("src/lib.rs")).unwrap(), "pub fn lib() {}\n");
+}
+
+#[test]
+fn test_dirscribe_apply_with_comment_prefix() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.zz").write_str("print(1)\n").unwrap();
+
+    let response = serde_json::json!({ "response": "--\n-- [DIRSCRIBE]\n-- Prints one.\n-- [/DIRSCRIBE]\n--", "done": true });
+    let (port, server) = serve_responses(vec![response.to_string()]);
+    let output = Command::new("dirscribe")
+        .args(["zz", "--summarize", "--apply", "--comment-style", "line", "--comment-prefix=--", "--output-path", "out.txt"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].contains("start every line of the summary with '--'"));
+
+    let content = fs::read_to_string(temp.path().join("main.zz")).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines[..3], ["--", "-- [DIRSCRIBE]", "-- Prints one."]);
+    assert!(lines[3].starts_with("-- ") && chrono::DateTime::parse_from_rfc3339(&lines[3][3..]).is_ok(), "Unexpected timestamp line: {}", lines[3]);
+    assert_eq!(lines[4..], ["-- [/DIRSCRIBE]", "--", "print(1)"]);
+}
+
+#[test]
+fn test_dirscribe_unknown_prompt_version() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--prompt-version", "summary-9.9"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "An unknown prompt version should be rejected");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Unknown prompt version 'summary-9.9'"), "{}", stderr);
+    assert!(stderr.contains("summary-0.2") && stderr.contains("summary-keywords-0.1"), "{}", stderr);
+}
+
+#[test]
+fn test_dirscribe_check_model_rejects_unknown_model() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let (port, server) = serve_responses(vec![r#"{"models":[{"name":"llama3.2:latest"}]}"#.to_string()]);
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--check-model", "--stdout"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_MODEL", "llama3.3")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "An unknown model should be rejected");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Model 'llama3.3' is not available"), "{}", stderr);
+    assert!(stderr.contains("llama3.2:latest"), "{}", stderr);
+
+    // Only the model list was requested, nothing was summarized
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].starts_with("GET /api/tags"), "{}", requests[0]);
+}
+
+#[test]
+fn test_dirscribe_overview() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() { lib::run(); }\n").unwrap();
+    temp.child("lib.rs").write_str("pub fn run() {}\n").unwrap();
+
+    let response = serde_json::json!({ "response": "A tiny program whose entry point calls into its library.", "done": true });
+    let (port, server) = serve_responses(vec![response.to_string()]);
+    let output = Command::new("dirscribe")
+        .args(["rs", "--overview", "--stdout"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].contains("lib::run()") && requests[0].contains("pub fn run()"), "{}", requests[0]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("Overview:").count(), 1, "{}", stdout);
+    assert!(stdout.starts_with("Overview:\n\nA tiny program whose entry point calls into its library.\n"), "{}", stdout);
+    assert!(stdout.contains("File Content of ./main.rs"), "{}", stdout);
+}
+
+#[test]
+fn test_dirscribe_overview_model() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let summary = serde_json::json!({ "response": "/*\n[DIRSCRIBE]\nThe entry point.\n[/DIRSCRIBE]\n*/", "done": true });
+    let overview = serde_json::json!({ "response": "A program with only an entry point.", "done": true });
+    let (port, server) = serve_responses(vec![summary.to_string(), overview.to_string()]);
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--overview", "--overview-model", "large-model", "--stdout"])
+        .env("DIRSCRIBE_PROVIDER", "ollama")
+        .env("DIRSCRIBE_MODEL", "small-model")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/api/generate", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    // The summary keeps DIRSCRIBE_MODEL, only the overview uses the overview model
+    let requests = server.join().unwrap();
+    let model = |request: &str| {
+        let body: serde_json::Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..])
+            .expect("Failed to parse request body");
+        body["model"].as_str().unwrap_or_default().to_string()
+    };
+    assert_eq!(model(&requests[0]), "small-model");
+    assert_eq!(model(&requests[1]), "large-model");
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Overview:\n\nA program with only an entry point.\n"));
+}
+
+#[test]
+fn test_dirscribe_overview_model_gemini() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    let response = |text: &str| serde_json::json!({ "candidates": [{ "content": { "parts": [{ "text": text }] } }] }).to_string();
+    let (port, server) = serve_responses(vec![
+        response("/*\n[DIRSCRIBE]\nThe entry point.\n[/DIRSCRIBE]\n*/"),
+        response("A program with only an entry point."),
+    ]);
+    let output = Command::new("dirscribe")
+        .args(["rs", "--summarize", "--overview", "--overview-model", "large-model", "--stdout"])
+        .env("DIRSCRIBE_PROVIDER", "gemini")
+        .env("PROVIDER_API_KEY", "test-key")
+        .env("DIRSCRIBE_MODEL", "small-model")
+        .env("DIRSCRIBE_BASE_URL", format!("http://127.0.0.1:{}/small-model/v1beta/models/small-model:generateContent", port))
+        .env("DIRSCRIBE_CACHE_DIR", temp.path().join("cache"))
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    // Gemini takes the model from the request path, not from the body; the rest of the path is kept
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /small-model/v1beta/models/small-model:generateContent "), "{}", requests[0]);
+    assert!(requests[1].starts_with("POST /small-model/v1beta/models/large-model:generateContent "), "{}", requests[1]);
+}
+
+#[test]
+fn test_dirscribe_output_dir() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("src/lib/util.rs").write_str("pub fn util() {}\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--output-dir", "docs"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    assert_eq!(fs::read_to_string(temp.path().join("docs/main.rs.txt")).unwrap(), "fn main() {}\n");
+    assert_eq!(fs::read_to_string(temp.path().join("docs/src/lib/util.rs.txt")).unwrap(), "pub fn util() {}\n");
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--output-dir", "docs", "--output-path", "out.txt"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "--output-dir with --output-path should be rejected");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--output-dir cannot be used with --output-path"));
+
+    let output = Command::new("dirscribe")
+        .args(["--summarize-stdin", "--output-dir", "docs"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "--output-dir with --summarize-stdin should be rejected");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--output-dir cannot be used with --summarize-stdin"));
+}
+
+#[test]
+fn test_dirscribe_clipboard_failure_falls_back_to_stdout() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    // No display server can be reached, so the clipboard is unavailable
+    let output = Command::new("dirscribe")
+        .arg("rs")
+        .env("DISPLAY", ":dirscribe-test")
+        .env_remove("WAYLAND_DISPLAY")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("writing the output to stdout instead"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("fn main() {}"));
+}
+
+#[test]
+fn test_dirscribe_global_and_info_excludes() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let home = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    git2::Repository::init(temp.path()).unwrap();
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("main.orig.rs").write_str("fn old_main() {}\n").unwrap();
+    temp.child("scratch.rs").write_str("fn scratch() {}\n").unwrap();
+    temp.child(".git/info/exclude").write_str("scratch.rs\n").unwrap();
+    home.child("global-excludes").write_str("*.orig.rs\n").unwrap();
+    home.child(".gitconfig")
+        .write_str(&format!("[core]\n\texcludesFile = {}\n", home.path().join("global-excludes").display()))
+        .unwrap();
+
+    let run = |extra_args: &[&str]| {
+        let output = Command::new("dirscribe")
+            .args(["rs", "--stdout"])
+            .args(extra_args)
+            .env("HOME", home.path())
+            .env_remove("XDG_CONFIG_HOME")
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let stdout = run(&[]);
+    assert!(stdout.contains("./main.rs\n"), "{}", stdout);
+    assert!(!stdout.contains("main.orig.rs") && !stdout.contains("scratch.rs"), "{}", stdout);
+
+    let stdout = run(&["--dont-use-gitignore"]);
+    assert!(stdout.contains("./main.orig.rs\n") && stdout.contains("./scratch.rs\n"), "{}", stdout);
+}
+
+#[test]
+fn test_dirscribe_quiet_keeps_stderr_empty() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    // Not valid UTF-8, so it is skipped with an error message
+    temp.child("latin1.rs").write_binary(b"// caf\xe9\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--quiet", "--allow-errors", "--output-path", "out.txt"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert!(fs::read_to_string(temp.path().join("out.txt")).unwrap().contains("fn main() {}"));
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--quiet", "--output-path", "out.txt", "--start-commit-id", "HEAD"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(!output.status.success(), "Fatal errors should still fail the run");
+
+    // The paths of the split outputs are the result, not a status message
+    let output = Command::new("dirscribe")
+        .args(["rs", "--quiet", "--allow-errors", "--output-path", "out.txt", "--split-size", "1000"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "out.1.txt\n");
+}
+
+#[test]
+fn test_dirscribe_verbose_reports_skipped_files() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() { todo!() }\n").unwrap();
+    temp.child("lib.rs").write_str("pub fn run() {}\n").unwrap();
+    temp.child("notes.md").write_str("notes\n").unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--verbose", "--stdout", "--exclude-keywords", "todo!"])
+        .env_remove("DIRSCRIBE_LOG")
+        .env_remove("RUST_LOG")
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let line_for = |file: &str| stderr.lines().find(|line| line.contains(file) && line.contains("Skipped file")).unwrap_or_default().to_string();
+    assert!(line_for("main.rs").contains("keyword filter: exclude keywords [todo!]"), "{}", stderr);
+    assert!(line_for("notes.md").contains("suffix mismatch"), "{}", stderr);
+    assert!(stderr.lines().any(|line| line.contains("lib.rs") && line.contains("Selected file")), "{}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dirscribe_failed_files_exit_code() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("latin1.rs").write_binary(b"// caf\xe9\n").unwrap();
+    // A dangling symlink can't be read, even by root
+    std::os::unix::fs::symlink(temp.path().join("missing.rs"), temp.path().join("broken.rs")).unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--output-path", "out.txt"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 files failed to process"), "{}", String::from_utf8_lossy(&output.stderr));
+    // The files that could be read are still written
+    assert!(fs::read_to_string(temp.path().join("out.txt")).unwrap().contains("fn main() {}"));
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--output-path", "out.txt", "--allow-errors"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "--allow-errors should exit with status 0");
+}
+
+#[test]
+fn test_dirscribe_failed_files_json_output() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    std::os::unix::fs::symlink(temp.path().join("missing.rs"), temp.path().join("broken.rs")).unwrap();
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--format", "json", "--output-path", "out.json"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 file failed to process"), "{}", String::from_utf8_lossy(&output.stderr));
+    // The JSON is still written, the unreadable file without content, size or hash
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(temp.path().join("out.json")).unwrap()).expect("Output is not valid JSON");
+    assert_eq!(json["paths"], serde_json::json!(["broken.rs", "main.rs"]));
+    assert!(json["files"][0].get("content").is_none());
+    assert!(json["files"][0].get("size").is_none());
+    assert!(json["files"][0].get("hash").is_none());
+    assert_eq!(json["files"][1]["content"], "fn main() {}\n");
+    assert_eq!(json["files"][1]["size"], 13);
+
+    let output = Command::new("dirscribe")
+        .args(["rs", "--format", "json", "--output-path", "out.json", "--allow-errors"])
+        .current_dir(temp.path())
+        .output()
+        .expect("Failed to run dirscribe");
+    assert!(output.status.success(), "--allow-errors should exit with status 0");
+}
+
+#[test]
+fn test_dirscribe_streamed_output_matches_stdout() {
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    for i in 0..500 {
+        temp.child(format!("src/module{}/file{}.rs", i % 10, i)).write_str(&format!("pub const N: usize = {};\n", i).repeat(20)).unwrap();
+    }
+    temp.child("template.txt").write_str("Files:\n${${FILE_LIST}$}$\n\n${${CONTENT}$}$\nEnd").unwrap();
+    temp.child("twice.txt").write_str("${${CONTENT}$}$\nAgain:\n${${CONTENT}$}$").unwrap();
+
+    for extra_args in [&[][..], &["--prompt-template-path", "template.txt"][..], &["--prompt-template-path", "twice.txt"][..]] {
+        let streamed = Command::new("dirscribe")
+            .args(["rs", "--output-path", "out.txt"])
+            .args(extra_args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(streamed.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&streamed.stderr));
+
+        let buffered = Command::new("dirscribe")
+            .args(["rs", "--stdout"])
+            .args(extra_args)
+            .current_dir(temp.path())
+            .output()
+            .expect("Failed to run dirscribe");
+        assert!(buffered.status.success(), "dirscribe command failed: {}", String::from_utf8_lossy(&buffered.stderr));
+
+        let output = fs::read(temp.path().join("out.txt")).unwrap();
+        assert!(output.len() > 500 * 400);
+        assert!(output == buffered.stdout, "Output written to the file differs from stdout with {:?}", extra_args);
+    }
+}
+
+// Peak resident memory of a running process, polled from /proc until it exits
+#[cfg(target_os = "linux")]
+fn peak_memory_kb(mut child: std::process::Child) -> (std::process::ExitStatus, u64) {
+    let status_path = format!("/proc/{}/status", child.id());
+    let mut peak = 0;
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return (status, peak);
+        }
+        let high_water_mark = fs::read_to_string(&status_path).ok().and_then(|status| status.lines()
+            .find_map(|line| line.strip_prefix("VmHWM:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok()));
+        peak = peak.max(high_water_mark.unwrap_or(0));
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_dirscribe_output_path_memory_is_bounded() {
+    const FILES: usize = 640;
+    const FILE_SIZE: usize = 100_000;
+
+    setup();
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let line = format!("// {}\n", "x".repeat(96));
+    for i in 0..FILES {
+        temp.child(format!("src/module{}/file{}.rs", i % 10, i)).write_str(&line.repeat(FILE_SIZE / line.len())).unwrap();
+    }
+
+    // Holding the contents and the formatted output at the same time would take twice the tree
+    let child = Command::new("dirscribe")
+        .args(["rs", "--output-path", "out.txt"])
+        .current_dir(temp.path())
+        .spawn()
+        .expect("Failed to run dirscribe");
+    let (status, peak_kb) = peak_memory_kb(child);
+    assert!(status.success(), "dirscribe command failed");
+
+    let tree_kb = (FILES * FILE_SIZE / 1024) as u64;
+    assert!(fs::metadata(temp.path().join("out.txt")).unwrap().len() >= (FILES * FILE_SIZE) as u64);
+    assert!(peak_kb < tree_kb * 3 / 2, "Peak memory of {} kB for {} kB of files", peak_kb, tree_kb);
+}
@@ -0,0 +1,669 @@
+use assert_fs::prelude::*;
+use dirscribe::{process_directory, ProcessOptions};
+use dirscribe::file_processing::{ContentOptions, SortOrder, SummaryPosition};
+use dirscribe::output::OutputFormat;
+use dirscribe::summary::{Provider, ProviderSpec, SummaryOptions};
+use std::collections::HashMap;
+
+// Names every field, so renaming or removing one breaks this test rather than downstream crates
+#[tokio::test]
+async fn test_process_options_fields() {
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("notes.md").write_str("# Notes\n").unwrap();
+
+    let options = ProcessOptions {
+        suffixes: vec!["rs".to_string()],
+        dont_use_gitignore: false,
+        follow_symlinks: false,
+        summarize: false,
+        summarize_keywords: false,
+        prompt_templates: HashMap::new(),
+        prompt_version: None,
+        apply: false,
+        sidecar: false,
+        comment_delimiters: None,
+        summary_position: SummaryPosition::Top,
+        retrieve: false,
+        diff_only: false,
+        exclude_paths: Vec::new(),
+        include_paths: Vec::new(),
+        or_keywords: Vec::new(),
+        and_keywords: Vec::new(),
+        exclude_keywords: Vec::new(),
+        regex_keywords: false,
+        ignore_case: false,
+        start_commit_id: None,
+        end_commit_id: None,
+        staged: false,
+        include_untracked: false,
+        ignore_whitespace: false,
+        detect_duplicates: false,
+        whitelist: None,
+        diff_line_ranges: false,
+        diffstat: false,
+        diff_with_content: false,
+        summarize_binary: false,
+        summary_options: SummaryOptions::default(),
+        section_order: vec!["paths".to_string(), "contents".to_string()],
+        sort: SortOrder::Path,
+        tree: false,
+        case_insensitive_suffixes: false,
+        exclude_dirs: Vec::new(),
+        exclude_extensions: Vec::new(),
+        preprocess_cmd: None,
+        format: OutputFormat::Text,
+        keyword_report: false,
+        keyword_stats: false,
+        include_commit_messages: false,
+        list_commits: false,
+        content_options: ContentOptions::default(),
+        index_path: None,
+        classify: false,
+        show_metadata: false,
+        content_only: false,
+        line_numbers: false,
+        max_output_bytes: None,
+        max_output_tokens: None,
+        compare_providers: Vec::new(),
+        skip_minified: false,
+        hierarchical_summary: false,
+        output_dir: None,
+        overview: false,
+        overview_model: None,
+        quiet: false,
+        dry_run: false,
+        files_from: None,
+    };
+
+    let output = process_directory(temp.path().to_str().unwrap(), &options).await
+        .expect("Failed to process directory")
+        .concat();
+    assert!(output.contains("fn main() {}"));
+    assert!(!output.contains("# Notes"));
+}
+
+#[test]
+fn test_filter_diff_for_file_matches_full_path() {
+    use dirscribe::git::filter_diff_for_file;
+    use std::path::Path;
+
+    let diff = "diff --git a/src/old_config.rs b/src/old_config.rs\n\
+                --- a/src/old_config.rs\n\
+                +++ b/src/old_config.rs\n\
+                @@ -1 +1 @@\n\
+                -old\n\
+                +older\n\
+                diff --git a/src/config.rs b/src/config.rs\n\
+                --- a/src/config.rs\n\
+                +++ b/src/config.rs\n\
+                @@ -1 +1 @@\n\
+                -new\n\
+                +newer\n";
+
+    let config = filter_diff_for_file(diff, Path::new("./src/config.rs"), Path::new("src/config.rs"));
+    assert!(config.starts_with("diff --git a/src/config.rs b/src/config.rs"));
+    assert!(config.contains("+newer"));
+    assert!(!config.contains("old_config.rs"));
+
+    let old_config = filter_diff_for_file(diff, Path::new("./src/old_config.rs"), Path::new("src/old_config.rs"));
+    assert!(old_config.starts_with("diff --git a/src/old_config.rs b/src/old_config.rs"));
+    assert!(old_config.contains("+older"));
+    assert!(!old_config.contains("src/config.rs"));
+}
+
+#[test]
+fn test_describe_changed_lines_reports_deleted_files() {
+    use dirscribe::git::describe_changed_lines;
+
+    let changed = "diff --git a/src/lib.rs b/src/lib.rs\n\
+                   --- a/src/lib.rs\n\
+                   +++ b/src/lib.rs\n\
+                   @@ -3,2 +3,3 @@\n\
+                   @@ -20,1 +21,0 @@\n";
+    assert_eq!(describe_changed_lines(changed), "Changed lines: 3-5, 21");
+
+    // The new side of a deleted file is empty, there is no line 0 to report
+    let deleted = "diff --git a/src/gone.rs b/src/gone.rs\n\
+                   deleted file mode 100644\n\
+                   --- a/src/gone.rs\n\
+                   +++ /dev/null\n\
+                   @@ -1,2 +0,0 @@\n\
+                   -fn gone() {}\n\
+                   -\n";
+    assert_eq!(describe_changed_lines(deleted), "Changed lines: deleted");
+}
+
+#[test]
+fn test_write_summary_to_file_rejects_single_line_summary() {
+    use dirscribe::file_processing::{create_comment_map, write_summary_to_file};
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let file = temp.child("main.rs");
+    file.write_str("fn main() {}\n").unwrap();
+
+    let result = write_summary_to_file(file.path(), "// a one-line summary", create_comment_map(), SummaryPosition::Top);
+    assert!(result.unwrap_err().to_string().contains("too short"));
+    file.assert("fn main() {}\n");
+}
+
+#[test]
+fn test_process_with_template_placeholders() {
+    use dirscribe::output::{process_with_template, TemplateValues};
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let values = TemplateValues {
+        file_list: "src/main.rs\nsrc/lib.rs".to_string(),
+        date: "2025-01-31".to_string(),
+        cwd: "/home/user/project".to_string(),
+    };
+
+    let template = temp.child("all.txt");
+    template.write_str("In ${${CWD}$}$ on ${${DATE}$}$:\n${${FILE_LIST}$}$\n---\n${${CONTENT}$}$").unwrap();
+    let result = process_with_template("the content", template.path().to_str().unwrap(), &values).unwrap();
+    assert_eq!(result, "In /home/user/project on 2025-01-31:\nsrc/main.rs\nsrc/lib.rs\n---\nthe content");
+
+    let template = temp.child("content.txt");
+    template.write_str("Review this:\n${${CONTENT}$}$").unwrap();
+    let result = process_with_template("the content", template.path().to_str().unwrap(), &values).unwrap();
+    assert_eq!(result, "Review this:\nthe content");
+}
+
+// Bytes read by this process so far, from the rchar counter in /proc/self/io
+#[cfg(target_os = "linux")]
+fn bytes_read() -> u64 {
+    std::fs::read_to_string("/proc/self/io").unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("rchar: "))
+        .and_then(|value| value.parse().ok())
+        .unwrap()
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_process_directory_reads_each_file_once() {
+    const FILES: usize = 20;
+    const FILE_SIZE: usize = 100_000;
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let line = format!("// TODO {}\n", "x".repeat(90));
+    for i in 0..FILES {
+        temp.child(format!("file{}.rs", i)).write_str(&line.repeat(FILE_SIZE / line.len())).unwrap();
+    }
+
+    // Keyword filtering reads the files too, so it must share the read with the content step
+    let options = ProcessOptions {
+        suffixes: vec!["rs".to_string()],
+        or_keywords: vec!["TODO".to_string()],
+        ..Default::default()
+    };
+    let before = bytes_read();
+    let output = process_directory(temp.path().to_str().unwrap(), &options).await
+        .expect("Failed to process directory")
+        .concat();
+    let read = bytes_read() - before;
+
+    assert!(output.len() >= FILES * FILE_SIZE * 9 / 10);
+    assert!(read < (FILES * FILE_SIZE * 3 / 2) as u64, "Read {} bytes for {} bytes of files", read, FILES * FILE_SIZE);
+}
+
+#[tokio::test]
+async fn test_get_summaries_assembles_streamed_response() {
+    use dirscribe::file_processing::create_comment_map;
+    use dirscribe::summary::{get_summaries, SummaryOutcome};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("No request received");
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while !String::from_utf8_lossy(&request).contains("\"stream\":true") {
+            let n = stream.read(&mut buffer).expect("Failed to read request");
+            assert!(n > 0, "Request was not streamed");
+            request.extend_from_slice(&buffer[..n]);
+        }
+        // Ollama sends one JSON object per line, without a content length
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n").unwrap();
+        for token in ["/*\n", "[DIRSCRIBE]\n", "streamed ", "summary\n", "[/DIRSCRIBE]\n", "*/"] {
+            let chunk = serde_json::json!({ "response": token, "done": false });
+            stream.write_all(format!("{}\n", chunk).as_bytes()).unwrap();
+            stream.flush().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        stream.write_all(b"{\"response\":\"\",\"done\":true}\n").unwrap();
+    });
+
+    // The endpoint is given with the provider, the environment is shared with the other tests
+    let options = SummaryOptions {
+        provider_spec: Some(ProviderSpec {
+            provider: Provider::Ollama,
+            model: None,
+            base_url: Some(format!("http://127.0.0.1:{}/api/generate", port)),
+        }),
+        stream: true,
+        no_cache: true,
+        ..Default::default()
+    };
+    let summaries = get_summaries(
+        vec!["main.rs".to_string()],
+        HashMap::from([("main.rs".to_string(), "fn main() {}".to_string())]),
+        "Summarize ${${CONTENT}$}$".to_string(),
+        create_comment_map(),
+        false,
+        &options,
+    ).await.expect("Failed to summarize");
+    server.join().unwrap();
+
+    assert_eq!(summaries, vec![SummaryOutcome::Ok("/*\n[DIRSCRIBE]\nstreamed summary\n[/DIRSCRIBE]\n*/".to_string())]);
+}
+
+#[tokio::test]
+async fn test_compare_summaries_labels_each_provider() {
+    use dirscribe::file_processing::create_comment_map;
+    use dirscribe::summary::{compare_summaries, render_comparison};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Answers each request with the model it asked for, and records how many were in flight at once
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let port = listener.local_addr().unwrap().port();
+    let (in_flight, max_in_flight) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let counters = (in_flight.clone(), max_in_flight.clone());
+    let server = std::thread::spawn(move || {
+        let handlers: Vec<_> = listener.incoming().take(2).map(|stream| {
+            let mut stream = stream.expect("No request received");
+            let (in_flight, max_in_flight) = (counters.0.clone(), counters.1.clone());
+            std::thread::spawn(move || {
+                max_in_flight.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                while !String::from_utf8_lossy(&request).ends_with('}') {
+                    let n = stream.read(&mut buffer).expect("Failed to read request");
+                    assert!(n > 0, "Request ended early");
+                    request.extend_from_slice(&buffer[..n]);
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                let body: serde_json::Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                let summary = format!("/*\n[DIRSCRIBE]\nsummary by {}\n[/DIRSCRIBE]\n*/", body["model"].as_str().unwrap());
+                let response = serde_json::json!({ "response": summary, "done": true }).to_string();
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                stream.write_all(format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", response.len(), response).as_bytes()).unwrap();
+            })
+        }).collect();
+        for handler in handlers {
+            handler.join().unwrap();
+        }
+    });
+
+    let base_url = Some(format!("http://127.0.0.1:{}/api/generate", port));
+    let providers: Vec<ProviderSpec> = ["first", "second"].iter()
+        .map(|model| ProviderSpec { provider: Provider::Ollama, model: Some(model.to_string()), base_url: base_url.clone() })
+        .collect();
+    // One request at a time across both providers
+    let options = SummaryOptions {
+        concurrency: Some(1),
+        no_cache: true,
+        ..Default::default()
+    };
+    let outcomes = compare_summaries(
+        vec!["main.rs".to_string()],
+        HashMap::from([("main.rs".to_string(), "fn main() {}".to_string())]),
+        "Summarize ${${CONTENT}$}$".to_string(),
+        create_comment_map(),
+        false,
+        &options,
+        &providers,
+    ).await.expect("Failed to summarize");
+    server.join().unwrap();
+
+    assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        render_comparison(&providers, &outcomes[0]),
+        "[ollama:first]\n/*\n[DIRSCRIBE]\nsummary by first\n[/DIRSCRIBE]\n*/\n\n[ollama:second]\n/*\n[DIRSCRIBE]\nsummary by second\n[/DIRSCRIBE]\n*/"
+    );
+}
+
+#[tokio::test]
+async fn test_streamed_response_reports_usage() {
+    use dirscribe::file_processing::create_comment_map;
+    use dirscribe::summary::{Message, TokenUsage, UnifiedClient};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("No request received");
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while !String::from_utf8_lossy(&request).contains("\"stream\":true") {
+            let n = stream.read(&mut buffer).expect("Failed to read request");
+            assert!(n > 0, "Request was not streamed");
+            request.extend_from_slice(&buffer[..n]);
+        }
+        // Only the final chunk carries the token counts
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n").unwrap();
+        stream.write_all(b"{\"response\":\"summary\",\"done\":false}\n").unwrap();
+        stream.write_all(b"{\"response\":\"\",\"done\":true,\"prompt_eval_count\":12,\"eval_count\":5}\n").unwrap();
+    });
+
+    let client = UnifiedClient::from_spec(&ProviderSpec {
+        provider: Provider::Ollama,
+        model: None,
+        base_url: Some(format!("http://127.0.0.1:{}/api/generate", port)),
+    }).expect("Failed to create client").with_streaming(true);
+    let messages = vec![Message { role: "user".to_string(), content: "Summarize".to_string(), image: None }];
+    let response = client.chat(&create_comment_map(), true, "main.rs", &messages, None, None).await
+        .expect("Failed to summarize");
+    server.join().unwrap();
+
+    assert_eq!(response.content, "summary");
+    assert_eq!(response.usage, Some(TokenUsage { input_tokens: 12, output_tokens: 5, files: 0 }));
+    assert_eq!(client.usage(), TokenUsage { input_tokens: 12, output_tokens: 5, files: 1 });
+}
+
+#[tokio::test]
+async fn test_summarize_without_prompt_templates() {
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+
+    // The templates are left empty, as in the default options
+    let options = ProcessOptions {
+        suffixes: vec!["rs".to_string()],
+        summarize: true,
+        ..Default::default()
+    };
+    let error = process_directory(temp.path().to_str().unwrap(), &options).await.unwrap_err();
+    assert_eq!(error.to_string(), "Prompt template 'summary-0.2' not found");
+}
+
+#[tokio::test]
+async fn test_get_directory_summaries_stop_at_processed_directory() {
+    use dirscribe::summary::{get_directory_summaries, SummaryOutcome};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+
+    // One response per directory, a request for any directory above the processed one finds no server
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        for summary in ["The source directory.", "The project."] {
+            let (mut stream, _) = listener.accept().expect("No request received");
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).ends_with('}') {
+                let n = stream.read(&mut buffer).expect("Failed to read request");
+                assert!(n > 0, "Request ended early");
+                request.extend_from_slice(&buffer[..n]);
+            }
+            let body = serde_json::json!({ "response": summary, "done": true }).to_string();
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            stream.write_all(response.as_bytes()).unwrap();
+            requests.push(String::from_utf8_lossy(&request).into_owned());
+        }
+        requests
+    });
+
+    let options = SummaryOptions {
+        provider_spec: Some(ProviderSpec {
+            provider: Provider::Ollama,
+            model: None,
+            base_url: Some(format!("http://127.0.0.1:{}/api/generate", port)),
+        }),
+        no_cache: true,
+        ..Default::default()
+    };
+    let summaries = get_directory_summaries(
+        Path::new("/home/user/project"),
+        &["/home/user/project/src/lib.rs".to_string(), "/home/user/project/src/broken.rs".to_string(), "/home/user/project/main.rs".to_string()],
+        &[
+            SummaryOutcome::Ok("The library.".to_string()),
+            SummaryOutcome::Failed("request timed out".to_string()),
+            SummaryOutcome::Ok("The entry point.".to_string()),
+        ],
+        "Summarize ${${DIRECTORY}$}$: ${${CONTENT}$}$".to_string(),
+        &options,
+    ).await.expect("Failed to summarize directories");
+    let requests = server.join().unwrap();
+
+    assert_eq!(summaries, vec![
+        ("/home/user/project".to_string(), SummaryOutcome::Ok("The project.".to_string())),
+        ("/home/user/project/src".to_string(), SummaryOutcome::Ok("The source directory.".to_string())),
+    ]);
+    // Files without a summary are left out of their directory's summary
+    assert!(requests[0].contains("The library.") && !requests[0].contains("broken.rs") && !requests[0].contains("timed out"), "{}", requests[0]);
+    assert!(requests[1].contains("The source directory.") && requests[1].contains("The entry point."), "{}", requests[1]);
+}
+
+#[test]
+fn test_detect_refusal_only_at_the_start() {
+    use dirscribe::summary::{detect_refusal, DEFAULT_REFUSAL_PATTERNS};
+
+    let patterns: Vec<String> = DEFAULT_REFUSAL_PATTERNS.iter().map(|p| p.to_string()).collect();
+    let refusal = "/*\n[DIRSCRIBE]\nI'm sorry, but I can't summarize this file.\n[/DIRSCRIBE]\n*/";
+    assert_eq!(detect_refusal(refusal, &patterns), Some("I'm sorry, but I can't"));
+    assert_eq!(detect_refusal("# i am unable to do that", &patterns), Some("I am unable to"));
+
+    // Quoting a phrase of the file is part of a legitimate summary
+    let summary = "/*\n[DIRSCRIBE]\nConnects to the database and logs \"I'm unable to connect\" on failure.\n[/DIRSCRIBE]\n*/";
+    assert_eq!(detect_refusal(summary, &patterns), None);
+    assert_eq!(detect_refusal("", &patterns), None);
+}
+
+#[test]
+fn test_check_for_keywords_with_regex() {
+    use dirscribe::file_processing::{check_for_keywords, Keyword};
+
+    let contents = "fn parse_config() {}\n// TODO(alice): handle errors\n";
+    let keywords = |patterns: &[&str]| -> Vec<Keyword> {
+        patterns.iter().map(|pattern| Keyword::new(pattern, true, false).unwrap()).collect()
+    };
+
+    let matched = check_for_keywords(contents, &keywords(&[r"fn \w+_test\(", r"TODO\(\w+\)"]), &[], &[]);
+    assert!(matched.included);
+    assert_eq!(matched.matched_or, vec![r"TODO\(\w+\)".to_string()]);
+
+    let matched = check_for_keywords(contents, &[], &keywords(&[r"fn parse_\w+", r"TODO\(bob\)"]), &[]);
+    assert!(!matched.included);
+    assert_eq!(matched.missing_and, vec![r"TODO\(bob\)".to_string()]);
+
+    let matched = check_for_keywords(contents, &[], &[], &keywords(&[r"^// TODO"]));
+    assert!(matched.included, "^ anchors at the start of the contents, not of a line");
+    let matched = check_for_keywords(contents, &[], &[], &keywords(&[r"(?m)^// TODO"]));
+    assert!(!matched.included);
+
+    // Without regex mode the same pattern is literal text
+    let literal = [Keyword::new(r"TODO\(\w+\)", false, false).unwrap()];
+    assert!(!check_for_keywords(contents, &literal, &[], &[]).included);
+
+    // Case-insensitive literal keywords are reported as given, not as their escaped pattern
+    let ignore_case = [Keyword::new("Parse_Config()", false, true).unwrap()];
+    let matched = check_for_keywords(contents, &ignore_case, &[], &[]);
+    assert!(matched.included);
+    assert_eq!(matched.matched_or, vec!["Parse_Config()".to_string()]);
+}
+
+#[tokio::test]
+async fn test_wildcard_with_excluded_extensions() {
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("main.rs").write_str("fn main() {}\n").unwrap();
+    temp.child("script.py").write_str("print(1)\n").unwrap();
+    temp.child("logo.svg").write_str("<svg></svg>\n").unwrap();
+    temp.child("Cargo.lock").write_str("version = 3\n").unwrap();
+
+    let options = ProcessOptions {
+        suffixes: vec!["*".to_string()],
+        exclude_extensions: vec!["py".to_string(), "svg".to_string(), "lock".to_string()],
+        ..Default::default()
+    };
+    let processed = dirscribe::process_directory_with_files(temp.path().to_str().unwrap(), &options).await.unwrap();
+    let names: Vec<String> = processed.files.iter()
+        .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(names, ["main.rs"]);
+}
+
+#[tokio::test]
+async fn test_process_directory_many_files_in_order() {
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    for i in 0..300 {
+        temp.child(format!("dir{}/file{:03}.rs", i % 7, i)).write_str(&format!("const N: usize = {};\n", i)).unwrap();
+    }
+
+    let options = ProcessOptions {
+        suffixes: vec!["rs".to_string()],
+        ..Default::default()
+    };
+    let processed = dirscribe::process_directory_with_files(temp.path().to_str().unwrap(), &options).await.unwrap();
+    assert_eq!(processed.files.len(), 300);
+    assert!(processed.failed_files.is_empty());
+
+    // Every file appears once, right after its own heading and in the sorted order of the paths
+    let output = processed.blocks.concat();
+    let mut last_position = 0;
+    for file in &processed.files {
+        let number: usize = file.file_stem().unwrap().to_str().unwrap()[4..].parse().unwrap();
+        let expected = format!("File Content of {}:\n\nconst N: usize = {};\n", file.display(), number);
+        let position = output.find(&expected).unwrap_or_else(|| panic!("Missing content of {}", file.display()));
+        assert!(position >= last_position, "{} is out of order", file.display());
+        last_position = position;
+    }
+}
+
+#[test]
+fn test_drop_largest_blocks_counts_bytes() {
+    use dirscribe::output::drop_largest_blocks;
+
+    // Three bytes per character, so the first block is larger than its character count suggests
+    let blocks = vec!["\u{2192}".repeat(100), "a".repeat(100), "b".repeat(50)];
+    let kept = drop_largest_blocks(&blocks, 200);
+    assert_eq!(kept, vec!["a".repeat(100), "b".repeat(50), "\n[truncated: 1 files omitted]\n".to_string()]);
+    assert!(kept.concat().len() <= 200);
+}
+
+#[test]
+fn test_split_into_chunks_fits_the_template() {
+    use dirscribe::output::{chunk_budget, parse_size, split_into_chunks, TemplateValues};
+
+    assert_eq!(parse_size("100").unwrap(), 100);
+    assert_eq!(parse_size("25t").unwrap(), 100);
+    assert!(parse_size("0").is_err());
+    assert!(parse_size(&format!("{}t", usize::MAX)).is_err());
+
+    let blocks = vec!["a".repeat(40), "b".repeat(40), "c".repeat(40), "d".repeat(150)];
+    assert_eq!(split_into_chunks(&blocks, 100), vec!["a".repeat(40) + &"b".repeat(40), "c".repeat(40), "d".repeat(150)]);
+
+    // The template text takes 20 bytes of each chunk, and the content is embedded twice
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    temp.child("template.txt").write_str("Files:\n${${CONTENT}$}$\nAgain:\n${${CONTENT}$}$\nEnd\n").unwrap();
+    let template_path = temp.path().join("template.txt");
+    let budget = chunk_budget(100, template_path.to_str(), &TemplateValues::default()).unwrap();
+    assert_eq!(budget, 40);
+    assert_eq!(chunk_budget(100, None, &TemplateValues::default()).unwrap(), 100);
+}
+
+#[test]
+fn test_minify_data_keeps_key_order() {
+    use dirscribe::file_processing::minify_data;
+    use std::path::Path;
+
+    let json = "{\n  \"zeta\": 1,\n  \"alpha\": {\"b\": \"x y\", \"a\": [1, 2]}\n}\n";
+    assert_eq!(minify_data(Path::new("package.json"), json).unwrap(), r#"{"zeta":1,"alpha":{"b":"x y","a":[1,2]}}"#);
+
+    let yaml = "zeta: 1\nalpha:\n  b: x y\n  a: [1, 2]\n";
+    assert_eq!(minify_data(Path::new("config.yaml"), yaml).unwrap(), r#"{"zeta":1,"alpha":{"b":"x y","a":[1,2]}}"#);
+
+    // Files that don't parse are left as they are
+    assert!(minify_data(Path::new("broken.json"), "{\"zeta\":").is_none());
+}
+
+#[test]
+fn test_check_summary_accepts_comment_variants() {
+    use dirscribe::file_processing::create_comment_map;
+    use dirscribe::summary::check_summary;
+    use std::path::Path;
+
+    let comment_map = create_comment_map();
+    let check = |file: &str, summary: &str| check_summary(Path::new(file), summary, &comment_map);
+
+    assert!(check("main.rs", "/**\n[DIRSCRIBE]\nEntry point\n[/DIRSCRIBE]\n*/"));
+    assert!(check("main.rs", "/* \n[DIRSCRIBE]\nEntry point\n[/DIRSCRIBE]\n*/ \n"));
+    assert!(check("main.rs", "/**\n[DIRSCRIBE]\nEntry point\n[/DIRSCRIBE]\n**/"));
+    assert!(check("main.py", "'''\n[DIRSCRIBE]\nEntry point\n[/DIRSCRIBE]\n\"\"\""));
+
+    // The markers stay strict
+    assert!(!check("main.rs", "/**\n[DIRSCRIBE] Entry point\n[/DIRSCRIBE]\n*/\n"));
+    assert!(!check("main.rs", "/**\nEntry point\nmore\n[/DIRSCRIBE]\n*/"));
+}
+
+#[test]
+fn test_write_summary_to_file_positions() {
+    use dirscribe::file_processing::{create_comment_map, write_summary_to_file};
+
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let file = temp.child("main.rs");
+    file.write_str("// Licensed under MIT\nfn main() {}\n").unwrap();
+    let summary = |text: &str| format!("/*\n[DIRSCRIBE]\n{}\n[/DIRSCRIBE]\n*/", text);
+    let read = || std::fs::read_to_string(file.path()).unwrap();
+
+    write_summary_to_file(file.path(), &summary("First"), create_comment_map(), SummaryPosition::Bottom).unwrap();
+    let content = read();
+    assert!(content.starts_with("// Licensed under MIT\nfn main() {}\n\n/*\n[DIRSCRIBE]\nFirst\n"), "{}", content);
+    assert!(content.ends_with("[/DIRSCRIBE]\n*/\n"), "{}", content);
+
+    // A summary at the bottom is replaced, at the bottom or at the top
+    write_summary_to_file(file.path(), &summary("Second"), create_comment_map(), SummaryPosition::Bottom).unwrap();
+    let content = read();
+    assert!(content.contains("Second") && !content.contains("First"), "{}", content);
+    assert_eq!(content.matches("[DIRSCRIBE]").count(), 1, "{}", content);
+
+    write_summary_to_file(file.path(), &summary("Third"), create_comment_map(), SummaryPosition::Top).unwrap();
+    let content = read();
+    assert!(content.starts_with("/*\n[DIRSCRIBE]\nThird\n"), "{}", content);
+    assert!(content.contains("*/\n// Licensed under MIT\nfn main() {}") && !content.contains("Second"), "{}", content);
+
+    // And so is one at the top
+    write_summary_to_file(file.path(), &summary("Fourth"), create_comment_map(), SummaryPosition::Bottom).unwrap();
+    let content = read();
+    assert!(content.starts_with("// Licensed under MIT\nfn main() {}\n\n/*\n[DIRSCRIBE]\nFourth\n"), "{}", content);
+    assert_eq!(content.matches("[DIRSCRIBE]").count(), 1, "{}", content);
+}
+
+#[test]
+fn test_filter_dirscribe_sections_after_shebang_and_blank_lines() {
+    use dirscribe::file_processing::{create_comment_map, filter_dirscribe_sections, write_summary_to_file};
+
+    let block = "#\n# [DIRSCRIBE]\n# Prints a greeting\n# [/DIRSCRIBE]\n#";
+    let content = format!("\n\n{}\nprint('hello')\n", block);
+    assert_eq!(filter_dirscribe_sections(&content, true), "\n\nprint('hello')");
+    assert_eq!(filter_dirscribe_sections(&content, false), block);
+
+    // Reapplying a summary replaces the old one and keeps the shebang first
+    let temp = assert_fs::TempDir::new().expect("Failed to create temp directory");
+    let file = temp.child("hello.py");
+    file.write_str("#!/usr/bin/env python3\n\nprint('hello')\n").unwrap();
+    for text in ["First", "Second"] {
+        let summary = format!("#\n# [DIRSCRIBE]\n# {}\n# [/DIRSCRIBE]\n#", text);
+        write_summary_to_file(file.path(), &summary, create_comment_map(), SummaryPosition::Top).unwrap();
+    }
+    let content = std::fs::read_to_string(file.path()).unwrap();
+    assert!(content.starts_with("#!/usr/bin/env python3\n#\n# [DIRSCRIBE]\n# Second\n"), "{}", content);
+    assert!(!content.contains("First") && content.ends_with("print('hello')"), "{}", content);
+}
+
+#[test]
+fn test_invalid_provider_lists_every_provider() {
+    use std::str::FromStr;
+
+    let error = Provider::from_str("openai").unwrap_err().to_string();
+    assert_eq!(error, "Invalid provider: openai. Valid options are: deepseek, anthropic, ollama, gemini");
+    assert!(matches!(Provider::from_str("Gemini"), Ok(Provider::Gemini)));
+}